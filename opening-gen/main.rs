@@ -1,112 +1,251 @@
-use antichess_engine::engine::Engine;
-
-use anyhow::Result;
-
-use pleco::{BitMove, Board};
-
-use std::collections::HashMap;
-
+use antichess_engine::book_gen::{search_position, BookGenOptions, GenRecord};
+use antichess_engine::engine::{opening::OpeningBook, polyglot::save_book, Engine};
+
+use anyhow::{anyhow, Result};
+use clap::{App, Arg};
+use pleco::Board;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 use std::time::Duration;
 
-use std::fs;
-
-/// will create an opening book
+/// Builds an opening book by searching every position the book can reach,
+/// one ply at a time. Every searched position is written to a checkpoint file
+/// straight away, so an interrupted run picks up where it left off.
 fn main() -> Result<()> {
-    let mut opening_book: HashMap<String, BitMove> = HashMap::new();
-
-    generate_opening_book(&mut opening_book);
-
-    write_opening_book_to_file(&opening_book, "opening.rs")?;
+    let matches = App::new("opening-gen")
+        .about("Generates an opening book by searching every book position")
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("where to write the book, .bin for a Polyglot book")
+                .takes_value(true)
+                .default_value("opening.book"),
+        )
+        .arg(
+            Arg::with_name("depth")
+                .long("depth")
+                .value_name("PLIES")
+                .help("how many plies deep the book goes")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("search-depth")
+                .long("search-depth")
+                .value_name("DEPTH")
+                .help("depth to search every position to")
+                .takes_value(true)
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("movetime")
+                .long("movetime")
+                .value_name("MS")
+                .help("most time to spend on a single position, in milliseconds")
+                .takes_value(true)
+                .default_value("60000"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .help("number of positions to search at the same time")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("replies")
+                .long("replies")
+                .value_name("N")
+                .help("only follow the N most plausible moves out of every position, 0 follows them all")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("reply-nodes")
+                .long("reply-nodes")
+                .value_name("N")
+                .help("nodes to search every move for when picking the plausible ones")
+                .takes_value(true)
+                .default_value("2000"),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .help("searched positions are saved here and reused when the same run is started again")
+                .takes_value(true)
+                .default_value("opening-gen.checkpoint"),
+        )
+        .get_matches();
+
+    let options = BookGenOptions {
+        depth: matches.value_of("depth").unwrap().parse()?,
+        search_depth: matches.value_of("search-depth").unwrap().parse()?,
+        movetime: Duration::from_millis(matches.value_of("movetime").unwrap().parse()?),
+        replies: matches.value_of("replies").unwrap().parse()?,
+        reply_nodes: matches.value_of("reply-nodes").unwrap().parse()?,
+    };
+    let threads: usize = matches
+        .value_of("threads")
+        .unwrap()
+        .parse::<usize>()?
+        .max(1);
+    let checkpoint = matches.value_of("checkpoint").unwrap();
+
+    let mut records = load_checkpoint(checkpoint, &options)?;
+    if !records.is_empty() {
+        println!(
+            "resuming with {} positions from {}",
+            records.len(),
+            checkpoint
+        );
+    }
 
-    Ok(())
-}
+    let mut out = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(checkpoint)?;
+
+    // don't glue the next record onto a line that got cut off
+    let written = fs::read(checkpoint)?;
+    if written.is_empty() {
+        writeln!(out, "{}", options.checkpoint_header())?;
+    } else if written.last().is_some_and(|b| *b != b'\n') {
+        writeln!(out)?;
+    }
 
-fn generate_opening_book(store_main: &mut HashMap<String, BitMove>) {
-    fn gen_rec(
-        engine: &mut Engine,
-        store: &mut HashMap<String, BitMove>,
-        board: &mut Board,
-        depth: u8,
-    ) {
-        if depth == 0 {
-            return;
+    let mut level = vec![Board::start_pos()];
+    let mut seen = HashSet::from([Board::start_pos().zobrist()]);
+
+    for ply in 0..options.depth {
+        let todo: Vec<String> = level
+            .iter()
+            .filter(|board| !records.contains_key(&board.zobrist()))
+            .map(|board| board.fen())
+            .collect();
+
+        println!(
+            "ply {}: {} positions, {} to search",
+            ply,
+            level.len(),
+            todo.len()
+        );
+
+        for record in search_all(todo, ply, &options, threads) {
+            writeln!(out, "{}", record.to_line())?;
+            out.flush()?;
+            records.insert(record.board().zobrist(), record);
         }
 
-        let best_move_option = engine.generate_move(board);
-
-        if let Ok(best_move) = best_move_option {
-            store.insert(board.fen(), best_move);
-        } else {
-            return;
-        }
-
-        let moves = board.generate_moves();
-
-        for m in moves {
-            board.apply_move(m);
-
-            gen_rec(engine, store, board, depth - 1);
-
-            board.undo_move();
+        let mut next = Vec::new();
+        for board in &level {
+            let record = match records.get(&board.zobrist()) {
+                Some(record) => record,
+                None => continue,
+            };
+
+            for m in &record.follow {
+                let mut child = board.clone();
+                child.apply_move(*m);
+
+                if !child.checkmate() && !child.stalemate() && seen.insert(child.zobrist()) {
+                    next.push(child);
+                }
+            }
         }
+        level = next;
     }
 
-    let recursion_depth = 2;
-    let reasonable_search_depth = 8;
-    let timeout = 60; // in seconds
-
-    let mut game = Board::start_pos();
-    let mut engine = Engine::new();
+    let mut book = OpeningBook::empty();
+    for record in records.values().filter(|r| r.ply < options.depth) {
+        book.insert(&record.board(), record.best_move, 1);
+    }
 
-    engine.params.depth = reasonable_search_depth;
-    engine.params.max_time = Duration::from_secs(timeout);
+    let output = matches.value_of("output").unwrap();
+    save_book(&book, output)?;
+    println!("wrote {} positions to {}", book.len(), output);
 
-    gen_rec(&mut engine, store_main, &mut game, recursion_depth);
+    Ok(())
 }
 
-fn write_opening_book_to_file(
-    opening_book: &HashMap<String, BitMove>,
-    file_name: &str,
-) -> Result<()> {
-    let mut content = String::new();
-
-    let header = "use pleco::BitMove;
-use std::collections::HashMap; 
-
-pub struct OpeningBook { 
-    openings: HashMap<String, BitMove>, 
-} 
-impl OpeningBook { 
-    pub fn new() -> OpeningBook { 
-        let openings: HashMap<String, BitMove> = HashMap::from([
-";
-
-    let footer = "
-        ]);
-        OpeningBook { openings, } 
-    } 
-    pub fn get_move(&self, fen: &str) -> Option<BitMove> { 
-        self.openings.get(fen).cloned() 
-    } 
-}
-impl Default for OpeningBook {
-    fn default() -> Self {
-        Self::new()
+/// Searches the positions on `threads` threads, each with its own engine,
+/// handing the records back as they finish
+fn search_all(
+    todo: Vec<String>,
+    ply: u32,
+    options: &BookGenOptions,
+    threads: usize,
+) -> mpsc::Receiver<GenRecord> {
+    let (sender, receiver) = mpsc::channel();
+
+    for worker in 0..threads {
+        let sender = sender.clone();
+        let options = options.clone();
+        let fens: Vec<String> = todo.iter().skip(worker).step_by(threads).cloned().collect();
+
+        thread::spawn(move || {
+            let mut engine = Engine::new();
+            engine.params.debug_print = -1;
+
+            for fen in fens {
+                let board = Board::from_fen(&fen).unwrap();
+
+                match search_position(&mut engine, &board, ply, &options) {
+                    Ok(record) => {
+                        if sender.send(record).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => eprintln!("searching {} failed: {}", fen, e),
+                }
+            }
+        });
     }
-}";
 
-    content.push_str(header);
+    receiver
+}
 
-    for (fen, chess_move) in opening_book {
-        let chess_move_str = format!("BitMove::new({})", chess_move.get_raw());
+/// Every record in the checkpoint file by position. A line that doesn't parse
+/// is most likely the last one, cut off when the run was stopped. A checkpoint
+/// made with other search options is refused rather than mixed in.
+fn load_checkpoint(path: &str, options: &BookGenOptions) -> Result<HashMap<u64, GenRecord>> {
+    let mut records = HashMap::new();
 
-        let line = format!("(String::from(\"{}\"), {}),\n", fen, chess_move_str);
-        content.push_str(line.as_str());
+    if !Path::new(path).exists() {
+        return Ok(records);
     }
 
-    content.push_str(footer);
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    let header = options.checkpoint_header();
+    match lines.next() {
+        None => return Ok(records),
+        Some(line) if line == header => {}
+        Some(line) => {
+            return Err(anyhow!(
+                "{} was made with other options ({:?}, this run is {:?}), delete it or pick another --checkpoint",
+                path,
+                line,
+                header
+            ))
+        }
+    }
 
-    fs::write(file_name, content)?;
+    for line in lines {
+        match GenRecord::parse(line) {
+            Ok(record) => {
+                records.insert(record.board().zobrist(), record);
+            }
+            Err(e) => eprintln!("skipping checkpoint line {:?}: {}", line, e),
+        }
+    }
 
-    Ok(())
+    Ok(records)
 }
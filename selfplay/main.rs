@@ -0,0 +1,181 @@
+use antichess_engine::engine::Engine;
+use antichess_engine::selfplay::{
+    play_game, write_header, write_training_text, SelfPlayOptions, TrainingRecord,
+};
+
+use anyhow::Result;
+use clap::{App, Arg};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+use std::thread;
+
+/// Plays the engine against itself and writes every searched position, with
+/// its score, best move and the final result of the game, to a file.
+fn main() -> Result<()> {
+    let matches = App::new("selfplay")
+        .about("Generates training data from engine vs engine games")
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("where to write the positions")
+                .takes_value(true)
+                .default_value("selfplay.data"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("binary for the tuner, or text (fen | score | result) for training a network")
+                .takes_value(true)
+                .possible_values(["binary", "text"])
+                .default_value("binary"),
+        )
+        .arg(
+            Arg::with_name("games")
+                .long("games")
+                .value_name("N")
+                .help("number of games to play")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .help("number of games to play at the same time")
+                .takes_value(true)
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("nodes")
+                .long("nodes")
+                .value_name("N")
+                .help("nodes to search for every move")
+                .takes_value(true)
+                .default_value("20000"),
+        )
+        .arg(
+            Arg::with_name("random-plies")
+                .long("random-plies")
+                .value_name("N")
+                .help("random moves played at the start of every game")
+                .takes_value(true)
+                .default_value("8"),
+        )
+        .arg(
+            Arg::with_name("max-plies")
+                .long("max-plies")
+                .value_name("N")
+                .help("games longer than this are scored as a draw")
+                .takes_value(true)
+                .default_value("400"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("game n is played with seed + n, so runs can be reproduced")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .get_matches();
+
+    let games: u64 = matches.value_of("games").unwrap().parse()?;
+    let threads: u64 = matches.value_of("threads").unwrap().parse::<u64>()?.max(1);
+    let seed: u64 = matches.value_of("seed").unwrap().parse()?;
+    let options = SelfPlayOptions {
+        random_plies: matches.value_of("random-plies").unwrap().parse()?,
+        max_plies: matches.value_of("max-plies").unwrap().parse()?,
+        nodes: matches.value_of("nodes").unwrap().parse()?,
+    };
+
+    let text = matches.value_of("format") == Some("text");
+
+    let mut out = BufWriter::new(File::create(matches.value_of("output").unwrap())?);
+    if !text {
+        write_header(&mut out)?;
+    }
+
+    let (sender, receiver) = mpsc::channel::<Vec<TrainingRecord>>();
+
+    let mut workers = Vec::new();
+    for worker in 0..threads {
+        let sender = sender.clone();
+        let options = options.clone();
+
+        workers.push(thread::spawn(move || {
+            for game in (worker..games).step_by(threads as usize) {
+                let mut engine = Engine::new();
+                engine.seed(seed + game);
+
+                match play_game(&mut engine, &options) {
+                    Ok(records) => {
+                        if sender.send(records).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => eprintln!("game {} failed: {}", game, e),
+                }
+            }
+        }));
+    }
+
+    // only the workers hold senders now, so the loop ends when they finish
+    drop(sender);
+
+    let mut finished = 0;
+    let mut positions = 0;
+    let mut white_wins = 0;
+    let mut draws = 0;
+    let mut black_wins = 0;
+
+    for records in receiver {
+        // a game over before anything was searched has no result to count
+        let result = match records.first() {
+            Some(record) => record.result,
+            None => continue,
+        };
+
+        if text {
+            write_training_text(&records, &mut out)?;
+        } else {
+            for record in &records {
+                out.write_all(&record.encode())?;
+            }
+        }
+        out.flush()?;
+
+        finished += 1;
+        positions += records.len();
+        if result == 1.0 {
+            white_wins += 1;
+        } else if result == 0.0 {
+            black_wins += 1;
+        } else {
+            draws += 1;
+        }
+
+        println!(
+            "games: {}/{} positions: {} (+{} ={} -{})",
+            finished, games, positions, white_wins, draws, black_wins
+        );
+    }
+
+    let panicked = workers
+        .into_iter()
+        .map(|worker| worker.join())
+        .filter(|joined| joined.is_err())
+        .count();
+    if panicked > 0 {
+        eprintln!(
+            "{} of the workers panicked, their games are missing",
+            panicked
+        );
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use crate::engine::opening::{position_key, BookMove, BookStats, OpeningBook};
+use crate::engine::search::SearchLimits;
+use crate::engine::{valid_moves, Engine};
+use crate::notation::parse_move;
+use crate::pgn::PgnGame;
+
+use anyhow::{anyhow, Result};
+use pleco::{BitMove, Board};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct BookGenOptions {
+    /// how many plies deep the book goes
+    pub depth: u32,
+    /// depth every book position is searched to
+    pub search_depth: i32,
+    /// most time spent searching a single book position
+    pub movetime: Duration,
+    /// only follow this many of the most plausible moves out of every
+    /// position, 0 follows every valid move
+    pub replies: usize,
+    /// nodes spent on every move when ranking them for `replies`
+    pub reply_nodes: u64,
+}
+
+impl Default for BookGenOptions {
+    fn default() -> Self {
+        BookGenOptions {
+            depth: 2,
+            search_depth: 8,
+            movetime: Duration::from_secs(60),
+            replies: 0,
+            reply_nodes: 2000,
+        }
+    }
+}
+
+impl BookGenOptions {
+    /// The first line of a checkpoint file, with every option that changes
+    /// what gets searched. Records searched with other options can't be mixed
+    /// in. The book depth isn't in it, a deeper run can carry on from a
+    /// shallower one.
+    pub fn checkpoint_header(&self) -> String {
+        format!(
+            "# search-depth {} movetime {} replies {} reply-nodes {}",
+            self.search_depth,
+            self.movetime.as_millis(),
+            self.replies,
+            self.reply_nodes
+        )
+    }
+}
+
+/// A searched book position, one line of the checkpoint file
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenRecord {
+    pub fen: String,
+    /// plies from the starting position
+    pub ply: u32,
+    pub best_move: BitMove,
+    /// search score from the point of view of the side to move
+    pub score: f64,
+    /// the moves the book carries on with from here
+    pub follow: Vec<BitMove>,
+}
+
+/// the last field of every record, so we can tell a line that got cut off
+/// (even between two moves) from a complete one
+const RECORD_END: &str = "end";
+
+impl GenRecord {
+    /// `fen | ply | best move | score | moves to follow | end`, moves in UCI
+    pub fn to_line(&self) -> String {
+        let follow: Vec<String> = self.follow.iter().map(|m| m.stringify()).collect();
+
+        format!(
+            "{} | {} | {} | {} | {} | {}",
+            self.fen,
+            self.ply,
+            self.best_move.stringify(),
+            self.score,
+            follow.join(" "),
+            RECORD_END
+        )
+    }
+
+    pub fn parse(line: &str) -> Result<GenRecord> {
+        let fields: Vec<&str> = line.split('|').map(|f| f.trim()).collect();
+        if fields.len() != 6 || fields[5] != RECORD_END {
+            return Err(anyhow!("incomplete record"));
+        }
+
+        let board =
+            Board::from_fen(fields[0]).map_err(|e| anyhow!("bad fen {}: {:?}", fields[0], e))?;
+        let find = |text: &str| {
+            parse_move(&board, text).ok_or_else(|| anyhow!("{} isn't legal in {}", text, fields[0]))
+        };
+
+        Ok(GenRecord {
+            fen: fields[0].to_string(),
+            ply: fields[1].parse()?,
+            best_move: find(fields[2])?,
+            score: fields[3].parse()?,
+            follow: fields[4]
+                .split_whitespace()
+                .map(find)
+                .collect::<Result<Vec<BitMove>>>()?,
+        })
+    }
+
+    pub fn board(&self) -> Board {
+        Board::from_fen(&self.fen).unwrap()
+    }
+}
+
+/// Searches a book position and picks the moves to follow out of it
+pub fn search_position(
+    engine: &mut Engine,
+    board: &Board,
+    ply: u32,
+    options: &BookGenOptions,
+) -> Result<GenRecord> {
+    let limits = SearchLimits {
+        depth: Some(options.search_depth),
+        movetime: Some(options.movetime),
+        ..Default::default()
+    };
+    let res = engine.search(board, &limits)?;
+
+    let follow = if options.replies == 0 {
+        valid_moves(board)
+    } else {
+        let mut follow = vec![res.best_move];
+        for m in plausible_moves(engine, board, options)? {
+            if follow.len() >= options.replies {
+                break;
+            }
+            if !follow.contains(&m) {
+                follow.push(m);
+            }
+        }
+        follow
+    };
+
+    Ok(GenRecord {
+        fen: board.fen(),
+        ply,
+        best_move: res.best_move,
+        score: res.score,
+        follow,
+    })
+}
+
+/// Every valid move, best first by a quick search of the position it leads to
+pub fn plausible_moves(
+    engine: &mut Engine,
+    board: &Board,
+    options: &BookGenOptions,
+) -> Result<Vec<BitMove>> {
+    let limits = SearchLimits {
+        nodes: Some(options.reply_nodes),
+        depth: Some(options.search_depth),
+        ..Default::default()
+    };
+
+    let mut scored = Vec::new();
+    for m in valid_moves(board) {
+        let mut after = board.clone();
+        after.apply_move(m);
+
+        let score = if after.checkmate() {
+            f64::INFINITY
+        } else if after.stalemate() {
+            0.0
+        } else {
+            -engine.search(&after, &limits)?.score
+        };
+        scored.push((m, score));
+    }
+
+    // stable, so equal scores keep the move generation order
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    Ok(scored.into_iter().map(|(m, _)| m).collect())
+}
+
+#[derive(Clone)]
+pub struct BookBuildOptions {
+    /// only moves in the first `plies` plies of a game go in the book
+    pub plies: u32,
+    /// least number of games a move has to be played in
+    pub min_count: u32,
+    /// least average result for the side playing the move, moves only seen in
+    /// games without a result are kept
+    pub min_score: f64,
+}
+
+impl Default for BookBuildOptions {
+    fn default() -> Self {
+        BookBuildOptions {
+            plies: 20,
+            min_count: 2,
+            min_score: 0.0,
+        }
+    }
+}
+
+/// Builds a book out of the opening moves of a game collection. Every move
+/// gets a weight of how many games played it, and the games' results as its
+/// stats. Games that don't start from the starting position are left out.
+pub fn build_from_games(games: &[PgnGame], options: &BookBuildOptions) -> OpeningBook {
+    let mut counts: HashMap<(u64, u16), (u32, BookStats)> = HashMap::new();
+
+    for game in games.iter().filter(|g| g.from_start_pos()) {
+        let mut board = Board::start_pos();
+
+        for m in game.moves.iter().take(options.plies as usize) {
+            let (count, stats) = counts
+                .entry((position_key(&board), m.get_raw()))
+                .or_default();
+            *count += 1;
+
+            if let Some(result) = game.result {
+                let white = board.turn() == pleco::Player::White;
+                stats.record(if white { result } else { 1.0 - result });
+            }
+
+            board.apply_move(*m);
+        }
+    }
+
+    let mut book = OpeningBook::empty();
+    for ((key, raw), (count, stats)) in counts {
+        if count < options.min_count || stats.score().is_some_and(|s| s < options.min_score) {
+            continue;
+        }
+
+        book.insert_key(
+            key,
+            BookMove {
+                chess_move: BitMove::new(raw),
+                weight: count.min(u16::MAX as u32) as u16,
+                stats,
+            },
+        );
+    }
+
+    book
+}
+
+#[cfg(test)]
+mod book_gen_tests {
+    use super::*;
+
+    #[test]
+    fn test_record_line() {
+        let board = Board::start_pos();
+        let moves = valid_moves(&board);
+
+        let record = GenRecord {
+            fen: board.fen(),
+            ply: 0,
+            best_move: moves[3],
+            score: f64::NEG_INFINITY,
+            follow: moves[..4].to_vec(),
+        };
+
+        assert_eq!(GenRecord::parse(&record.to_line()).unwrap(), record);
+        assert!(GenRecord::parse("8/8/8 | 0").is_err());
+
+        // a line cut off by an interrupted run, wherever it stopped
+        let line = record.to_line();
+        for cut in 1..line.len() {
+            assert!(GenRecord::parse(&line[..cut]).is_err(), "{}", &line[..cut]);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_header() {
+        let options = BookGenOptions::default();
+        let deeper = BookGenOptions {
+            depth: 6,
+            ..options.clone()
+        };
+        let slower = BookGenOptions {
+            movetime: Duration::from_secs(5),
+            ..options.clone()
+        };
+
+        assert_eq!(options.checkpoint_header(), deeper.checkpoint_header());
+        assert_ne!(options.checkpoint_header(), slower.checkpoint_header());
+        assert!(GenRecord::parse(&options.checkpoint_header()).is_err());
+    }
+
+    #[test]
+    fn test_search_position() {
+        let board = Board::from_fen("7k/8/8/3q1n2/4P3/8/8/6K1 w - - 0 1").unwrap();
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+
+        let options = BookGenOptions {
+            search_depth: 3,
+            replies: 2,
+            reply_nodes: 200,
+            ..Default::default()
+        };
+        let record = search_position(&mut engine, &board, 4, &options).unwrap();
+
+        // e4 has to take something
+        assert_eq!(record.follow.len(), 2);
+        assert_eq!(record.follow[0], record.best_move);
+        assert!(board.is_capture(record.best_move));
+        assert_eq!(record.ply, 4);
+
+        let options = BookGenOptions {
+            replies: 0,
+            ..options
+        };
+        let record = search_position(&mut engine, &board, 4, &options).unwrap();
+        assert_eq!(record.follow, valid_moves(&board));
+    }
+
+    #[test]
+    fn test_build_from_games() {
+        let games = crate::pgn::parse_pgn(
+            "1. e3 b5 2. Bxb5 1-0\n\
+             1. e3 b5 2. Bxb5 0-1\n\
+             1. e3 e6 1-0\n\
+             1. g3 *\n\
+             [FEN \"8/8/8/8/8/8/P7/K6k w - - 0 1\"]\n1. a4 a5 1-0",
+        );
+        assert_eq!(games.len(), 5);
+
+        let options = BookBuildOptions {
+            plies: 2,
+            min_count: 1,
+            min_score: 0.0,
+        };
+        let book = build_from_games(&games, &options);
+
+        let start = Board::start_pos();
+        let moves = book.moves(&start);
+        assert_eq!(moves.len(), 2);
+
+        let e3 = moves
+            .iter()
+            .find(|m| m.chess_move.stringify() == "e2e3")
+            .unwrap();
+        assert_eq!(e3.weight, 3);
+        assert_eq!((e3.stats.wins, e3.stats.losses), (2, 1));
+
+        // the set up game and the moves past `plies` stay out
+        assert_eq!(book.len(), 2);
+
+        let mut board = start.clone();
+        board.apply_move(e3.chess_move);
+        let replies = book.moves(&board);
+        let b5 = replies
+            .iter()
+            .find(|m| m.chess_move.stringify() == "b7b5")
+            .unwrap();
+        assert_eq!((b5.stats.wins, b5.stats.draws, b5.stats.losses), (1, 0, 1));
+
+        // e6 only lost for black, g3 has no result so it stays
+        let options = BookBuildOptions {
+            min_score: 0.4,
+            ..options
+        };
+        let book = build_from_games(&games, &options);
+        assert_eq!(book.moves(&start).len(), 2);
+        assert_eq!(book.moves(&board).len(), 1);
+
+        let options = BookBuildOptions {
+            min_count: 2,
+            ..options
+        };
+        let book = build_from_games(&games, &options);
+        assert_eq!(book.moves(&start).len(), 1);
+        assert_eq!(book.moves(&board).len(), 1);
+    }
+}
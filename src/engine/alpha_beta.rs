@@ -1,5 +1,7 @@
-use crate::engine::evaluate_game::evaluate;
+use crate::engine::eval_state::EvalState;
+use crate::engine::evaluate_game::{evaluate, evaluate_with_state};
 use crate::engine::move_sort::sort_moves;
+use crate::engine::see::see;
 use crate::engine::store::TranspositionTableFlag;
 use crate::engine::Engine;
 
@@ -17,6 +19,19 @@ pub fn alpha_beta(board: &Board, engine: &mut Engine) -> Result<(BitMove, f64)>
     let reasonable_depth = engine.store.curr_depth;
     let max_depth = engine.params.max_depth;
 
+    engine.store.root_ply = board.ply();
+
+    engine.store.eval_states.clear();
+    engine
+        .store
+        .eval_states
+        .push(EvalState::new(board, &engine.eval_params));
+
+    engine.store.accumulators.clear();
+    if let Some(network) = &engine.nnue {
+        engine.store.accumulators.push(network.refresh(board));
+    }
+
     let mut best_move: Option<BitMove> = None;
 
     let mut alpha = f64::NEG_INFINITY;
@@ -25,12 +40,20 @@ pub fn alpha_beta(board: &Board, engine: &mut Engine) -> Result<(BitMove, f64)>
     let mut best_score = f64::NEG_INFINITY;
 
     let mut all_valid_moves = engine.generate_valid_moves(board);
-    let valid_moves_len = all_valid_moves.len();
 
     if all_valid_moves.is_empty() {
         return Err(ChessError::NoValidMoves.into());
     }
 
+    // only search the moves that keep the tablebase result, so we don't throw
+    // away a win or make a mate take longer than it has to
+    if let Some(tablebase) = &engine.tablebase {
+        if let Some(moves) = tablebase.root_moves(board, &all_valid_moves) {
+            all_valid_moves = moves;
+        }
+    }
+    let valid_moves_len = all_valid_moves.len();
+
     // move ordering, sort moves based on order we should check them.
     // we want to check the best moves first because that gives us the
     // best chance of pruning the search tree.
@@ -47,21 +70,14 @@ pub fn alpha_beta(board: &Board, engine: &mut Engine) -> Result<(BitMove, f64)>
             );
         }
 
-        if let Some(start_time) = engine.store.start_time {
-            if start_time.elapsed() > engine.params.max_time {
-                if engine.params.debug_print > 0 {
-                    eprintln!(
-                        "Search time limit reached: {}",
-                        engine.params.max_time.as_secs()
-                    );
-                }
-                break;
+        if engine.store.limit_reached()? {
+            if engine.params.debug_print > 0 {
+                eprintln!("Search limit reached after {} nodes", engine.store.nodes);
             }
-        } else {
-            return Err(ChessError::NoStartTime.into());
+            break;
         }
 
-        new_board.apply_move(chess_move);
+        make_move(&mut new_board, chess_move, engine);
 
         let eval_res = alpha_beta_impl(
             &mut new_board,
@@ -73,18 +89,21 @@ pub fn alpha_beta(board: &Board, engine: &mut Engine) -> Result<(BitMove, f64)>
             engine,
         );
 
-        new_board.undo_move();
+        unmake_move(&mut new_board, engine);
 
-        if eval_res.is_err() {
-            if engine.params.handle_errors {
+        if let Err(e) = eval_res {
+            if is_limit_reached(&e) {
+                // this move was only partly searched, so we can't trust its score
+                break;
+            } else if engine.params.handle_errors {
                 // if we get an error, we just continue with the best move we have so far
                 continue;
             } else {
-                return Err(eval_res.err().unwrap());
+                return Err(e);
             }
         }
 
-        let eval = -eval_res.unwrap();
+        let eval = -eval_res?;
 
         if eval == best_score && best_move.is_none() {
             best_move = Some(chess_move)
@@ -127,6 +146,59 @@ pub fn alpha_beta(board: &Board, engine: &mut Engine) -> Result<(BitMove, f64)>
     }
 }
 
+/// An aborted search has to unwind all the way back to the root, unlike other
+/// errors which only skip the move that caused them.
+fn is_limit_reached(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ChessError>() == Some(&ChessError::SearchLimitReached)
+}
+
+/// Plays the move, keeping the eval state and the network accumulators in
+/// step with the board. Null moves don't move any pieces, so they don't need this.
+fn make_move(board: &mut Board, m: BitMove, engine: &mut Engine) {
+    let state = match engine.store.eval_states.last() {
+        Some(state) => state.update(board, m, &engine.eval_params),
+        None => EvalState::new(board, &engine.eval_params),
+    };
+    engine.store.eval_states.push(state);
+
+    if let Some(network) = &engine.nnue {
+        let acc = match engine.store.accumulators.last() {
+            Some(acc) => network.update(acc, board, m),
+            None => network.refresh(board),
+        };
+        engine.store.accumulators.push(acc);
+    }
+
+    board.apply_move(m);
+}
+
+fn unmake_move(board: &mut Board, engine: &mut Engine) {
+    engine.store.eval_states.pop();
+    if engine.nnue.is_some() {
+        engine.store.accumulators.pop();
+    }
+
+    board.undo_move();
+}
+
+/// Score of a leaf from the side to move's point of view. Finished games
+/// always go through the classical eval, the network doesn't know about them.
+fn leaf_eval(board: &Board, pov: f64, engine: &mut Engine) -> f64 {
+    if let (Some(network), Some(acc)) = (&engine.nnue, engine.store.accumulators.last()) {
+        if !board.checkmate() && !board.stalemate() {
+            return network.evaluate(acc, board.turn());
+        }
+    }
+
+    let store = &mut engine.store;
+    match store.eval_states.last() {
+        Some(state) => {
+            pov * evaluate_with_state(board, state, &engine.eval_params, &mut store.pawn_table)
+        }
+        None => pov * evaluate(board, &engine.eval_params),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn alpha_beta_impl(
     board: &mut Board,
@@ -137,6 +209,23 @@ fn alpha_beta_impl(
     do_null: bool,
     engine: &mut Engine,
 ) -> Result<f64> {
+    engine.store.nodes += 1;
+
+    // the root move itself is one ply, so the horizon sits one past curr_depth
+    let in_quiescence =
+        board.ply().saturating_sub(engine.store.root_ply) as i32 > engine.store.curr_depth + 1;
+    if in_quiescence {
+        engine.store.qnodes += 1;
+    }
+
+    // mates found by the search score more than ones from the tables
+    if let Some(probe) = engine.tablebase.as_ref().and_then(|tb| tb.probe(board)) {
+        if !board.checkmate() {
+            engine.store.tb_hits += 1;
+            return Ok(probe.score());
+        }
+    }
+
     if curr_depth <= 0 || max_depth <= 0 || board.checkmate() || board.stalemate() {
         let pov = if board.turn() == Player::White {
             1.0
@@ -144,7 +233,7 @@ fn alpha_beta_impl(
             -1.0
         };
 
-        return Ok(pov * evaluate(board));
+        return Ok(leaf_eval(board, pov, engine));
     }
 
     let mut curr_alpha = alpha;
@@ -222,26 +311,26 @@ fn alpha_beta_impl(
 
     let mut best_move = None;
 
-    for move_option in all_valid_moves {
+    for (ind, move_option) in all_valid_moves.into_iter().enumerate() {
         if engine.params.debug_print > 1 {
             eprintln!("move: {} {} {:?}", board.turn(), move_option, move_option);
         }
 
-        if let Some(start_time) = engine.store.start_time {
-            if start_time.elapsed() > engine.params.max_time {
-                if engine.params.debug_print > 0 {
-                    eprintln!(
-                        "Search time limit reached: {}",
-                        engine.params.max_time.as_secs()
-                    );
-                }
-                break;
-            }
-        } else {
-            return Err(ChessError::NoStartTime.into());
+        if engine.store.limit_reached()? {
+            return Err(ChessError::SearchLimitReached.into());
+        }
+
+        // always search the first move, so there is a score to return
+        if in_quiescence
+            && engine.params.see_pruning
+            && ind > 0
+            && move_option.is_capture()
+            && see(board, move_option) < 0.0
+        {
+            continue;
         }
 
-        board.apply_move(move_option);
+        make_move(board, move_option, engine);
 
         let eval_res = alpha_beta_impl(
             board,
@@ -253,18 +342,18 @@ fn alpha_beta_impl(
             engine,
         );
 
-        board.undo_move();
+        unmake_move(board, engine);
 
-        if eval_res.is_err() {
-            if engine.params.handle_errors {
+        if let Err(e) = eval_res {
+            if engine.params.handle_errors && !is_limit_reached(&e) {
                 // if we get an error, we just continue with the best move we have so far
                 continue;
             } else {
-                return Err(eval_res.err().unwrap());
+                return Err(e);
             }
         }
 
-        let eval = -eval_res.unwrap();
+        let eval = -eval_res?;
 
         if eval > score {
             score = eval;
@@ -308,7 +397,7 @@ mod alpha_beta_tests {
         let mut game = Board::start_pos();
         let mut engine = Engine::new();
         engine.params.depth = 5;
-        engine.params.max_time = Duration::from_secs(8);
+        engine.store.time_limit = Some(Duration::from_secs(8));
 
         engine.store.curr_depth = engine.params.depth;
 
@@ -0,0 +1,243 @@
+use crate::engine::opening::position_key;
+use crate::engine::search::SearchResult;
+use crate::engine::valid_moves;
+
+use anyhow::{bail, Result};
+use pleco::{BitMove, Board};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ACAN";
+const VERSION: u8 = 1;
+/// magic, version, the settings fingerprint and the number of entries, both
+/// little endian u64
+const HEADER_LEN: usize = 21;
+/// position key (u64), depth (i32), score (f64) and the pv length (u16), all
+/// little endian, followed by the pv as raw moves (u16 each)
+const ENTRY_LEN: usize = 22;
+
+/// What a finished search found for a root position
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedAnalysis {
+    /// the last depth that was fully searched
+    pub depth: i32,
+    /// score from the point of view of the side to move
+    pub score: f64,
+    /// principal variation, starting with the best move
+    pub pv: Vec<BitMove>,
+}
+
+impl CachedAnalysis {
+    pub fn best_move(&self) -> BitMove {
+        self.pv[0]
+    }
+}
+
+/// FNV-1a, which unlike the std hashers is the same on every build, so a
+/// fingerprint saved in a file still means something to the next version
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Search results of root positions by `position_key`, kept across searches
+/// and saved to a file so a later session can reuse them. The transposition
+/// table is cleared every move, this is what survives.
+///
+/// Results depend on the engine settings, so the cache keeps the fingerprint
+/// of the settings it was made with (see `Engine::settings_fingerprint`).
+/// `Engine::set_analysis_cache` only takes it with the same ones.
+#[derive(Clone, Debug, Default)]
+pub struct AnalysisCache {
+    fingerprint: u64,
+    entries: HashMap<u64, CachedAnalysis>,
+}
+
+impl AnalysisCache {
+    pub fn new(fingerprint: u64) -> AnalysisCache {
+        AnalysisCache {
+            fingerprint,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// The analysis of this position, unless its move isn't valid here, which
+    /// only happens on a key collision
+    pub fn get(&self, board: &Board) -> Option<&CachedAnalysis> {
+        self.entries
+            .get(&position_key(board))
+            .filter(|entry| valid_moves(board).contains(&entry.best_move()))
+    }
+
+    /// Keeps the search result, unless there already is a deeper one for the
+    /// position. A proven win is never replaced by an unproven result.
+    pub fn store(&mut self, board: &Board, res: &SearchResult) {
+        if res.depth <= 0 || res.pv.is_empty() {
+            return;
+        }
+
+        let key = position_key(board);
+        if let Some(existing) = self.entries.get(&key) {
+            if existing.depth > res.depth || existing.score == f64::INFINITY {
+                return;
+            }
+        }
+
+        self.entries.insert(
+            key,
+            CachedAnalysis {
+                depth: res.depth,
+                score: res.score,
+                pv: res.pv.clone(),
+            },
+        );
+    }
+
+    /// number of positions in the cache
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cache as bytes: a header, then the entries sorted by key
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut keys: Vec<&u64> = self.entries.keys().collect();
+        keys.sort_unstable();
+
+        let mut out = Vec::with_capacity(HEADER_LEN + ENTRY_LEN * keys.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.fingerprint.to_le_bytes());
+        out.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+
+        for key in keys {
+            let entry = &self.entries[key];
+            out.extend_from_slice(&key.to_le_bytes());
+            out.extend_from_slice(&entry.depth.to_le_bytes());
+            out.extend_from_slice(&entry.score.to_le_bytes());
+            out.extend_from_slice(&(entry.pv.len() as u16).to_le_bytes());
+            for m in &entry.pv {
+                out.extend_from_slice(&m.get_raw().to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<AnalysisCache> {
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            bail!("not an analysis cache file");
+        }
+        if bytes[4] != VERSION {
+            bail!("unsupported analysis cache version {}", bytes[4]);
+        }
+
+        let fingerprint = u64::from_le_bytes(bytes[5..13].try_into()?);
+        let count = u64::from_le_bytes(bytes[13..21].try_into()?) as usize;
+        let mut cache = AnalysisCache::new(fingerprint);
+        let mut at = HEADER_LEN;
+
+        for _ in 0..count {
+            if bytes.len() < at + ENTRY_LEN {
+                bail!("analysis cache ends part way through an entry");
+            }
+            let entry = &bytes[at..at + ENTRY_LEN];
+            let key = u64::from_le_bytes(entry[0..8].try_into()?);
+            let depth = i32::from_le_bytes(entry[8..12].try_into()?);
+            let score = f64::from_le_bytes(entry[12..20].try_into()?);
+            let pv_len = u16::from_le_bytes([entry[20], entry[21]]) as usize;
+            at += ENTRY_LEN;
+
+            if pv_len == 0 || bytes.len() < at + 2 * pv_len {
+                bail!("analysis cache has a bad pv at byte {}", at);
+            }
+            let pv = bytes[at..at + 2 * pv_len]
+                .chunks(2)
+                .map(|raw| BitMove::new(u16::from_le_bytes([raw[0], raw[1]])))
+                .collect();
+            at += 2 * pv_len;
+
+            cache
+                .entries
+                .insert(key, CachedAnalysis { depth, score, pv });
+        }
+
+        if at != bytes.len() {
+            bail!("analysis cache has {} bytes left over", bytes.len() - at);
+        }
+
+        Ok(cache)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<AnalysisCache> {
+        AnalysisCache::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn result(board: &Board, depth: i32, score: f64) -> SearchResult {
+        let pv = valid_moves(board)[..2].to_vec();
+
+        SearchResult {
+            best_move: pv[0],
+            ponder_move: None,
+            score,
+            depth,
+            nodes: 100,
+            pv,
+            elapsed: Duration::from_millis(1),
+            from_book: false,
+            from_cache: false,
+            iterations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_store_and_round_trip() {
+        let board = Board::start_pos();
+        let mut cache = AnalysisCache::new(42);
+        assert!(cache.get(&board).is_none());
+
+        cache.store(&board, &result(&board, 4, 1.5));
+        // shallower results don't replace deeper ones
+        cache.store(&board, &result(&board, 2, -3.0));
+        assert_eq!(cache.get(&board).unwrap().depth, 4);
+
+        cache.store(&board, &result(&board, 6, f64::INFINITY));
+        cache.store(&board, &result(&board, 8, 2.0));
+        assert_eq!(cache.get(&board).unwrap().score, f64::INFINITY);
+
+        let other = Board::from_fen("7k/8/8/3q1n2/4P3/8/8/6K1 w - - 0 1").unwrap();
+        cache.store(&other, &result(&other, 3, -0.25));
+        assert_eq!(cache.len(), 2);
+
+        let loaded = AnalysisCache::from_bytes(&cache.to_bytes()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.fingerprint(), 42);
+        assert_eq!(loaded.get(&board), cache.get(&board));
+        assert_eq!(loaded.get(&other), cache.get(&other));
+
+        let bytes = cache.to_bytes();
+        assert!(AnalysisCache::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(AnalysisCache::from_bytes(b"ACBK").is_err());
+    }
+}
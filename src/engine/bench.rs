@@ -0,0 +1,100 @@
+use crate::engine::search::SearchLimits;
+use crate::engine::Engine;
+
+use anyhow::{anyhow, Result};
+use pleco::Board;
+
+use std::time::{Duration, Instant};
+
+/// Positions searched by the bench. Changing this list changes the signature.
+pub const BENCH_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+    "7k/8/8/3q1n2/4P3/8/8/7K w - - 0 1",
+    "7k/8/8/4p3/3Q1N2/8/8/7K b - - 0 1",
+    "6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - 0 1",
+    "2bqkbnr/3p2pp/2n2p2/4p3/8/1PP1P3/3PQPPP/2r1KBNR w - - 0 1",
+    "2bqk2r/1p1p1ppp/2pNp3/7P/8/8/R2KP3/2B2BnR b - - 0 1",
+];
+
+/// The depth the bench searches to when none is given.
+pub const DEFAULT_BENCH_DEPTH: i32 = 5;
+
+/// The signature is the node count at this depth, low enough for the tests to
+/// check it in a debug build
+pub const SIGNATURE_DEPTH: i32 = 3;
+
+/// Node count of `bench(SIGNATURE_DEPTH)`. If you meant to change how the
+/// search behaves, update this number, otherwise your change has a bug.
+pub const BENCH_SIGNATURE: u64 = 8519;
+
+pub struct BenchResult {
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// nodes searched per second
+    pub fn nps(&self) -> u64 {
+        let millis = self.elapsed.as_millis().max(1);
+
+        (self.nodes as u128 * 1000 / millis) as u64
+    }
+}
+
+/// Searches every bench position to a fixed depth with no time limit.
+///
+/// The total node count doubles as a signature of the search: a change that
+/// only makes the search faster keeps it the same, anything that changes which
+/// nodes get visited (pruning, move ordering, evaluation) will change it.
+pub fn bench(depth: i32) -> Result<BenchResult> {
+    let limits = SearchLimits {
+        depth: Some(depth),
+        infinite: true,
+        ..Default::default()
+    };
+
+    let mut nodes = 0;
+    let mut qnodes = 0;
+
+    let start = Instant::now();
+
+    for fen in BENCH_POSITIONS {
+        let board = Board::from_fen(fen).map_err(|e| anyhow!("bad bench fen {}: {:?}", fen, e))?;
+
+        // a fresh engine for every position so the result never depends on
+        // what was searched before it
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+
+        let res = engine.search(&board, &limits)?;
+
+        nodes += res.nodes;
+        qnodes += engine.store.qnodes;
+    }
+
+    Ok(BenchResult {
+        nodes,
+        qnodes,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod bench_tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_signature() {
+        let first = bench(SIGNATURE_DEPTH).unwrap();
+        let second = bench(SIGNATURE_DEPTH).unwrap();
+
+        assert_eq!(first.nodes, second.nodes);
+        assert_eq!(first.qnodes, second.qnodes);
+        assert!(first.qnodes <= first.nodes);
+
+        assert_eq!(first.nodes, BENCH_SIGNATURE);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::engine::search::{SearchLimits, SearchResult};
+use crate::engine::store::AlphaBetaStore;
+use crate::engine::Engine;
+use crate::notation::parse_move;
+
+use anyhow::{anyhow, bail, Result};
+use pleco::{BitMove, Board};
+
+use std::fs;
+use std::time::Duration;
+
+/// One position of an EPD test suite.
+///
+/// We understand the standard `id`, `bm` (best move) and `am` (avoid move)
+/// opcodes, plus `dm n` which means the side to move can force a win in `n`
+/// moves. Every other opcode is ignored.
+pub struct EpdEntry {
+    pub board: Board,
+    pub id: String,
+    pub best_moves: Vec<BitMove>,
+    pub avoid_moves: Vec<BitMove>,
+    pub mate_in: Option<u32>,
+}
+
+impl EpdEntry {
+    /// Parses a single EPD line. Blank lines and `#` comments give `None`.
+    pub fn parse(line: &str) -> Result<Option<EpdEntry>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        // the four position fields, then everything after them is operations
+        let mut fields = Vec::new();
+        let mut rest = line;
+        while fields.len() < 4 {
+            let trimmed = rest.trim_start();
+            let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            if end == 0 {
+                bail!("not enough fields in epd line: {}", line);
+            }
+            fields.push(&trimmed[..end]);
+            rest = &trimmed[end..];
+        }
+
+        // epd leaves out the move counters, which pleco needs
+        let fen = format!("{} 0 1", fields[..4].join(" "));
+        let board = Board::from_fen(&fen)
+            .map_err(|e| anyhow!("bad position in epd line {}: {:?}", line, e))?;
+
+        let mut entry = EpdEntry {
+            board,
+            id: String::new(),
+            best_moves: Vec::new(),
+            avoid_moves: Vec::new(),
+            mate_in: None,
+        };
+
+        for op in split_operations(rest) {
+            let (opcode, operands) = op.split_once(char::is_whitespace).unwrap_or((&op, ""));
+            let operands = operands.trim();
+
+            match opcode {
+                "id" => entry.id = operands.trim_matches('"').to_string(),
+                "bm" => entry.best_moves = parse_moves(&entry.board, operands)?,
+                "am" => entry.avoid_moves = parse_moves(&entry.board, operands)?,
+                "dm" => {
+                    entry.mate_in = Some(
+                        operands
+                            .parse()
+                            .map_err(|_| anyhow!("bad dm operand: {}", operands))?,
+                    )
+                }
+                _ => (),
+            }
+        }
+
+        if entry.id.is_empty() {
+            entry.id = entry.board.fen();
+        }
+
+        Ok(Some(entry))
+    }
+
+    /// Whether a search ending on `best_move` with `score` solves this position
+    pub fn is_solved_by(&self, best_move: BitMove, score: f64) -> bool {
+        if !self.best_moves.is_empty() && !self.best_moves.contains(&best_move) {
+            return false;
+        }
+
+        if self.avoid_moves.contains(&best_move) {
+            return false;
+        }
+
+        if self.mate_in.is_some() && score != f64::INFINITY {
+            return false;
+        }
+
+        true
+    }
+
+    /// Whether a winning line is short enough for the `dm` opcode, a mate in
+    /// `n` takes at most `2n - 1` plies
+    pub fn mates_in_time(&self, pv: &[BitMove]) -> bool {
+        self.mate_in.is_none_or(|n| pv.len() < 2 * n as usize)
+    }
+}
+
+/// Splits the operations on `;`, except for the ones inside quotes.
+fn split_operations(operations: &str) -> Vec<String> {
+    let mut ops = Vec::new();
+    let mut curr = String::new();
+    let mut in_quotes = false;
+
+    for c in operations.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                curr.push(c);
+            }
+            ';' if !in_quotes => {
+                ops.push(curr.trim().to_string());
+                curr.clear();
+            }
+            _ => curr.push(c),
+        }
+    }
+
+    if !curr.trim().is_empty() {
+        ops.push(curr.trim().to_string());
+    }
+
+    ops
+}
+
+fn parse_moves(board: &Board, operands: &str) -> Result<Vec<BitMove>> {
+    operands
+        .split_whitespace()
+        .map(|text| {
+            parse_move(board, text)
+                .ok_or_else(|| anyhow!("illegal move {} in {}", text, board.fen()))
+        })
+        .collect()
+}
+
+/// Reads every position out of an EPD file.
+pub fn load_epd(path: &str) -> Result<Vec<EpdEntry>> {
+    let content = fs::read_to_string(path)?;
+
+    let mut entries = Vec::new();
+    for line in content.lines() {
+        if let Some(entry) = EpdEntry::parse(line)? {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+pub struct EpdOutcome {
+    pub id: String,
+    /// `None` if the search failed, see `error`
+    pub played: Option<BitMove>,
+    pub error: Option<String>,
+    pub passed: bool,
+    /// time until the search found the solution and stuck with it
+    pub solve_time: Option<Duration>,
+}
+
+pub struct SuiteReport {
+    pub outcomes: Vec<EpdOutcome>,
+}
+
+impl SuiteReport {
+    pub fn passed(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.passed).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.outcomes.len() - self.passed()
+    }
+}
+
+/// Searches every position in the suite with the given limits, using the
+/// engine's settings (eval params, network, tablebase, book). Its search state
+/// is reset before every position so the results don't depend on the order.
+/// Positions with a `dm` opcode also get it as their `mate_in` limit. A
+/// position the search fails on counts as failed, and the suite carries on.
+pub fn run_suite(engine: &mut Engine, entries: &[EpdEntry], limits: &SearchLimits) -> SuiteReport {
+    let mut outcomes = Vec::new();
+
+    for entry in entries {
+        engine.store = AlphaBetaStore::new();
+
+        let mut entry_limits = limits.clone();
+        if entry.mate_in.is_some() {
+            entry_limits.mate_in = entry.mate_in;
+        }
+
+        let res = match engine.search(&entry.board, &entry_limits) {
+            Ok(res) => res,
+            Err(e) => {
+                outcomes.push(EpdOutcome {
+                    id: entry.id.clone(),
+                    played: None,
+                    error: Some(e.to_string()),
+                    passed: false,
+                    solve_time: None,
+                });
+                continue;
+            }
+        };
+        let passed = entry.is_solved_by(res.best_move, res.score) && entry.mates_in_time(&res.pv);
+
+        outcomes.push(EpdOutcome {
+            id: entry.id.clone(),
+            played: Some(res.best_move),
+            error: None,
+            passed,
+            solve_time: if passed {
+                Some(solve_time(entry, &res))
+            } else {
+                None
+            },
+        });
+    }
+
+    SuiteReport { outcomes }
+}
+
+/// The time of the first iteration after which every iteration had the right answer
+fn solve_time(entry: &EpdEntry, res: &SearchResult) -> Duration {
+    let mut solved_at = res.elapsed;
+
+    for info in res.iterations.iter().rev() {
+        if !entry.is_solved_by(info.best_move, info.score) {
+            break;
+        }
+        solved_at = info.elapsed;
+    }
+
+    solved_at
+}
+
+#[cfg(test)]
+mod epd_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_epd() {
+        let entry = EpdEntry::parse(
+            r#"7k/8/8/3q1n2/4P3/8/8/7K w - - bm exd5; am Kg1; id "pawn; takes queen";"#,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(entry.id, "pawn; takes queen");
+        assert_eq!(entry.best_moves.len(), 1);
+        assert_eq!(entry.best_moves[0].stringify(), "e4d5");
+        assert_eq!(entry.avoid_moves[0].stringify(), "h1g1");
+        assert_eq!(entry.mate_in, None);
+
+        let entry = EpdEntry::parse("6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - dm 1;")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.mate_in, Some(1));
+        assert_eq!(entry.id, "6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - 0 1");
+
+        // any amount of whitespace between the fields
+        let entry = EpdEntry::parse("7k/8/8/3q1n2/4P3/8/8/7K   w  -\t- bm exd5;")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.best_moves[0].stringify(), "e4d5");
+        assert!(EpdEntry::parse("7k/8/8/3q1n2/4P3/8/8/7K w  -").is_err());
+
+        assert!(EpdEntry::parse("# just a comment").unwrap().is_none());
+        assert!(EpdEntry::parse("7k/8/8/8/8/8/8/7K w - - bm Qh8;").is_err());
+    }
+
+    #[test]
+    fn test_load_suite_file() {
+        let entries = load_epd("suites/antichess.epd").unwrap();
+
+        assert!(!entries.is_empty());
+        assert!(entries
+            .iter()
+            .all(|e| !e.best_moves.is_empty() || !e.avoid_moves.is_empty() || e.mate_in.is_some()));
+    }
+
+    #[test]
+    fn test_run_suite() {
+        let entries = vec![
+            EpdEntry::parse("7k/8/8/4p3/3Q1N2/8/8/7K b - - bm exd4; id \"black\";")
+                .unwrap()
+                .unwrap(),
+            EpdEntry::parse("6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - dm 1; id \"mate\";")
+                .unwrap()
+                .unwrap(),
+            // already mated, so there is nothing to search
+            EpdEntry::parse("7k/6Q1/6K1/8/8/8/8/8 b - - id \"mated\";")
+                .unwrap()
+                .unwrap(),
+            EpdEntry::parse("7k/8/8/4p3/3Q1N2/8/8/7K b - - bm exd4; id \"after\";")
+                .unwrap()
+                .unwrap(),
+        ];
+
+        let limits = SearchLimits {
+            depth: Some(4),
+            infinite: true,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        let report = run_suite(&mut engine, &entries, &limits);
+
+        assert_eq!(report.passed(), 3);
+        assert_eq!(report.failed(), 1);
+
+        let mated = &report.outcomes[2];
+        assert!(!mated.passed);
+        assert!(mated.played.is_none() && mated.error.is_some());
+        assert!(report.outcomes[3].passed);
+    }
+
+    #[test]
+    fn test_mate_length() {
+        let entry = EpdEntry::parse("6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - dm 1;")
+            .unwrap()
+            .unwrap();
+        let moves = crate::engine::valid_moves(&entry.board);
+
+        assert!(entry.mates_in_time(&moves[..1]));
+        assert!(!entry.mates_in_time(&moves[..3]));
+    }
+}
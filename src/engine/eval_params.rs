@@ -0,0 +1,447 @@
+use crate::engine::position_scores::*;
+
+use anyhow::{anyhow, bail, Result};
+use pleco::PieceType;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+/// Every weight used by `evaluate`. The defaults are the hand picked values the
+/// engine has always used, so a params file only needs the values it changes.
+///
+/// Every weight but `pst_scale` has a middlegame value ending in `_mg` and an
+/// endgame value ending in `_eg`, which get blended by how much material is
+/// left, see `game_phase`. The endgame values default to the middlegame ones,
+/// except for the king, rook and queen tables.
+///
+/// Params can be loaded from a TOML or JSON file, and single values can be
+/// changed with `set`, e.g. `set("material_mg.knight", "320")`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalParams {
+    /// value of each piece, in pawn, knight, bishop, rook, queen, king order
+    pub material_mg: [f64; 6],
+    /// bonus for attacking an enemy piece, same order as `material_mg`
+    pub threat_mg: [f64; 6],
+    /// bonus for a passed pawn on each rank, counted from its own side
+    pub passed_pawn_mg: [f64; 8],
+    /// bonus for every castling right that is still available
+    pub castle_mg: f64,
+    /// bonus for keeping both bishops
+    pub double_bishop_mg: f64,
+    /// bonus for every piece protecting the king
+    pub king_protection_mg: f64,
+    /// what a 7 in the piece square tables is worth
+    pub pst_scale: f64,
+    /// bonus for the side not to move when the side to move has to capture
+    pub capture_tempo_mg: f64,
+    /// bonus for every capture a side could make. Since captures are forced,
+    /// few options means the opponent gets to decide what we play.
+    pub capture_choice_mg: f64,
+    /// bonus for every defended piece that the opponent can only take with
+    /// something worth more, and so would lose material when forced to
+    pub poisoned_piece_mg: f64,
+    /// bonus for every move a side has, only counting captures if it has any
+    pub mobility_mg: f64,
+    /// bonus for every piece the opponent wins material by taking, going by
+    /// the static exchange, so it should be negative
+    pub hanging_piece_mg: f64,
+    /// the pawn structure terms, see `PawnStructure` for what each one means
+    pub isolated_pawn_mg: f64,
+    pub doubled_pawn_mg: f64,
+    pub backward_pawn_mg: f64,
+    pub connected_pawn_mg: f64,
+    pub candidate_passer_mg: f64,
+    pub blocked_pawn_mg: f64,
+    pub material_eg: [f64; 6],
+    pub threat_eg: [f64; 6],
+    pub passed_pawn_eg: [f64; 8],
+    pub castle_eg: f64,
+    pub double_bishop_eg: f64,
+    pub king_protection_eg: f64,
+    pub capture_tempo_eg: f64,
+    pub capture_choice_eg: f64,
+    pub poisoned_piece_eg: f64,
+    pub mobility_eg: f64,
+    pub hanging_piece_eg: f64,
+    pub isolated_pawn_eg: f64,
+    pub doubled_pawn_eg: f64,
+    pub backward_pawn_eg: f64,
+    pub connected_pawn_eg: f64,
+    pub candidate_passer_eg: f64,
+    pub blocked_pawn_eg: f64,
+    /// the piece square tables, see `position_scores`
+    pub pawn_table_mg: PieceTable,
+    pub knight_table_mg: PieceTable,
+    pub bishop_table_mg: PieceTable,
+    pub rook_table_mg: PieceTable,
+    pub queen_table_mg: PieceTable,
+    pub king_table_mg: PieceTable,
+    pub pawn_table_eg: PieceTable,
+    pub knight_table_eg: PieceTable,
+    pub bishop_table_eg: PieceTable,
+    pub rook_table_eg: PieceTable,
+    pub queen_table_eg: PieceTable,
+    pub king_table_eg: PieceTable,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        let material = [100.0, 300.0, 300.0, 500.0, 900.0, 0.0];
+        let threat = [50.0, 160.0, 170.0, 250.0, 450.0, 1000.0];
+        let passed_pawn = [0.0, 17.0, 20.0, 35.0, 70.0, 175.0, 275.0, 0.0];
+
+        EvalParams {
+            material_mg: material,
+            threat_mg: threat,
+            passed_pawn_mg: passed_pawn,
+            castle_mg: 15.0,
+            double_bishop_mg: 50.0,
+            king_protection_mg: 10.0,
+            pst_scale: 100.0,
+            // the antichess specific and pawn structure terms are off until
+            // they have been tested
+            capture_tempo_mg: 0.0,
+            capture_choice_mg: 0.0,
+            poisoned_piece_mg: 0.0,
+            mobility_mg: 0.0,
+            hanging_piece_mg: 0.0,
+            isolated_pawn_mg: 0.0,
+            doubled_pawn_mg: 0.0,
+            backward_pawn_mg: 0.0,
+            connected_pawn_mg: 0.0,
+            candidate_passer_mg: 0.0,
+            blocked_pawn_mg: 0.0,
+            material_eg: material,
+            threat_eg: threat,
+            passed_pawn_eg: passed_pawn,
+            castle_eg: 15.0,
+            double_bishop_eg: 50.0,
+            king_protection_eg: 10.0,
+            capture_tempo_eg: 0.0,
+            capture_choice_eg: 0.0,
+            poisoned_piece_eg: 0.0,
+            mobility_eg: 0.0,
+            hanging_piece_eg: 0.0,
+            isolated_pawn_eg: 0.0,
+            doubled_pawn_eg: 0.0,
+            backward_pawn_eg: 0.0,
+            connected_pawn_eg: 0.0,
+            candidate_passer_eg: 0.0,
+            blocked_pawn_eg: 0.0,
+            pawn_table_mg: PAWN_TABLE,
+            knight_table_mg: KNIGHT_TABLE,
+            bishop_table_mg: BISHOP_TABLE,
+            rook_table_mg: ROOK_START_TABLE,
+            queen_table_mg: QUEEN_START_TABLE,
+            king_table_mg: KING_TABLE,
+            pawn_table_eg: PAWN_TABLE,
+            knight_table_eg: KNIGHT_TABLE,
+            bishop_table_eg: BISHOP_TABLE,
+            rook_table_eg: ROOK_END_TABLE,
+            queen_table_eg: QUEEN_END_TABLE,
+            king_table_eg: KING_END_TABLE,
+        }
+    }
+}
+
+/// index into the per piece arrays like `EvalParams::material_mg`
+pub fn piece_index(piece_type: PieceType) -> Option<usize> {
+    match piece_type {
+        PieceType::P => Some(0),
+        PieceType::N => Some(1),
+        PieceType::B => Some(2),
+        PieceType::R => Some(3),
+        PieceType::Q => Some(4),
+        PieceType::K => Some(5),
+        _ => None,
+    }
+}
+
+/// Blends a middlegame and an endgame value, `phase` goes from 1.0 for the
+/// middlegame down to 0.0 for the endgame
+pub fn taper(mg: f64, eg: f64, phase: f64) -> f64 {
+    mg * phase + eg * (1.0 - phase)
+}
+
+const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+impl EvalParams {
+    /// Loads params from a file. Files ending in `.json` are read as JSON,
+    /// everything else as TOML.
+    pub fn load(path: &str) -> Result<EvalParams> {
+        let content = fs::read_to_string(path)?;
+
+        if is_json(path) {
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(toml::from_str(&content)?)
+        }
+    }
+
+    /// Writes the params to a file, in the same formats `load` reads
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content = if is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string(self)?
+        };
+
+        fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// Every weight as a flat list, in a fixed order. This is what the tuner
+    /// works on, it doesn't need to know what any of the weights mean.
+    pub fn weights_mut(&mut self) -> Vec<&mut f64> {
+        self.weight_groups()
+            .into_iter()
+            .flat_map(|group| group.iter_mut())
+            .collect()
+    }
+
+    /// number of weights in `weights_mut`
+    pub fn num_weights(&mut self) -> usize {
+        self.weight_groups().iter().map(|group| group.len()).sum()
+    }
+
+    /// A single weight by its index in `weights_mut`, without building the
+    /// whole list
+    pub fn weight_mut(&mut self, mut id: usize) -> Option<&mut f64> {
+        for group in self.weight_groups() {
+            if id < group.len() {
+                return Some(&mut group[id]);
+            }
+            id -= group.len();
+        }
+
+        None
+    }
+
+    /// the weights in the order of `weights_mut`, a group per field
+    fn weight_groups(&mut self) -> [&mut [f64]; 47] {
+        use std::slice::from_mut;
+
+        [
+            &mut self.material_mg,
+            &mut self.threat_mg,
+            &mut self.passed_pawn_mg,
+            from_mut(&mut self.castle_mg),
+            from_mut(&mut self.double_bishop_mg),
+            from_mut(&mut self.king_protection_mg),
+            from_mut(&mut self.pst_scale),
+            from_mut(&mut self.capture_tempo_mg),
+            from_mut(&mut self.capture_choice_mg),
+            from_mut(&mut self.poisoned_piece_mg),
+            from_mut(&mut self.mobility_mg),
+            from_mut(&mut self.hanging_piece_mg),
+            from_mut(&mut self.isolated_pawn_mg),
+            from_mut(&mut self.doubled_pawn_mg),
+            from_mut(&mut self.backward_pawn_mg),
+            from_mut(&mut self.connected_pawn_mg),
+            from_mut(&mut self.candidate_passer_mg),
+            from_mut(&mut self.blocked_pawn_mg),
+            &mut self.material_eg,
+            &mut self.threat_eg,
+            &mut self.passed_pawn_eg,
+            from_mut(&mut self.castle_eg),
+            from_mut(&mut self.double_bishop_eg),
+            from_mut(&mut self.king_protection_eg),
+            from_mut(&mut self.capture_tempo_eg),
+            from_mut(&mut self.capture_choice_eg),
+            from_mut(&mut self.poisoned_piece_eg),
+            from_mut(&mut self.mobility_eg),
+            from_mut(&mut self.hanging_piece_eg),
+            from_mut(&mut self.isolated_pawn_eg),
+            from_mut(&mut self.doubled_pawn_eg),
+            from_mut(&mut self.backward_pawn_eg),
+            from_mut(&mut self.connected_pawn_eg),
+            from_mut(&mut self.candidate_passer_eg),
+            from_mut(&mut self.blocked_pawn_eg),
+            self.pawn_table_mg.as_flattened_mut(),
+            self.knight_table_mg.as_flattened_mut(),
+            self.bishop_table_mg.as_flattened_mut(),
+            self.rook_table_mg.as_flattened_mut(),
+            self.queen_table_mg.as_flattened_mut(),
+            self.king_table_mg.as_flattened_mut(),
+            self.pawn_table_eg.as_flattened_mut(),
+            self.knight_table_eg.as_flattened_mut(),
+            self.bishop_table_eg.as_flattened_mut(),
+            self.rook_table_eg.as_flattened_mut(),
+            self.queen_table_eg.as_flattened_mut(),
+            self.king_table_eg.as_flattened_mut(),
+        ]
+    }
+
+    /// Changes a single value. Names are either a plain field like `castle_mg`,
+    /// or a field and a piece or rank like `threat_mg.queen` or `passed_pawn_eg.6`.
+    /// The piece square tables can only be changed through a file.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid value {} for {}", value, name))?;
+
+        let (field, index) = match name.split_once('.') {
+            Some((field, index)) => (field, Some(index)),
+            None => (name, None),
+        };
+
+        let slot = match (field, index) {
+            ("castle_mg", None) => &mut self.castle_mg,
+            ("double_bishop_mg", None) => &mut self.double_bishop_mg,
+            ("king_protection_mg", None) => &mut self.king_protection_mg,
+            ("pst_scale", None) => &mut self.pst_scale,
+            ("capture_tempo_mg", None) => &mut self.capture_tempo_mg,
+            ("capture_choice_mg", None) => &mut self.capture_choice_mg,
+            ("poisoned_piece_mg", None) => &mut self.poisoned_piece_mg,
+            ("mobility_mg", None) => &mut self.mobility_mg,
+            ("hanging_piece_mg", None) => &mut self.hanging_piece_mg,
+            ("castle_eg", None) => &mut self.castle_eg,
+            ("double_bishop_eg", None) => &mut self.double_bishop_eg,
+            ("king_protection_eg", None) => &mut self.king_protection_eg,
+            ("capture_tempo_eg", None) => &mut self.capture_tempo_eg,
+            ("capture_choice_eg", None) => &mut self.capture_choice_eg,
+            ("poisoned_piece_eg", None) => &mut self.poisoned_piece_eg,
+            ("mobility_eg", None) => &mut self.mobility_eg,
+            ("hanging_piece_eg", None) => &mut self.hanging_piece_eg,
+            ("isolated_pawn_mg", None) => &mut self.isolated_pawn_mg,
+            ("doubled_pawn_mg", None) => &mut self.doubled_pawn_mg,
+            ("backward_pawn_mg", None) => &mut self.backward_pawn_mg,
+            ("connected_pawn_mg", None) => &mut self.connected_pawn_mg,
+            ("candidate_passer_mg", None) => &mut self.candidate_passer_mg,
+            ("blocked_pawn_mg", None) => &mut self.blocked_pawn_mg,
+            ("isolated_pawn_eg", None) => &mut self.isolated_pawn_eg,
+            ("doubled_pawn_eg", None) => &mut self.doubled_pawn_eg,
+            ("backward_pawn_eg", None) => &mut self.backward_pawn_eg,
+            ("connected_pawn_eg", None) => &mut self.connected_pawn_eg,
+            ("candidate_passer_eg", None) => &mut self.candidate_passer_eg,
+            ("blocked_pawn_eg", None) => &mut self.blocked_pawn_eg,
+            ("material_mg", Some(piece)) => &mut self.material_mg[piece_name_index(piece)?],
+            ("material_eg", Some(piece)) => &mut self.material_eg[piece_name_index(piece)?],
+            ("threat_mg", Some(piece)) => &mut self.threat_mg[piece_name_index(piece)?],
+            ("threat_eg", Some(piece)) => &mut self.threat_eg[piece_name_index(piece)?],
+            ("passed_pawn_mg", Some(rank)) => &mut self.passed_pawn_mg[rank_index(rank)?],
+            ("passed_pawn_eg", Some(rank)) => &mut self.passed_pawn_eg[rank_index(rank)?],
+            _ => bail!("unknown eval param {}", name),
+        };
+
+        *slot = value;
+
+        Ok(())
+    }
+}
+
+fn piece_name_index(name: &str) -> Result<usize> {
+    PIECE_NAMES
+        .iter()
+        .position(|p| *p == name)
+        .ok_or_else(|| anyhow!("unknown piece {}", name))
+}
+
+fn rank_index(rank: &str) -> Result<usize> {
+    rank.parse()
+        .ok()
+        .filter(|r| *r < 8)
+        .ok_or_else(|| anyhow!("invalid rank {}", rank))
+}
+
+fn is_json(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "json")
+}
+
+#[cfg(test)]
+mod eval_params_tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_set_params() {
+        let mut params = EvalParams::default();
+
+        params.set("material_mg.knight", "320").unwrap();
+        params.set("threat_mg.king", "900").unwrap();
+        params.set("passed_pawn_mg.6", "300.5").unwrap();
+        params.set("castle_mg", "0").unwrap();
+        params.set("material_eg.rook", "550").unwrap();
+
+        assert_eq!(params.material_mg[1], 320.0);
+        assert_eq!(params.threat_mg[5], 900.0);
+        assert_eq!(params.passed_pawn_mg[6], 300.5);
+        assert_eq!(params.castle_mg, 0.0);
+        assert_eq!(params.material_eg[3], 550.0);
+        assert_eq!(params.material_mg[3], 500.0);
+
+        assert!(params.set("material_mg.dragon", "1").is_err());
+        assert!(params.set("passed_pawn_mg.8", "1").is_err());
+        assert!(params.set("castle_mg", "lots").is_err());
+        assert!(params.set("space", "1").is_err());
+    }
+
+    #[test]
+    fn test_weights_mut() {
+        let mut params = EvalParams::default();
+
+        let weights = params.weights_mut();
+        assert_eq!(weights.len(), 2 * (6 + 6 + 8 + 14) + 1 + 12 * 64);
+        *weights.into_iter().next().unwrap() = 90.0;
+
+        assert_eq!(params.material_mg[0], 90.0);
+
+        // single weights by index agree with the list
+        let last = params.num_weights() - 1;
+        *params.weight_mut(last).unwrap() = 7.0;
+        assert_eq!(params.king_table_eg[7][7], 7.0);
+        *params.weight_mut(6).unwrap() = 1.5;
+        assert_eq!(params.threat_mg[0], 1.5);
+        assert_eq!(*params.weights_mut()[6], 1.5);
+        assert!(params.weight_mut(last + 1).is_none());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut params = EvalParams::default();
+        params.material_mg[4] = 1000.0;
+        params.knight_table_mg[3][3] = 6.0;
+
+        for name in ["eval_params_test.toml", "eval_params_test.json"] {
+            let path = env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            let path = path.to_str().unwrap();
+
+            params.save(path).unwrap();
+            let loaded = EvalParams::load(path).unwrap();
+            fs::remove_file(path).unwrap();
+
+            assert_eq!(loaded, params);
+        }
+    }
+
+    #[test]
+    fn test_partial_file_keeps_defaults() {
+        let params: EvalParams = toml::from_str("castle_mg = 5.0").unwrap();
+
+        assert_eq!(params.castle_mg, 5.0);
+        assert_eq!(params.castle_eg, EvalParams::default().castle_eg);
+        assert_eq!(params.material_mg, EvalParams::default().material_mg);
+    }
+
+    #[test]
+    fn test_unknown_names_rejected() {
+        for (name, content) in [
+            ("eval_params_typo.toml", "castel_mg = 1.0"),
+            ("eval_params_typo.json", r#"{"rook_tabel_mg": []}"#),
+        ] {
+            let path = env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            let path = path.to_str().unwrap();
+
+            fs::write(path, content).unwrap();
+            let loaded = EvalParams::load(path);
+            fs::remove_file(path).unwrap();
+
+            assert!(loaded.is_err(), "{}", name);
+        }
+    }
+}
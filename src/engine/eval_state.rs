@@ -0,0 +1,267 @@
+use crate::engine::eval_params::{piece_index, taper, EvalParams};
+use crate::engine::evaluate_game::{phase_from_material, phase_weight};
+use crate::engine::position_scores::piece_square_values;
+
+use pleco::{BitMove, Board, Piece, PieceType, Player, SQ};
+
+/// The pieces a move takes off the board and puts on it. No move touches
+/// more than two of either.
+pub struct PieceChanges {
+    pub removed: [Option<(Piece, SQ)>; 2],
+    pub added: [Option<(Piece, SQ)>; 2],
+}
+
+impl PieceChanges {
+    /// What playing `m` changes, `board` is the board before the move
+    pub fn of_move(board: &Board, m: BitMove) -> Self {
+        let src = m.get_src();
+        let dest = m.get_dest();
+        let piece = board.piece_at_sq(src);
+
+        if m.is_castle() {
+            // castling moves are stored as the king taking its own rook
+            let rank = src.0 & !7;
+            let (king_to, rook_to) = if m.is_king_castle() {
+                (rank + 6, rank + 5)
+            } else {
+                (rank + 2, rank + 3)
+            };
+            let rook = board.piece_at_sq(dest);
+
+            return PieceChanges {
+                removed: [Some((piece, src)), Some((rook, dest))],
+                added: [Some((piece, SQ(king_to))), Some((rook, SQ(rook_to)))],
+            };
+        }
+
+        let captured = if m.is_en_passant() {
+            // the pawn that gets taken is next to us, not on the square we go to
+            let captured_sq = SQ((src.0 & !7) | (dest.0 & 7));
+            Some((board.piece_at_sq(captured_sq), captured_sq))
+        } else if board.is_capture(m) {
+            Some((board.piece_at_sq(dest), dest))
+        } else {
+            None
+        };
+
+        let placed = if m.is_promo() {
+            Piece::make_lossy(board.turn(), m.promo_piece())
+        } else {
+            piece
+        };
+
+        PieceChanges {
+            removed: [Some((piece, src)), captured],
+            added: [Some((placed, dest)), None],
+        }
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = (Piece, SQ)> + '_ {
+        self.removed.iter().flatten().copied()
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = (Piece, SQ)> + '_ {
+        self.added.iter().flatten().copied()
+    }
+}
+
+/// The parts of the eval that only depend on which piece is on which square:
+/// material, piece square tables, game phase and the pawn structure. The
+/// search updates this move by move instead of going over every piece at
+/// every leaf, see `evaluate_with_state`.
+///
+/// The sums are kept in fixed point, so they come out exactly the same
+/// whatever order the pieces were added and removed in, and the incremental
+/// eval gives the same score as `evaluate` down to the last bit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalState {
+    /// material and piece square tables with the middlegame weights, white
+    /// minus black, in units of `1 / FIXED_ONE`
+    pub mg: i64,
+    /// the same with the endgame weights
+    pub eg: i64,
+    /// sum of `phase_weight` over every piece on the board
+    pub phase_material: u32,
+    /// hash of where the pawns are, see `pawn_key`
+    pub pawn_key: u64,
+}
+
+/// 1.0 in the fixed point sums of `EvalState`
+const FIXED_ONE: f64 = (1u64 << 32) as f64;
+
+impl EvalState {
+    /// Builds the state for a board from scratch
+    pub fn new(board: &Board, params: &EvalParams) -> Self {
+        let mut state = EvalState {
+            mg: 0,
+            eg: 0,
+            phase_material: 0,
+            pawn_key: 0,
+        };
+
+        for (sq, piece) in board.get_piece_locations() {
+            if piece != Piece::None {
+                state.add(piece, sq, params);
+            }
+        }
+
+        state
+    }
+
+    /// The state after `m` is played, `board` is the board before the move
+    pub fn update(&self, board: &Board, m: BitMove, params: &EvalParams) -> Self {
+        let mut state = self.clone();
+        let changes = PieceChanges::of_move(board, m);
+
+        for (piece, sq) in changes.removed() {
+            state.remove(piece, sq, params);
+        }
+        for (piece, sq) in changes.added() {
+            state.add(piece, sq, params);
+        }
+
+        state
+    }
+
+    pub fn phase(&self) -> f64 {
+        phase_from_material(self.phase_material)
+    }
+
+    /// material and piece square tables blended for the current phase,
+    /// from white's point of view
+    pub fn score(&self) -> f64 {
+        taper(
+            self.mg as f64 / FIXED_ONE,
+            self.eg as f64 / FIXED_ONE,
+            self.phase(),
+        )
+    }
+
+    fn add(&mut self, piece: Piece, sq: SQ, params: &EvalParams) {
+        let (mg, eg) = piece_values(piece, sq, params);
+        self.mg += mg;
+        self.eg += eg;
+        self.phase_material += phase_weight(piece.type_of());
+        self.pawn_key ^= pawn_key(piece, sq);
+    }
+
+    fn remove(&mut self, piece: Piece, sq: SQ, params: &EvalParams) {
+        let (mg, eg) = piece_values(piece, sq, params);
+        self.mg -= mg;
+        self.eg -= eg;
+        self.phase_material -= phase_weight(piece.type_of());
+        self.pawn_key ^= pawn_key(piece, sq);
+    }
+}
+
+/// material plus piece square values of a piece in fixed point, negative for
+/// black
+fn piece_values(piece: Piece, sq: SQ, params: &EvalParams) -> (i64, i64) {
+    let (player, piece_type) = piece.player_piece_lossy();
+
+    let (material_mg, material_eg) = match piece_index(piece_type) {
+        Some(ind) => (params.material_mg[ind], params.material_eg[ind]),
+        None => (0.0, 0.0),
+    };
+    let (pst_mg, pst_eg) = piece_square_values(piece_type, sq, player, params);
+
+    let fixed = |value: f64| (value * FIXED_ONE).round() as i64;
+    let sign = if player == Player::White { 1 } else { -1 };
+
+    (
+        sign * fixed(material_mg + pst_mg),
+        sign * fixed(material_eg + pst_eg),
+    )
+}
+
+/// The hash of a pawn on a square, 0 for every other piece. The pawn key of a
+/// board is all of these xored together.
+pub fn pawn_key(piece: Piece, sq: SQ) -> u64 {
+    let (player, piece_type) = piece.player_piece_lossy();
+    if piece_type != PieceType::P {
+        return 0;
+    }
+
+    PAWN_KEYS[player as usize * 64 + sq.0 as usize]
+}
+
+const PAWN_KEYS: [u64; 128] = pawn_keys();
+
+/// splitmix64, so the keys are the same on every run without a random crate
+const fn pawn_keys() -> [u64; 128] {
+    let mut keys = [0u64; 128];
+    let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+
+    let mut ind = 0;
+    while ind < 128 {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[ind] = z ^ (z >> 31);
+        ind += 1;
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod eval_state_tests {
+    use super::*;
+
+    #[test]
+    fn test_incremental_matches_scratch() {
+        let params = EvalParams::default();
+
+        // castling both ways, en passant and a promotion with capture
+        for (fen, moves) in [
+            (
+                "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1",
+                ["e1g1", "e8c8", "d2d4"].as_slice(),
+            ),
+            (
+                "4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1",
+                ["e2e4", "d4e3"].as_slice(),
+            ),
+            ("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", ["a7b8q"].as_slice()),
+        ] {
+            let mut board = Board::from_fen(fen).unwrap();
+            let mut state = EvalState::new(&board, &params);
+
+            for text in moves {
+                let m = board
+                    .generate_moves()
+                    .into_iter()
+                    .find(|m| m.stringify() == *text)
+                    .unwrap();
+
+                state = state.update(&board, m, &params);
+                board.apply_move(m);
+
+                assert_eq!(
+                    state,
+                    EvalState::new(&board, &params),
+                    "{} after {}",
+                    board.fen(),
+                    text
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_pawn_key() {
+        let params = EvalParams::default();
+
+        // the pieces are different, the pawns are the same
+        let a = Board::from_fen("4k3/pp6/8/8/8/8/PP6/R3K3 w - - 0 1").unwrap();
+        let b = Board::from_fen("4k3/pp6/8/8/8/8/PP6/4K2N w - - 0 1").unwrap();
+        let c = Board::from_fen("4k3/pp6/8/8/8/P7/1P6/R3K3 w - - 0 1").unwrap();
+
+        let key = |board: &Board| EvalState::new(board, &params).pawn_key;
+
+        assert_eq!(key(&a), key(&b));
+        assert_ne!(key(&a), key(&c));
+        assert_ne!(key(&a), 0);
+    }
+}
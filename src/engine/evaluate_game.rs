@@ -1,129 +1,530 @@
+use crate::engine::eval_params::{piece_index, taper, EvalParams};
+use crate::engine::eval_state::EvalState;
+use crate::engine::pawns::{pawn_structure, PawnStructure, PawnTable};
 use crate::engine::position_scores::*;
+use crate::engine::see::see_capture;
 
 use pleco::{core::CastleType, Board, Piece, PieceType, Player, SQ};
 
+use std::fmt;
+
 /// This will evaluate the game state of the board and return a score
 /// This returns a score from the point of view of the white player. We only
 /// need to return a single value since this is a zero sum game. So, a
 /// positive score is good for white, and a negative score is good for black.
 /// White is maximizing, black is minimizing.
-pub fn evaluate(board: &Board) -> f64 {
+pub fn evaluate(board: &Board, params: &EvalParams) -> f64 {
+    match game_over_score(board) {
+        Some(score) => score,
+        None => evaluate_from_state(
+            board,
+            &EvalState::new(board, params),
+            &pawn_structure(board),
+            params,
+        ),
+    }
+}
+
+/// Every term of the eval, split up by side. Each side's value is from its
+/// own point of view, so the total is the sum of white's terms minus the sum
+/// of black's.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EvalTrace {
+    /// `[white, black]` for each of the terms
+    pub material: [f64; 2],
+    /// piece square tables, in pawn, knight, bishop, rook, queen, king order
+    pub piece_square: [[f64; 2]; 6],
+    pub threats: [f64; 2],
+    pub passed_pawns: [f64; 2],
+    pub castling: [f64; 2],
+    pub king_protection: [f64; 2],
+    pub double_bishop: [f64; 2],
+    pub capture_tempo: [f64; 2],
+    pub capture_choice: [f64; 2],
+    pub poisoned_pieces: [f64; 2],
+    pub mobility: [f64; 2],
+    pub hanging_pieces: [f64; 2],
+    pub isolated_pawns: [f64; 2],
+    pub doubled_pawns: [f64; 2],
+    pub backward_pawns: [f64; 2],
+    pub connected_pawns: [f64; 2],
+    pub candidate_passers: [f64; 2],
+    pub blocked_pawns: [f64; 2],
+    /// how far the game is from the endgame, see `game_phase`
+    pub phase: f64,
+    /// the same value `evaluate` gives. When the game is over this is the
+    /// result and every term is left at 0.
+    pub total: f64,
+}
+
+/// Same as `evaluate`, but keeps track of what every term added up to
+pub fn evaluate_trace(board: &Board, params: &EvalParams) -> EvalTrace {
+    let mut trace = EvalTrace::default();
+
+    if let Some(score) = game_over_score(board) {
+        trace.total = score;
+        return trace;
+    }
+
+    let phase = game_phase(board);
+    trace.phase = phase;
+
+    for (sq, piece) in board.get_piece_locations() {
+        let side = match piece.player() {
+            Some(Player::White) => 0,
+            Some(Player::Black) => 1,
+            None => continue,
+        };
+
+        trace.material[side] += evaluate_material(&piece.type_of(), phase, params);
+        if let Some(ind) = piece_index(piece.type_of()) {
+            trace.piece_square[ind][side] += evaluate_piece_pos(&piece, sq, phase, params);
+        }
+    }
+
+    evaluate_dynamic_terms(board, phase, params, &mut trace);
+    evaluate_pawn_structure(&pawn_structure(board), phase, params, &mut trace);
+
+    // material and piece square tables are only added up per side above,
+    // the total goes through `EvalState` like the search's eval does
+    trace.total = EvalState::new(board, params).score() + trace.dynamic_sum();
+
+    trace
+}
+
+/// The same as `evaluate`, but takes material, piece square tables and the
+/// game phase from an incrementally updated `EvalState` instead of going over
+/// every piece for them. The pawn structure comes out of `pawn_table` when it
+/// can. Both go through `evaluate_from_state`, so as long as the state is
+/// the one `EvalState::new` builds (checked in debug builds) the score is the
+/// same as `evaluate`'s down to the last bit.
+pub fn evaluate_with_state(
+    board: &Board,
+    state: &EvalState,
+    params: &EvalParams,
+    pawn_table: &mut PawnTable,
+) -> f64 {
+    debug_assert_eq!(
+        *state,
+        EvalState::new(board, params),
+        "incremental eval state is out of sync at {}",
+        board.fen()
+    );
+
+    if let Some(score) = game_over_score(board) {
+        return score;
+    }
+
+    let structure = pawn_table.probe(board, state.pawn_key);
+    evaluate_from_state(board, state, &structure, params)
+}
+
+/// the eval of a game that isn't over, for the board's state and pawn structure
+fn evaluate_from_state(
+    board: &Board,
+    state: &EvalState,
+    structure: &PawnStructure,
+    params: &EvalParams,
+) -> f64 {
+    let mut trace = EvalTrace::default();
+    evaluate_dynamic_terms(board, state.phase(), params, &mut trace);
+    evaluate_pawn_structure(structure, state.phase(), params, &mut trace);
+
+    state.score() + trace.dynamic_sum()
+}
+
+/// the score of a game that is already decided, if it is
+fn game_over_score(board: &Board) -> Option<f64> {
     if board.checkmate() {
         if board.turn() == Player::White {
-            return f64::NEG_INFINITY;
+            return Some(f64::NEG_INFINITY);
         } else {
-            return f64::INFINITY;
+            return Some(f64::INFINITY);
         }
     }
 
     if board.stalemate() && board.rule_50() >= 50 {
-        return 0.0;
+        return Some(0.0);
     }
 
-    let mut score = 0.0;
+    None
+}
 
+/// Every term that depends on more than where a single piece is, so can't be
+/// kept up to date move by move
+fn evaluate_dynamic_terms(board: &Board, phase: f64, params: &EvalParams, trace: &mut EvalTrace) {
     for (sq, piece) in board.get_piece_locations() {
-        if piece == Piece::None {
-            continue;
-        }
+        let side = match piece.player() {
+            Some(Player::White) => 0,
+            Some(Player::Black) => 1,
+            None => continue,
+        };
+
+        trace.threats[side] += evaluate_threats(board, &piece, sq, phase, params);
+        trace.passed_pawns[side] += evaluate_passed_pawn(board, &piece, sq, phase, params);
+    }
+
+    trace.castling = per_player(|p| evaluate_castle(board, p, phase, params));
+    trace.king_protection = per_player(|p| evaluate_king_protection(board, p, phase, params));
+    trace.double_bishop = per_player(|p| evaluate_double_bishop(board, p, phase, params));
 
-        let piece_score = evaluate_material(&piece.type_of())
-            + evaluate_piece_pos(&piece, sq, board.ply() as i64)
-            + evaluate_threats(board, &piece, sq)
-            + evaluate_passed_pawn(board, &piece, sq);
+    evaluate_variant_terms(board, phase, params, trace);
+}
+
+/// The pawn structure counts times their weights. The counts come from
+/// `pawn_structure`, or the pawn table during search.
+fn evaluate_pawn_structure(
+    structure: &PawnStructure,
+    phase: f64,
+    params: &EvalParams,
+    trace: &mut EvalTrace,
+) {
+    let term = |counts: [u32; 2], mg: f64, eg: f64| {
+        let weight = taper(mg, eg, phase);
+        [counts[0] as f64 * weight, counts[1] as f64 * weight]
+    };
 
-        if piece.player() == Some(Player::White) {
-            score += piece_score;
-        } else if piece.player() == Some(Player::Black) {
-            score -= piece_score;
+    trace.isolated_pawns = term(
+        structure.isolated,
+        params.isolated_pawn_mg,
+        params.isolated_pawn_eg,
+    );
+    trace.doubled_pawns = term(
+        structure.doubled,
+        params.doubled_pawn_mg,
+        params.doubled_pawn_eg,
+    );
+    trace.backward_pawns = term(
+        structure.backward,
+        params.backward_pawn_mg,
+        params.backward_pawn_eg,
+    );
+    trace.connected_pawns = term(
+        structure.connected,
+        params.connected_pawn_mg,
+        params.connected_pawn_eg,
+    );
+    trace.candidate_passers = term(
+        structure.candidate_passers,
+        params.candidate_passer_mg,
+        params.candidate_passer_eg,
+    );
+    trace.blocked_pawns = term(
+        structure.blocked,
+        params.blocked_pawn_mg,
+        params.blocked_pawn_eg,
+    );
+}
+
+impl EvalTrace {
+    /// every term but material and piece square tables added up, white
+    /// minus black. Those two come from `EvalState`, the ones in the trace
+    /// are only there to show what they add up to for each side.
+    fn dynamic_sum(&self) -> f64 {
+        let mut score = 0.0;
+
+        for term in [
+            &self.threats,
+            &self.passed_pawns,
+            &self.castling,
+            &self.king_protection,
+            &self.double_bishop,
+            &self.capture_tempo,
+            &self.capture_choice,
+            &self.poisoned_pieces,
+            &self.mobility,
+            &self.hanging_pieces,
+            &self.isolated_pawns,
+            &self.doubled_pawns,
+            &self.backward_pawns,
+            &self.connected_pawns,
+            &self.candidate_passers,
+            &self.blocked_pawns,
+        ] {
+            score += term[0] - term[1];
         }
+
+        score
     }
+}
 
-    score += evaluate_castle(board);
-    score += evaluate_king_protection(board);
-    score += evaluate_double_bishop(board);
+/// How much of the game is left before the endgame, from 1.0 with all the
+/// pieces on the board down to 0.0 when only kings and pawns are left. This
+/// goes by material and not by move number, since antichess games often trade
+/// down very quickly.
+pub fn game_phase(board: &Board) -> f64 {
+    let mut material = 0;
 
-    score
+    for piece_type in [PieceType::N, PieceType::B, PieceType::R, PieceType::Q] {
+        material +=
+            board.piece_bb_both_players(piece_type).count_bits() as u32 * phase_weight(piece_type);
+    }
+
+    phase_from_material(material)
 }
 
-fn evaluate_king_protection(board: &Board) -> f64 {
-    fn eval_player_king_prot(board: &Board, player: Player) -> f64 {
-        let squares_near_king = board.piece_bb(player, PieceType::K);
-        let player_squares = board.get_occupied_player(player);
+/// how much a piece counts towards the game phase
+pub fn phase_weight(piece_type: PieceType) -> u32 {
+    match piece_type {
+        PieceType::N | PieceType::B => 1,
+        PieceType::R => 2,
+        PieceType::Q => 4,
+        _ => 0,
+    }
+}
 
-        let occupised_squares_near_king = squares_near_king & player_squares;
+/// the phase for the summed up `phase_weight` of every piece on the board
+pub fn phase_from_material(material: u32) -> f64 {
+    // promotions can push it past the starting material
+    material.min(TOTAL_PHASE) as f64 / TOTAL_PHASE as f64
+}
+
+/// the phase material of the starting position
+const TOTAL_PHASE: u32 = 24;
+
+fn per_player(eval: impl Fn(Player) -> f64) -> [f64; 2] {
+    [eval(Player::White), eval(Player::Black)]
+}
 
-        let num_occupised_squares_near_king = occupised_squares_near_king.count_bits();
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
 
-        num_occupised_squares_near_king as f64 * 10.0
+        let mut rows = vec![("material".to_string(), self.material)];
+        for (name, term) in PIECE_NAMES.iter().zip(self.piece_square.iter()) {
+            rows.push((format!("pst {}", name), *term));
+        }
+        rows.push(("threats".to_string(), self.threats));
+        rows.push(("passed pawns".to_string(), self.passed_pawns));
+        rows.push(("castling".to_string(), self.castling));
+        rows.push(("king protection".to_string(), self.king_protection));
+        rows.push(("double bishop".to_string(), self.double_bishop));
+        rows.push(("capture tempo".to_string(), self.capture_tempo));
+        rows.push(("capture choice".to_string(), self.capture_choice));
+        rows.push(("poisoned pieces".to_string(), self.poisoned_pieces));
+        rows.push(("mobility".to_string(), self.mobility));
+        rows.push(("hanging pieces".to_string(), self.hanging_pieces));
+        rows.push(("isolated pawns".to_string(), self.isolated_pawns));
+        rows.push(("doubled pawns".to_string(), self.doubled_pawns));
+        rows.push(("backward pawns".to_string(), self.backward_pawns));
+        rows.push(("connected pawns".to_string(), self.connected_pawns));
+        rows.push(("candidate passers".to_string(), self.candidate_passers));
+        rows.push(("blocked pawns".to_string(), self.blocked_pawns));
+
+        writeln!(
+            f,
+            "{:<16} {:>9} {:>9} {:>9}",
+            "term", "white", "black", "diff"
+        )?;
+        for (name, [white, black]) in rows {
+            writeln!(
+                f,
+                "{:<16} {:>9.2} {:>9.2} {:>9.2}",
+                name,
+                white,
+                black,
+                white - black
+            )?;
+        }
+        writeln!(f, "{:<16} {:>29.2}", "phase", self.phase)?;
+        write!(f, "{:<16} {:>29.2}", "total", self.total)
     }
+}
+
+fn evaluate_king_protection(board: &Board, player: Player, phase: f64, params: &EvalParams) -> f64 {
+    let squares_near_king = board.piece_bb(player, PieceType::K);
+    let player_squares = board.get_occupied_player(player);
 
-    let white_king_prot = eval_player_king_prot(board, Player::White);
-    let black_king_prot = eval_player_king_prot(board, Player::Black);
+    let occupised_squares_near_king = squares_near_king & player_squares;
 
-    white_king_prot - black_king_prot
+    let num_occupised_squares_near_king = occupised_squares_near_king.count_bits();
+
+    num_occupised_squares_near_king as f64
+        * taper(params.king_protection_mg, params.king_protection_eg, phase)
 }
 
-fn evaluate_castle(board: &Board) -> f64 {
+fn evaluate_castle(board: &Board, player: Player, phase: f64, params: &EvalParams) -> f64 {
+    let castle = taper(params.castle_mg, params.castle_eg, phase);
     let mut score = 0.0;
 
-    if board.can_castle(Player::White, CastleType::KingSide) {
-        score += 15.0;
+    if board.can_castle(player, CastleType::KingSide) {
+        score += castle;
+    }
+
+    if board.can_castle(player, CastleType::QueenSide) {
+        score += castle;
     }
 
-    if board.can_castle(Player::White, CastleType::QueenSide) {
-        score += 15.0;
+    score
+}
+
+fn evaluate_double_bishop(board: &Board, player: Player, phase: f64, params: &EvalParams) -> f64 {
+    if board.piece_bb(player, PieceType::B).more_than_one() {
+        taper(params.double_bishop_mg, params.double_bishop_eg, phase)
+    } else {
+        0.0
     }
+}
 
-    if board.can_castle(Player::Black, CastleType::KingSide) {
-        score -= 15.0;
+/// The terms that only make sense because captures are forced. They are all
+/// off (weight 0) by default, and skipped entirely while they are off.
+fn evaluate_variant_terms(board: &Board, phase: f64, params: &EvalParams, trace: &mut EvalTrace) {
+    let capture_tempo = taper(params.capture_tempo_mg, params.capture_tempo_eg, phase);
+    let capture_choice = taper(params.capture_choice_mg, params.capture_choice_eg, phase);
+    let poisoned_piece = taper(params.poisoned_piece_mg, params.poisoned_piece_eg, phase);
+    let mobility = taper(params.mobility_mg, params.mobility_eg, phase);
+    let hanging_piece = taper(params.hanging_piece_mg, params.hanging_piece_eg, phase);
+
+    if capture_tempo == 0.0
+        && capture_choice == 0.0
+        && poisoned_piece == 0.0
+        && mobility == 0.0
+        && hanging_piece == 0.0
+    {
+        return;
     }
 
-    if board.can_castle(Player::Black, CastleType::QueenSide) {
-        score -= 15.0;
+    let captures = per_player(|p| capture_count(board, p) as f64);
+
+    // having to capture means the side to move gives up its tempo to the other side
+    let to_move = board.turn() as usize;
+    if captures[to_move] > 0.0 {
+        trace.capture_tempo[1 - to_move] = capture_tempo;
     }
 
-    score
+    trace.capture_choice = [captures[0] * capture_choice, captures[1] * capture_choice];
+
+    if poisoned_piece != 0.0 {
+        trace.poisoned_pieces = per_player(|p| poisoned_count(board, p) as f64 * poisoned_piece);
+    }
+
+    if mobility != 0.0 {
+        // when a side has a capture it can only make captures
+        let mut moves = per_player(|p| quiet_count(board, p) as f64);
+        for side in 0..2 {
+            if captures[side] > 0.0 {
+                moves[side] = captures[side];
+            }
+        }
+
+        trace.mobility = [moves[0] * mobility, moves[1] * mobility];
+    }
+
+    if hanging_piece != 0.0 {
+        trace.hanging_pieces = per_player(|p| hanging_count(board, p) as f64 * hanging_piece);
+    }
 }
 
-fn evaluate_double_bishop(board: &Board) -> f64 {
-    let mut score = 0.0;
+/// How many captures the player could make if it was their move. This works
+/// off the attack tables, so it ignores pins and en passant. Attacking the
+/// king isn't a capture.
+fn capture_count(board: &Board, player: Player) -> u32 {
+    let enemy = player.other_player();
+    let targets = board.get_occupied_player(enemy) & !board.piece_bb(enemy, PieceType::K);
+
+    board
+        .get_occupied_player(player)
+        .map(|sq| {
+            let piece_type = board.piece_at_sq(sq).type_of();
+            (board.attacks_from(piece_type, sq, player) & targets).count_bits() as u32
+        })
+        .sum()
+}
+
+/// How many moves to empty squares the player has, pawn pushes included
+fn quiet_count(board: &Board, player: Player) -> u32 {
+    let empty = !board.occupied();
+
+    let piece_moves: u32 = (board.get_occupied_player(player)
+        & !board.piece_bb(player, PieceType::P))
+    .map(|sq| {
+        let piece_type = board.piece_at_sq(sq).type_of();
+        (board.attacks_from(piece_type, sq, player) & empty).count_bits() as u32
+    })
+    .sum();
+
+    // a double push needs the square in between to be empty too
+    let pawns = board.piece_bb(player, PieceType::P).0;
+    let (single, double) = match player {
+        Player::White => {
+            let single = (pawns << 8) & empty.0;
+            (single, ((single & RANK_3) << 8) & empty.0)
+        }
+        Player::Black => {
+            let single = (pawns >> 8) & empty.0;
+            (single, ((single & RANK_6) >> 8) & empty.0)
+        }
+    };
+
+    piece_moves + single.count_ones() + double.count_ones()
+}
+
+const RANK_3: u64 = 0xFF << 16;
+const RANK_6: u64 = 0xFF << 40;
+
+/// A piece is poisoned when the other side can take it, but every capture of
+/// it loses material once the forced recaptures are played out (see `see`)
+fn poisoned_count(board: &Board, player: Player) -> u32 {
+    let occupied = board.occupied();
+    let ours = board.get_occupied_player(player);
+    let theirs = board.get_occupied_player(player.other_player());
+    let their_king = board.piece_bb(player.other_player(), PieceType::K);
+
+    let mut count = 0;
+
+    for sq in ours & !board.piece_bb(player, PieceType::K) {
+        let attackers = board.attackers_to(sq, occupied);
 
-    if board.piece_bb(Player::White, PieceType::B).more_than_one() {
-        score += 50.0;
+        // the king can't take a defended piece
+        let mut takers = attackers & theirs;
+        if (attackers & ours).is_not_empty() {
+            takers &= !their_king;
+        }
+
+        if takers.is_not_empty() && takers.all(|a| see_capture(board, a, sq) < 0.0) {
+            count += 1;
+        }
     }
 
-    if board.piece_bb(Player::Black, PieceType::B).more_than_one() {
-        score -= 50.0;
+    count
+}
+
+/// How many of the player's pieces the opponent would win material by taking,
+/// counting the recaptures that follow
+fn hanging_count(board: &Board, player: Player) -> u32 {
+    let occupied = board.occupied();
+    let theirs = board.get_occupied_player(player.other_player());
+
+    let mut count = 0;
+
+    for sq in board.get_occupied_player(player) & !board.piece_bb(player, PieceType::K) {
+        if (board.attackers_to(sq, occupied) & theirs).any(|a| see_capture(board, a, sq) > 0.0) {
+            count += 1;
+        }
     }
 
-    score
+    count
 }
 
 /// following regular piece values
-fn evaluate_material(piece: &PieceType) -> f64 {
-    match piece {
-        PieceType::P => 100.0,
-        PieceType::N => 300.0,
-        PieceType::B => 300.0,
-        PieceType::R => 500.0,
-        PieceType::Q => 900.0,
-        PieceType::K => 0.0,
-        _ => 0.0,
+fn evaluate_material(piece: &PieceType, phase: f64, params: &EvalParams) -> f64 {
+    match piece_index(*piece) {
+        Some(ind) => taper(params.material_mg[ind], params.material_eg[ind], phase),
+        None => 0.0,
     }
 }
 
 /// evalutes the positions of the knights
 /// The closer to the middle the knight is, the better
-fn evaluate_piece_pos(piece: &Piece, sq: SQ, turns: i64) -> f64 {
+fn evaluate_piece_pos(piece: &Piece, sq: SQ, phase: f64, params: &EvalParams) -> f64 {
     if let Some(p) = piece.player() {
         match piece.type_of() {
-            PieceType::P => pawn_position_score(sq, p),
-            PieceType::N => knight_position_score(sq, p),
-            PieceType::B => bishop_position_score(sq, p),
-            PieceType::R => rook_position_score(sq, p, turns),
-            PieceType::Q => queen_position_score(sq, p, turns),
-            PieceType::K => king_position_score(sq, p, turns),
+            PieceType::P => pawn_position_score(sq, p, phase, params),
+            PieceType::N => knight_position_score(sq, p, phase, params),
+            PieceType::B => bishop_position_score(sq, p, phase, params),
+            PieceType::R => rook_position_score(sq, p, phase, params),
+            PieceType::Q => queen_position_score(sq, p, phase, params),
+            PieceType::K => king_position_score(sq, p, phase, params),
             _ => 0.0,
         }
     } else {
@@ -132,16 +533,11 @@ fn evaluate_piece_pos(piece: &Piece, sq: SQ, turns: i64) -> f64 {
 }
 
 /// evaluates threats to either side
-fn evaluate_threats(game: &Board, piece: &Piece, pos: SQ) -> f64 {
-    fn threat_score_calc(piece_type: PieceType) -> f64 {
-        match piece_type {
-            PieceType::P => 50.0,
-            PieceType::N => 160.0,
-            PieceType::B => 170.0,
-            PieceType::R => 250.0,
-            PieceType::Q => 450.0,
-            PieceType::K => 1000.0,
-            _ => 0.0,
+fn evaluate_threats(game: &Board, piece: &Piece, pos: SQ, phase: f64, params: &EvalParams) -> f64 {
+    fn threat_score_calc(piece_type: PieceType, phase: f64, params: &EvalParams) -> f64 {
+        match piece_index(piece_type) {
+            Some(ind) => taper(params.threat_mg[ind], params.threat_eg[ind], phase),
+            None => 0.0,
         }
     }
 
@@ -171,7 +567,8 @@ fn evaluate_threats(game: &Board, piece: &Piece, pos: SQ) -> f64 {
         let piece_threat_count = piece_threat_bb.count_bits();
 
         // support is not nearly as useful, since it'll probs def be dead anyways
-        let piece_threat_score = piece_threat_count as f64 * threat_score_calc(piece_type);
+        let piece_threat_score =
+            piece_threat_count as f64 * threat_score_calc(piece_type, phase, params);
 
         piece_score += piece_threat_score;
     }
@@ -179,15 +576,18 @@ fn evaluate_threats(game: &Board, piece: &Piece, pos: SQ) -> f64 {
     piece_score
 }
 
-fn evaluate_passed_pawn(game: &Board, piece: &Piece, sq: SQ) -> f64 {
+fn evaluate_passed_pawn(
+    game: &Board,
+    piece: &Piece,
+    sq: SQ,
+    phase: f64,
+    params: &EvalParams,
+) -> f64 {
     let piece_player = match piece.player() {
         Some(p) => p,
         None => return 0.0,
     };
 
-    // array of scores based on rank of piece
-    let passed_pawn_scores = vec![0.0, 17.0, 20.0, 35.0, 70.0, 175.0, 275.0, 0.0];
-
     if game.pawn_passed(piece_player, sq) {
         let rank_from_player_pov = if piece_player == Player::White {
             sq.rank_idx_of_sq()
@@ -195,7 +595,12 @@ fn evaluate_passed_pawn(game: &Board, piece: &Piece, sq: SQ) -> f64 {
             7 - sq.rank_idx_of_sq()
         };
 
-        passed_pawn_scores[rank_from_player_pov as usize]
+        let rank = rank_from_player_pov as usize;
+        taper(
+            params.passed_pawn_mg[rank],
+            params.passed_pawn_eg[rank],
+            phase,
+        )
     } else {
         0.0
     }
@@ -208,13 +613,150 @@ mod evaluate_tests {
     #[test]
     fn test_starting_eval() {
         let game = Board::start_pos();
-        let score = evaluate(&game);
+        let score = evaluate(&game, &EvalParams::default());
         assert!(score.abs() < 1.0);
     }
 
+    #[test]
+    fn test_eval_trace() {
+        let game = Board::from_fen("6k1/4QNpp/2p5/7P/8/6n1/3KP3/2B3BR b - - 0 1").unwrap();
+        let params = EvalParams::default();
+
+        let trace = evaluate_trace(&game, &params);
+        assert_eq!(trace.total, evaluate(&game, &params));
+        assert_eq!(trace.material, [2500.0, 600.0]);
+        assert_eq!(trace.double_bishop, [50.0, 0.0]);
+
+        let mut terms = vec![
+            trace.material,
+            trace.threats,
+            trace.passed_pawns,
+            trace.castling,
+            trace.king_protection,
+            trace.double_bishop,
+            trace.isolated_pawns,
+            trace.doubled_pawns,
+            trace.backward_pawns,
+            trace.connected_pawns,
+            trace.candidate_passers,
+            trace.blocked_pawns,
+        ];
+        terms.extend(trace.piece_square);
+        let sum: f64 = terms.iter().map(|[white, black]| white - black).sum();
+        assert!((sum - trace.total).abs() < 1e-6);
+
+        assert!(trace.to_string().contains("pst knight"));
+    }
+
+    #[test]
+    fn test_incremental_eval_is_exact() {
+        let params = EvalParams::default();
+        let mut pawn_table = PawnTable::new();
+
+        for start in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/pp1n1ppp/2p5/3p4/3P4/2N2N2/PPP2PPP/R3K2R w KQkq - 0 1",
+        ] {
+            let mut board = Board::from_fen(start).unwrap();
+            let mut state = EvalState::new(&board, &params);
+
+            // the same made up game every run, until it ends
+            for ply in 0..120 {
+                let score = evaluate_with_state(&board, &state, &params, &mut pawn_table);
+                assert_eq!(
+                    score.to_bits(),
+                    evaluate(&board, &params).to_bits(),
+                    "{}",
+                    board.fen()
+                );
+
+                let moves = crate::engine::valid_moves(&board);
+                if moves.is_empty() {
+                    break;
+                }
+                let m = moves[(ply * 7 + 3) % moves.len()];
+
+                state = state.update(&board, m, &params);
+                board.apply_move(m);
+            }
+        }
+    }
+
+    #[test]
+    fn test_game_phase() {
+        assert_eq!(game_phase(&Board::start_pos()), 1.0);
+
+        // only a rook each left, so it's an endgame no matter how early it is
+        let game = Board::from_fen("4k3/pppr4/8/8/8/8/PPPR4/4K3 w - - 0 10").unwrap();
+        assert!((game_phase(&game) - 4.0 / 24.0).abs() < 1e-9);
+
+        let mut params = EvalParams::default();
+        params.material_eg[0] = 200.0;
+        let trace = evaluate_trace(&game, &params);
+        let pawn = 100.0 * trace.phase + 200.0 * (1.0 - trace.phase);
+        assert!((trace.material[0] - (3.0 * pawn + 500.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_variant_terms() {
+        // white has to take, with exd5, exf5 or Qxd5, and black could only take dxe4
+        let game = Board::from_fen("7k/8/4p3/3p1n2/4P3/8/3Q4/7K w - - 0 1").unwrap();
+
+        let mut params = EvalParams::default();
+        assert_eq!(evaluate_trace(&game, &params).capture_choice, [0.0, 0.0]);
+
+        // only a queen on the board, so set the endgame weights too
+        params.capture_tempo_mg = 20.0;
+        params.capture_tempo_eg = 20.0;
+        params.capture_choice_mg = 5.0;
+        params.capture_choice_eg = 5.0;
+        params.poisoned_piece_mg = 30.0;
+        params.poisoned_piece_eg = 30.0;
+        params.mobility_mg = 2.0;
+        params.mobility_eg = 2.0;
+
+        let trace = evaluate_trace(&game, &params);
+        assert_eq!(trace.capture_tempo, [0.0, 20.0]);
+        assert_eq!(trace.capture_choice, [15.0, 5.0]);
+        assert_eq!(trace.mobility, [6.0, 2.0]);
+
+        // the d5 pawn is defended by e6, and only the queen can take it
+        let trace = evaluate_trace(
+            &Board::from_fen("7k/8/4p3/3p4/8/8/3Q4/7K w - - 0 1").unwrap(),
+            &params,
+        );
+        assert_eq!(trace.poisoned_pieces, [0.0, 30.0]);
+
+        // Nxd5 Qxd5 Rxd5 wins the queen, so d5 isn't poisoned even though
+        // both of its attackers are worth more
+        let trace = evaluate_trace(
+            &Board::from_fen("3q3k/8/8/3p4/8/2N5/8/3R3K w - - 0 1").unwrap(),
+            &params,
+        );
+        assert_eq!(trace.poisoned_pieces, [0.0, 0.0]);
+
+        // attacking the king isn't a capture, and pawn pushes are moves
+        let trace = evaluate_trace(
+            &Board::from_fen("7k/8/8/8/8/8/P7/K6R b - - 0 1").unwrap(),
+            &params,
+        );
+        assert_eq!(trace.capture_choice, [0.0, 0.0]);
+        assert_eq!(trace.mobility, [32.0, 6.0]);
+
+        // dxe4 wins a pawn, exd5 wins one back after exd5 Qxd5, and exf5 wins
+        // the knight for a pawn
+        params.hanging_piece_mg = -25.0;
+        params.hanging_piece_eg = -25.0;
+        assert_eq!(
+            evaluate_trace(&game, &params).hanging_pieces,
+            [-25.0, -50.0]
+        );
+    }
+
     #[test]
     fn test_start_material_eval_even() {
         let game = Board::start_pos();
+        let params = EvalParams::default();
 
         let mut score = 0.0;
         let mut piece_count = 0;
@@ -224,9 +766,9 @@ mod evaluate_tests {
                 Piece::None => (),
                 piece => {
                     if piece.player() == Some(Player::White) {
-                        score += evaluate_material(&piece.type_of());
+                        score += evaluate_material(&piece.type_of(), 1.0, &params);
                     } else if piece.player() == Some(Player::Black) {
-                        score -= evaluate_material(&piece.type_of());
+                        score -= evaluate_material(&piece.type_of(), 1.0, &params);
                     }
                     piece_count += 1;
                 }
@@ -241,9 +783,9 @@ mod evaluate_tests {
     fn test_eval_threat() {
         let game = Board::from_fen("6k1/4QNpp/2p5/7P/8/6n1/3KP3/2B3BR b - - 0 1").unwrap();
         let pos = SQ(22);
-        let piece = game.piece_at_sq(pos).clone();
+        let piece = game.piece_at_sq(pos);
 
-        let score = evaluate_threats(&game, &piece, pos);
+        let score = evaluate_threats(&game, &piece, pos, 1.0, &EvalParams::default());
 
         println!("score: {}", score);
         assert!((score - 350.0).abs() < 0.1);
@@ -253,8 +795,12 @@ mod evaluate_tests {
     fn test_passed_pawn() {
         let game = Board::from_fen("8/8/4P2k/8/3p4/7K/8/8 w - - 0 1").unwrap();
 
-        let white_passed_pawn_score = evaluate_passed_pawn(&game, &Piece::WhitePawn, SQ(44));
-        let black_passed_pawn_score = evaluate_passed_pawn(&game, &Piece::BlackPawn, SQ(27));
+        let params = EvalParams::default();
+
+        let white_passed_pawn_score =
+            evaluate_passed_pawn(&game, &Piece::WhitePawn, SQ(44), 1.0, &params);
+        let black_passed_pawn_score =
+            evaluate_passed_pawn(&game, &Piece::BlackPawn, SQ(27), 1.0, &params);
 
         assert!(white_passed_pawn_score > 0.0);
         assert!(black_passed_pawn_score > 0.0);
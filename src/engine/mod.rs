@@ -1,21 +1,43 @@
 mod alpha_beta;
-mod evaluate_game;
+pub mod analysis;
+pub mod bench;
+pub mod epd;
+pub mod eval_params;
+pub mod eval_state;
+pub mod evaluate_game;
 mod move_sort;
+pub mod nnue;
 pub mod opening;
+pub mod pawns;
+pub mod polyglot;
 mod position_scores;
 mod random;
+pub mod search;
+pub mod see;
+pub mod solver;
 pub mod store;
+pub mod tablebase;
 
 use crate::engine::alpha_beta::alpha_beta;
-use crate::engine::opening::OpeningBook;
+use crate::engine::analysis::{fingerprint, AnalysisCache, CachedAnalysis};
+use crate::engine::eval_params::EvalParams;
+use crate::engine::nnue::Network;
+use crate::engine::opening::{BookPolicy, OpeningBook};
 use crate::engine::random::random_move;
+use crate::engine::search::{IterationInfo, SearchLimits, SearchResult};
+use crate::engine::solver::{solve, Solution, SolveResult};
 use crate::engine::store::AlphaBetaStore;
+use crate::engine::tablebase::Tablebase;
 use crate::error::ChessError;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use pleco::{BitMove, Board};
+use rand::{rngs::StdRng, SeedableRng};
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// the seed deterministic mode starts from, unless it's given another one
+pub const DETERMINISTIC_SEED: u64 = 0;
 
 pub struct AlphaBetaParams {
     /// the usual depth to search to.
@@ -26,6 +48,21 @@ pub struct AlphaBetaParams {
     /// When performing null move pruning, we often don't need to go as deep. This is how much less
     /// deep we go compared to the normal depth.
     pub null_move_reduction: i32,
+    /// past the depth limit, skip captures that lose material in the exchange
+    /// after at least one move has been searched
+    pub see_pruning: bool,
+    /// once the search score is at least this big either way, hand the position
+    /// to the proof-number solver to see if it is actually won or lost
+    pub solve_threshold: f64,
+    /// how many positions the solver gets to look at, 0 turns it off
+    pub solve_nodes: u64,
+    /// how to choose between the book moves of a position, see `Engine::seed`
+    /// for making the choice reproducible
+    pub book_policy: BookPolicy,
+    /// the book is only played from before this many plies into the game.
+    /// Plies are counted from the move number of the position, so a game set
+    /// up from a fen counts the moves before it too.
+    pub book_max_ply: u16,
     /// enables debug printing
     pub debug_print: i8,
     /// the maximum amount of time to search for
@@ -35,6 +72,12 @@ pub struct AlphaBetaParams {
 
     /// total time allowed to be spent searching
     pub total_time: Duration,
+
+    /// the maximum number of nodes to search per move, if any
+    pub nodes: Option<u64>,
+    /// ignore the clock completely. Searches only stop on depth or node limits,
+    /// so the same position and limits always give the same move.
+    pub deterministic: bool,
 }
 
 impl Default for AlphaBetaParams {
@@ -43,10 +86,17 @@ impl Default for AlphaBetaParams {
             depth: 7,
             max_depth: 28,
             null_move_reduction: 2,
+            see_pruning: true,
+            solve_threshold: 500.0,
+            solve_nodes: 0,
+            book_policy: BookPolicy::Weighted,
+            book_max_ply: 40,
             debug_print: 1,
             max_time: Duration::from_secs(25),
             handle_errors: true,
             total_time: Duration::from_secs(180),
+            nodes: None,
+            deterministic: false,
         }
     }
 }
@@ -55,6 +105,18 @@ pub struct Engine {
     pub opening_book: Option<OpeningBook>,
     pub store: AlphaBetaStore,
     pub params: AlphaBetaParams,
+    pub eval_params: EvalParams,
+    /// evaluate with this network instead of the classical eval, if set
+    pub nnue: Option<Network>,
+    /// endgame tables, probed during the search once few enough pieces are left
+    pub tablebase: Option<Tablebase>,
+    /// results of earlier searches by root position, reused when they went at
+    /// least as deep as the new search would, and updated after every search.
+    /// Never used in deterministic mode. The settings are only checked once,
+    /// by `set_analysis_cache`, so change them before setting the cache.
+    pub analysis_cache: Option<AnalysisCache>,
+    /// used for anything random the engine does, see `Engine::seed`
+    pub rng: StdRng,
 }
 
 impl Engine {
@@ -63,17 +125,30 @@ impl Engine {
             opening_book: None,
             store: AlphaBetaStore::new(),
             params: AlphaBetaParams::default(),
+            eval_params: EvalParams::default(),
+            nnue: None,
+            tablebase: None,
+            analysis_cache: None,
+            rng: StdRng::from_entropy(),
         }
     }
 
-    fn dynamic_depth_calculator(&self, depth_estimate: i32) -> i32 {
-        let time_left_dur = match self.params.total_time.checked_sub(Duration::from_millis(
-            self.store.total_search_time_ms as u64,
-        )) {
-            Some(t) => t,
-            None => Duration::from_secs(0),
-        };
-        let time_left_secs = time_left_dur.as_secs_f64();
+    /// Reseeds the random number generator, so every random choice the engine
+    /// makes from here on is reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Turns on deterministic mode, seeded with `DETERMINISTIC_SEED` so that
+    /// weighted book moves and the fallback move repeat from run to run as
+    /// well. `seed` can still pick another seed afterwards.
+    pub fn set_deterministic(&mut self) {
+        self.params.deterministic = true;
+        self.seed(DETERMINISTIC_SEED);
+    }
+
+    fn dynamic_depth_calculator(&self, depth_estimate: i32, time_left: Duration) -> i32 {
+        let time_left_secs = time_left.as_secs_f64();
 
         if time_left_secs > 45.0 {
             depth_estimate
@@ -97,101 +172,336 @@ impl Engine {
     /// - Iterative deepening (inside the main search function inside mod.rs)
     /// - Time management (will dynamically adjust depth based on time left)
     pub fn generate_move(&mut self, board: &Board) -> Result<BitMove> {
-        // use opening book if available
-        if board.ply() < 5 && self.opening_book.is_some() {
-            let book: &OpeningBook = self.opening_book.as_ref().unwrap();
-            if let Some(m) = book.get_move(&board.fen()) {
-                return Ok(m);
+        let res = self.search(board, &SearchLimits::default())?;
+
+        Ok(res.best_move)
+    }
+
+    /// Searches the board within the given limits, see `generate_move` for the
+    /// details of the search itself.
+    pub fn search(&mut self, board: &Board, limits: &SearchLimits) -> Result<SearchResult> {
+        // use opening book if available. It goes by the zobrist key, which
+        // leaves out the move counters, so transpositions are found too.
+        if board.moves_played() < self.params.book_max_ply {
+            if let Some(book) = &self.opening_book {
+                if let Some(m) = book.select(board, self.params.book_policy, &mut self.rng) {
+                    if self.params.debug_print > 0 {
+                        eprintln!("book move {}", m);
+                    }
+
+                    return Ok(SearchResult {
+                        best_move: m,
+                        ponder_move: None,
+                        score: 0.0,
+                        depth: 0,
+                        nodes: 0,
+                        pv: vec![m],
+                        elapsed: Duration::from_secs(0),
+                        from_book: true,
+                        from_cache: false,
+                        iterations: Vec::new(),
+                    });
+                }
             }
         }
 
-        let target_final_depth = self.dynamic_depth_calculator(self.params.depth);
+        // a mate limit says how deep to go by itself, so it wins over depth
+        let target_final_depth = if let Some(mate_in) = limits.mate_in {
+            // a mate in n takes 2n - 1 plies, and the last depth is never searched
+            2 * mate_in as i32
+        } else if let Some(depth) = limits.depth {
+            depth
+        } else if self.params.deterministic {
+            // how much time we have left must not change how deep we go
+            self.params.depth
+        } else {
+            let time_left = match limits.clock {
+                Some(clock) => clock.time_left,
+                None => self.params.total_time.saturating_sub(Duration::from_millis(
+                    self.store.total_search_time_ms as u64,
+                )),
+            };
+
+            self.dynamic_depth_calculator(self.params.depth, time_left)
+        };
 
         if self.params.debug_print > 0 {
             eprintln!("searching to depth {}", target_final_depth);
         }
 
+        // the last iteration is target_final_depth - 1, and a proven win
+        // can't get any better
+        if let Some(cached) = self.cached_analysis(board) {
+            if cached.depth >= target_final_depth - 1 || cached.score == f64::INFINITY {
+                if self.params.debug_print > 0 {
+                    eprintln!("cached analysis from depth {}", cached.depth);
+                }
+
+                return Ok(SearchResult {
+                    best_move: cached.best_move(),
+                    ponder_move: cached.pv.get(1).copied(),
+                    score: cached.score,
+                    depth: cached.depth,
+                    nodes: 0,
+                    pv: cached.pv.clone(),
+                    elapsed: Duration::from_secs(0),
+                    from_book: false,
+                    from_cache: true,
+                    iterations: Vec::new(),
+                });
+            }
+
+            // too shallow to play straight away, but still the best guess at
+            // the line for the move ordering
+            self.store.pv = cached.pv.clone();
+        }
+
         let mut best_move = None;
 
         let mut best_score = f64::NEG_INFINITY;
 
+        let mut depth_reached = 0;
+
+        let mut iterations = Vec::new();
+
         self.store.start_turn();
+        self.store.node_limit = limits.nodes.or(self.params.nodes);
+
+        if self.params.deterministic {
+            self.store.time_limit = None;
+            // the pv left over from the last search would change the move ordering
+            self.store.pv.clear();
+        } else {
+            self.store.time_limit = limits.time_budget(&self.params);
+        }
 
         for curr_depth in 1..target_final_depth {
             if self.params.debug_print > 0 {
                 eprintln!("Starting depth {}", curr_depth);
             }
 
-            if let Some(start_time) = self.store.start_time {
-                if start_time.elapsed() > self.params.max_time {
+            match self.store.limit_reached() {
+                Ok(true) => {
                     if self.params.debug_print > 0 {
-                        eprintln!(
-                            "Search time limit reached: {}",
-                            self.params.max_time.as_secs()
-                        );
+                        eprintln!("Search limit reached after {} nodes", self.store.nodes);
                     }
                     break;
                 }
-            } else if self.params.handle_errors {
-                return Err(ChessError::NoStartTime.into());
-            } else {
-                panic!("No start time");
+                Ok(false) => (),
+                Err(e) => {
+                    if self.params.handle_errors {
+                        return Err(e);
+                    } else {
+                        panic!("No start time");
+                    }
+                }
             }
 
             self.store.curr_depth = curr_depth;
             if let Ok(res) = alpha_beta(board, self) {
                 best_move = Some(res.0);
                 best_score = res.1;
+
+                // a search that ran out of budget part way through only tells us
+                // about the moves it got to
+                if !self.store.limit_reached()? {
+                    depth_reached = curr_depth;
+                    iterations.push(IterationInfo {
+                        depth: curr_depth,
+                        best_move: res.0,
+                        score: res.1,
+                        nodes: self.store.nodes,
+                        elapsed: self.store.start_time.unwrap().elapsed(),
+                    });
+                }
             } else if !self.params.handle_errors {
                 panic!("Alpha beta error");
             } // else we just ignore the error and keep going
 
-            if best_score == f64::INFINITY || best_score == f64::NEG_INFINITY {
+            if best_score.is_infinite() {
                 break;
             }
         }
 
-        let search_time = self.store.start_time.unwrap().elapsed().as_millis();
-        self.store.total_search_time_ms += search_time;
+        let mut search_time = self.store.start_time.unwrap().elapsed();
+        self.store.total_search_time_ms += search_time.as_millis();
+
+        // the solver gets whatever is left of the time for this move
+        let solve_time = self
+            .store
+            .time_limit
+            .map(|limit| limit.saturating_sub(search_time));
 
         if self.params.debug_print > 0 {
             eprintln!(
                 "search time: {}, total_time: {}",
-                search_time, self.store.total_search_time_ms
+                search_time.as_millis(),
+                self.store.total_search_time_ms
             );
         }
 
         self.store.end_turn();
 
-        if let Some(best_move_res) = best_move {
-            Ok(best_move_res)
+        let mut best_move = match best_move {
+            Some(m) => m,
+            None => return Err(ChessError::NoMoveGenerated.into()),
+        };
+
+        let mut solved_line = None;
+        if self.params.solve_nodes > 0
+            && best_score.is_finite()
+            && best_score.abs() >= self.params.solve_threshold
+            && solve_time.is_none_or(|time| !time.is_zero())
+        {
+            let solve_start = Instant::now();
+            let solved = self.solve(board, self.params.solve_nodes, solve_time);
+
+            let solver_elapsed = solve_start.elapsed();
+            search_time += solver_elapsed;
+            self.store.total_search_time_ms += solver_elapsed.as_millis();
+
+            if self.params.debug_print > 0 {
+                eprintln!("solver: {:?} after {} nodes", solved.solution, solved.nodes);
+            }
+
+            match solved.solution {
+                Solution::Win(line) if !line.is_empty() => {
+                    best_move = line[0];
+                    best_score = f64::INFINITY;
+                    solved_line = Some(line);
+                }
+                Solution::Loss => best_score = f64::NEG_INFINITY,
+                Solution::Win(_) | Solution::Unknown => (),
+            }
+        }
+
+        // the pv is only useful if it actually starts with the move we are playing
+        let pv = if let Some(line) = solved_line {
+            line
+        } else if self.store.pv.first() == Some(&best_move) {
+            self.store.pv.clone()
         } else {
-            Err(ChessError::NoMoveGenerated.into())
+            vec![best_move]
+        };
+
+        let res = SearchResult {
+            best_move,
+            ponder_move: pv.get(1).copied(),
+            score: best_score,
+            depth: depth_reached,
+            nodes: self.store.nodes,
+            pv,
+            elapsed: search_time,
+            from_book: false,
+            from_cache: false,
+            iterations,
+        };
+
+        if let Some(cache) = &mut self.analysis_cache {
+            cache.store(board, &res);
         }
+
+        Ok(res)
     }
 
-    pub fn generate_rand_move(&self, board: &Board) -> Result<BitMove> {
-        random_move(board, self)
+    /// A fingerprint of every setting that changes what a search finds: the
+    /// eval weights, the network, the tables we have and the pruning and
+    /// solver settings
+    pub fn settings_fingerprint(&self) -> u64 {
+        let mut bytes = serde_json::to_vec(&self.eval_params).unwrap();
+
+        if let Some(network) = &self.nnue {
+            bytes.extend_from_slice(b"nnue");
+            bytes.extend(network.to_bytes());
+        }
+        if let Some(tablebase) = &self.tablebase {
+            bytes.extend_from_slice(b"tablebase");
+            bytes.extend(tablebase.names().join(",").bytes());
+        }
+
+        let params = &self.params;
+        bytes.extend(
+            format!(
+                "max_depth {} null_move_reduction {} see_pruning {} solve_threshold {} solve_nodes {}",
+                params.max_depth,
+                params.null_move_reduction,
+                params.see_pruning,
+                params.solve_threshold,
+                params.solve_nodes
+            )
+            .bytes(),
+        );
+
+        fingerprint(&bytes)
     }
 
-    pub fn generate_valid_moves(&self, board: &Board) -> Vec<BitMove> {
-        let moves = board.generate_moves();
+    /// Starts using `cache` for searches, if it was made with the settings we
+    /// have now
+    pub fn set_analysis_cache(&mut self, cache: AnalysisCache) -> Result<()> {
+        if cache.fingerprint() != self.settings_fingerprint() {
+            bail!("it was made with other eval, network, tablebase or solver settings");
+        }
 
-        // get two lists of moves, one for captures and one for non-captures
-        let mut captures = Vec::new();
+        self.analysis_cache = Some(cache);
+        Ok(())
+    }
 
-        for m in moves.iter() {
-            if board.is_capture(*m) {
-                captures.push(*m);
+    /// The cached analysis of the position, if its move is one the search
+    /// itself could play. Whether the cache has a position depends on earlier
+    /// games, so deterministic mode never looks.
+    fn cached_analysis(&self, board: &Board) -> Option<CachedAnalysis> {
+        let cache = self.analysis_cache.as_ref()?;
+        if self.params.deterministic {
+            return None;
+        }
+
+        let cached = cache.get(board)?;
+        if let Some(tablebase) = &self.tablebase {
+            let moves = self.generate_valid_moves(board);
+            if let Some(moves) = tablebase.root_moves(board, &moves) {
+                if !moves.contains(&cached.best_move()) {
+                    return None;
+                }
             }
         }
 
-        if captures.is_empty() {
-            moves.into_iter().collect()
-        } else {
-            captures
+        Some(cached.clone())
+    }
+
+    /// Tries to prove a forced win or loss for the side to move with the
+    /// proof-number solver, using the tablebase if there is one
+    pub fn solve(&self, board: &Board, max_nodes: u64, max_time: Option<Duration>) -> SolveResult {
+        solve(board, max_nodes, max_time, self.tablebase.as_ref())
+    }
+
+    pub fn generate_rand_move(&mut self, board: &Board) -> Result<BitMove> {
+        random_move(board, self)
+    }
+
+    pub fn generate_valid_moves(&self, board: &Board) -> Vec<BitMove> {
+        valid_moves(board)
+    }
+}
+
+/// The legal moves under our rules, which are only the captures if there
+/// are any
+pub fn valid_moves(board: &Board) -> Vec<BitMove> {
+    let moves = board.generate_moves();
+
+    // get two lists of moves, one for captures and one for non-captures
+    let mut captures = Vec::new();
+
+    for m in moves.iter() {
+        if board.is_capture(*m) {
+            captures.push(*m);
         }
     }
+
+    if captures.is_empty() {
+        moves.into_iter().collect()
+    } else {
+        captures
+    }
 }
 
 impl Default for Engine {
@@ -210,6 +520,7 @@ mod gen_move_tests {
         let game = Board::start_pos();
         let mut engine = Engine::new();
         engine.params.depth = 10;
+        engine.params.book_policy = BookPolicy::Best;
         engine.opening_book = Some(OpeningBook::new());
 
         let curr_time = Instant::now();
@@ -221,10 +532,213 @@ mod gen_move_tests {
 
         assert_eq!(
             m.unwrap(),
-            engine.opening_book.unwrap().get_move(&game.fen()).unwrap()
+            engine.opening_book.unwrap().get_move(&game).unwrap()
         );
 
         // should use a move from the opening book, so it should be fast
         assert!(finish_time < Duration::from_millis(500));
     }
+
+    #[test]
+    fn test_search_limits() {
+        let game = Board::from_fen("7k/8/8/3q1n2/4P3/8/8/7K w - - 0 1").unwrap();
+        let mut engine = Engine::new();
+
+        let limits = SearchLimits {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let res = engine.search(&game, &limits).unwrap();
+
+        assert!(!res.from_book);
+        assert_eq!(res.depth, 3);
+        assert!(res.nodes > 0);
+        assert_eq!(res.pv[0], res.best_move);
+        assert_eq!(res.ponder_move, res.pv.get(1).copied());
+
+        // a tiny node budget still gives back a move
+        let limits = SearchLimits {
+            nodes: Some(10),
+            ..Default::default()
+        };
+        let res = engine.search(&game, &limits).unwrap();
+
+        assert!(res.nodes < 100);
+    }
+
+    #[test]
+    fn test_deterministic_search() {
+        let game =
+            Board::from_fen("2bqkbnr/3p2pp/2n2p2/4p3/8/1PP1P3/3PQPPP/2r1KBNR w - - 0 1").unwrap();
+
+        let mut engine = Engine::new();
+        engine.params.deterministic = true;
+        engine.params.nodes = Some(2000);
+
+        let first = engine.search(&game, &SearchLimits::default()).unwrap();
+
+        // searching something else in between must not change the answer
+        engine
+            .search(&Board::start_pos(), &SearchLimits::default())
+            .unwrap();
+
+        let second = engine.search(&game, &SearchLimits::default()).unwrap();
+
+        assert_eq!(first.best_move, second.best_move);
+        assert_eq!(first.nodes, second.nodes);
+        assert_eq!(first.pv, second.pv);
+    }
+
+    #[test]
+    fn test_deterministic_book_moves() {
+        let game = Board::start_pos();
+        let mut book = OpeningBook::empty();
+        for m in valid_moves(&game).into_iter().take(4) {
+            book.insert(&game, m, 5);
+        }
+
+        let book_moves = || {
+            let mut engine = Engine::new();
+            engine.set_deterministic();
+            engine.opening_book = Some(book.clone());
+
+            (0..10)
+                .map(|_| {
+                    engine
+                        .search(&game, &SearchLimits::default())
+                        .unwrap()
+                        .best_move
+                })
+                .collect::<Vec<BitMove>>()
+        };
+
+        // weighted picks a different move now and then, but the same ones on
+        // every run
+        let first = book_moves();
+        assert!(first.iter().any(|m| *m != first[0]));
+        assert_eq!(first, book_moves());
+    }
+
+    #[test]
+    fn test_seeded_random_move() {
+        let game = Board::start_pos();
+
+        let mut engine = Engine::new();
+        engine.seed(42);
+        let first: Vec<BitMove> = (0..5)
+            .map(|_| engine.generate_rand_move(&game).unwrap())
+            .collect();
+
+        engine.seed(42);
+        let second: Vec<BitMove> = (0..5)
+            .map(|_| engine.generate_rand_move(&game).unwrap())
+            .collect();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_search_from_book() {
+        let game = Board::start_pos();
+        let mut engine = Engine::new();
+        engine.opening_book = Some(OpeningBook::new());
+
+        let res = engine.search(&game, &SearchLimits::default()).unwrap();
+
+        assert!(res.from_book);
+        assert_eq!(res.pv, vec![res.best_move]);
+    }
+
+    #[test]
+    fn test_search_from_cache() {
+        let game = Board::from_fen("7k/8/8/3q1n2/4P3/8/8/6K1 w - - 0 1").unwrap();
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.analysis_cache = Some(AnalysisCache::new(engine.settings_fingerprint()));
+
+        let deep = SearchLimits {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let first = engine.search(&game, &deep).unwrap();
+        assert!(!first.from_cache);
+
+        // the same depth or less comes straight from the cache
+        for depth in [4, 2] {
+            let limits = SearchLimits {
+                depth: Some(depth),
+                ..Default::default()
+            };
+            let res = engine.search(&game, &limits).unwrap();
+
+            assert!(res.from_cache);
+            assert_eq!(res.best_move, first.best_move);
+            assert_eq!(res.depth, first.depth);
+            assert_eq!(res.nodes, 0);
+        }
+
+        // deeper has to search again, and the cache keeps the deeper result
+        let deeper = SearchLimits {
+            depth: Some(5),
+            ..Default::default()
+        };
+        assert!(!engine.search(&game, &deeper).unwrap().from_cache);
+        assert_eq!(
+            engine
+                .analysis_cache
+                .as_ref()
+                .unwrap()
+                .get(&game)
+                .unwrap()
+                .depth,
+            4
+        );
+
+        // other settings could find something else, so they don't get to use
+        // the cache
+        let cache = engine.analysis_cache.take().unwrap();
+        engine.eval_params.mobility_mg += 1.0;
+        assert!(engine.set_analysis_cache(cache.clone()).is_err());
+        engine.eval_params.mobility_mg -= 1.0;
+        engine.set_analysis_cache(cache).unwrap();
+
+        // neither does deterministic mode
+        engine.params.deterministic = true;
+        assert!(!engine.search(&game, &deep).unwrap().from_cache);
+        engine.params.deterministic = false;
+        assert!(engine.search(&game, &deep).unwrap().from_cache);
+    }
+
+    #[test]
+    fn test_book_max_ply() {
+        // the same position as 1. e4 h6 2. d4, deeper into a game and with an
+        // en passant square nothing can take on
+        let board =
+            Board::from_fen("rnbqkbnr/ppppppp1/7p/8/3PP3/8/PPP2PPP/RNBQKBNR b KQkq d3 3 12")
+                .unwrap();
+        let mut played = Board::start_pos();
+        for text in ["e4", "h6", "d4"] {
+            played.apply_move(crate::notation::parse_move(&played, text).unwrap());
+        }
+
+        let mut book = OpeningBook::empty();
+        let m = valid_moves(&played)[0];
+        book.insert(&played, m, 1);
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.opening_book = Some(book);
+        let limits = SearchLimits {
+            depth: Some(2),
+            ..Default::default()
+        };
+
+        let res = engine.search(&board, &limits).unwrap();
+        assert!(res.from_book);
+        assert_eq!(res.best_move, m);
+
+        // 11 full moves in, that's ply 23
+        engine.params.book_max_ply = 23;
+        assert!(!engine.search(&board, &limits).unwrap().from_book);
+    }
 }
@@ -1,7 +1,8 @@
+use crate::engine::see::see;
 use crate::engine::store::AlphaBetaStore;
-use pleco::{BitMove, Board, PieceType};
+use pleco::{BitMove, Board};
 
-pub fn sort_moves(board: &Board, store: &AlphaBetaStore, move_list: &Vec<BitMove>) -> Vec<BitMove> {
+pub fn sort_moves(board: &Board, store: &AlphaBetaStore, move_list: &[BitMove]) -> Vec<BitMove> {
     if move_list.is_empty() {
         return vec![];
     }
@@ -57,26 +58,10 @@ fn score_tt(store: &AlphaBetaStore, board: &Board, scored_moves: &mut [(&BitMove
     }
 }
 
+/// Orders captures by how much material the exchange they start wins, in pawns
 fn score_captures(board: &Board, move_list: &mut [(&BitMove, f64)]) {
-    fn eval_cost(piece_type: PieceType) -> f64 {
-        match piece_type {
-            PieceType::P => 1.0,
-            PieceType::N => 3.0,
-            PieceType::B => 3.0,
-            PieceType::R => 5.0,
-            PieceType::Q => 9.0,
-            PieceType::K => 15.0,
-            _ => 0.0,
-        }
-    }
-
     for (m, score) in move_list.iter_mut() {
-        let attacking_piece: PieceType = board.piece_at_sq(m.get_src()).type_of();
-
-        let victim_piece: PieceType = board.piece_at_sq(m.get_dest()).type_of();
-
-        // ratio of two pieces
-        *score += eval_cost(victim_piece) / eval_cost(attacking_piece);
+        *score += see(board, **m) / 100.0;
     }
 }
 
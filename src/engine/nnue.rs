@@ -0,0 +1,353 @@
+use crate::engine::eval_state::PieceChanges;
+
+use anyhow::{bail, Result};
+use pleco::{BitMove, Board, Piece, Player, SQ};
+
+use std::fs;
+
+/// Every network file starts with this, followed by a version byte
+pub const MAGIC: &[u8; 4] = b"ACNN";
+pub const VERSION: u8 = 1;
+
+/// one input for every piece type, of either side, on every square
+pub const INPUTS: usize = 2 * 6 * 64;
+
+/// The network's output is in units of this many centipawns. It matches the
+/// scale of the sigmoid the tuner uses, so a network trained on game results
+/// gives scores the search can use as is.
+pub const OUTPUT_SCALE: f64 = 400.0;
+
+/// A small efficiently updatable network: the board goes through a single
+/// hidden layer, once from each side's point of view, and the two halves are
+/// combined into a score for the side to move.
+///
+/// The hidden layer (the accumulator) only changes for the few pieces a move
+/// touches, so the search keeps it up to date move by move instead of
+/// building it from scratch at every leaf.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    pub hidden: usize,
+    /// `INPUTS * hidden` weights, all the weights of one input are next to each other
+    pub feature_weights: Vec<f32>,
+    pub feature_bias: Vec<f32>,
+    /// `2 * hidden` weights, the side to move's half comes first
+    pub output_weights: Vec<f32>,
+    pub output_bias: f32,
+}
+
+/// The hidden layer for both points of view
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accumulator {
+    pub white: Vec<f32>,
+    pub black: Vec<f32>,
+}
+
+/// Index of the input for `piece` on `sq`, as seen by `perspective`. Black
+/// sees the board flipped, so both sides see their own pieces the same way.
+pub fn feature_index(perspective: Player, piece: Piece, sq: SQ) -> usize {
+    let (player, piece_type) = piece.player_piece_lossy();
+
+    let side = if player == perspective { 0 } else { 1 };
+    let sq = if perspective == Player::White {
+        sq.0
+    } else {
+        sq.0 ^ 56
+    };
+
+    side * 6 * 64 + (piece_type as usize - 1) * 64 + sq as usize
+}
+
+impl Network {
+    /// A network where every weight is zero, which always evaluates to 0
+    pub fn zeroed(hidden: usize) -> Self {
+        Network {
+            hidden,
+            feature_weights: vec![0.0; INPUTS * hidden],
+            feature_bias: vec![0.0; hidden],
+            output_weights: vec![0.0; 2 * hidden],
+            output_bias: 0.0,
+        }
+    }
+
+    /// Loads a network file, see `to_bytes` for the format
+    pub fn load(path: &str) -> Result<Network> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// The file format is the magic bytes, a version byte, the hidden size as
+    /// a little endian u32, and then every weight as a little endian f32: the
+    /// feature weights, the feature biases, the output weights and the output
+    /// bias, in that order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+
+        for w in self
+            .feature_weights
+            .iter()
+            .chain(self.feature_bias.iter())
+            .chain(self.output_weights.iter())
+            .chain([self.output_bias].iter())
+        {
+            out.extend_from_slice(&w.to_le_bytes());
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network> {
+        if bytes.len() < 9 || &bytes[0..4] != MAGIC {
+            bail!("not a network file");
+        }
+        if bytes[4] != VERSION {
+            bail!("unsupported network file version {}", bytes[4]);
+        }
+
+        let hidden = u32::from_le_bytes(bytes[5..9].try_into()?) as usize;
+
+        let num_weights = INPUTS * hidden + hidden + 2 * hidden + 1;
+        if bytes.len() != 9 + 4 * num_weights {
+            bail!(
+                "network file has {} bytes, expected {} for {} hidden neurons",
+                bytes.len(),
+                9 + 4 * num_weights,
+                hidden
+            );
+        }
+
+        let mut weights = bytes[9..]
+            .chunks(4)
+            .map(|w| f32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+
+        let mut network = Network::zeroed(hidden);
+        for w in network
+            .feature_weights
+            .iter_mut()
+            .chain(network.feature_bias.iter_mut())
+            .chain(network.output_weights.iter_mut())
+            .chain([&mut network.output_bias])
+        {
+            // the length was checked above, so there are always enough
+            *w = weights.next().unwrap_or(0.0);
+        }
+
+        Ok(network)
+    }
+
+    /// Builds the accumulator for a board from scratch
+    pub fn refresh(&self, board: &Board) -> Accumulator {
+        let mut acc = Accumulator {
+            white: self.feature_bias.clone(),
+            black: self.feature_bias.clone(),
+        };
+
+        for (sq, piece) in board.get_piece_locations() {
+            if piece != Piece::None {
+                self.add(&mut acc, piece, sq);
+            }
+        }
+
+        acc
+    }
+
+    /// The accumulator after `m` is played, given the one from before it.
+    /// `board` is the board before the move.
+    pub fn update(&self, acc: &Accumulator, board: &Board, m: BitMove) -> Accumulator {
+        let mut acc = acc.clone();
+        let changes = PieceChanges::of_move(board, m);
+
+        for (piece, sq) in changes.removed() {
+            self.remove(&mut acc, piece, sq);
+        }
+        for (piece, sq) in changes.added() {
+            self.add(&mut acc, piece, sq);
+        }
+
+        acc
+    }
+
+    /// Score of the position from the point of view of `turn`, in centipawns
+    pub fn evaluate(&self, acc: &Accumulator, turn: Player) -> f64 {
+        let (us, them) = if turn == Player::White {
+            (&acc.white, &acc.black)
+        } else {
+            (&acc.black, &acc.white)
+        };
+
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+
+        let mut sum = self.output_bias;
+        for ind in 0..self.hidden {
+            sum += clipped_relu(us[ind]) * our_weights[ind];
+            sum += clipped_relu(them[ind]) * their_weights[ind];
+        }
+
+        sum as f64 * OUTPUT_SCALE
+    }
+
+    /// Evaluates a board from scratch, from white's point of view like the
+    /// classical `evaluate`. The search uses the incremental path instead.
+    pub fn evaluate_board(&self, board: &Board) -> f64 {
+        let score = self.evaluate(&self.refresh(board), board.turn());
+
+        if board.turn() == Player::White {
+            score
+        } else {
+            -score
+        }
+    }
+
+    fn add(&self, acc: &mut Accumulator, piece: Piece, sq: SQ) {
+        let white = self.weights_of(feature_index(Player::White, piece, sq));
+        let black = self.weights_of(feature_index(Player::Black, piece, sq));
+
+        for (a, w) in acc.white.iter_mut().zip(white) {
+            *a += w;
+        }
+        for (a, w) in acc.black.iter_mut().zip(black) {
+            *a += w;
+        }
+    }
+
+    fn remove(&self, acc: &mut Accumulator, piece: Piece, sq: SQ) {
+        let white = self.weights_of(feature_index(Player::White, piece, sq));
+        let black = self.weights_of(feature_index(Player::Black, piece, sq));
+
+        for (a, w) in acc.white.iter_mut().zip(white) {
+            *a -= w;
+        }
+        for (a, w) in acc.black.iter_mut().zip(black) {
+            *a -= w;
+        }
+    }
+
+    fn weights_of(&self, feature: usize) -> &[f32] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+}
+
+fn clipped_relu(x: f32) -> f32 {
+    x.clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod nnue_tests {
+    use super::*;
+    use crate::engine::search::SearchLimits;
+    use crate::engine::Engine;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn random_network(hidden: usize, seed: u64) -> Network {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut network = Network::zeroed(hidden);
+
+        for w in network
+            .feature_weights
+            .iter_mut()
+            .chain(network.feature_bias.iter_mut())
+            .chain(network.output_weights.iter_mut())
+        {
+            *w = rng.gen_range(-0.5..0.5);
+        }
+
+        network
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let network = random_network(4, 1);
+
+        let loaded = Network::from_bytes(&network.to_bytes()).unwrap();
+        assert_eq!(loaded, network);
+
+        let mut truncated = network.to_bytes();
+        truncated.pop();
+        assert!(Network::from_bytes(&truncated).is_err());
+        assert!(Network::from_bytes(b"ACSP\x01").is_err());
+    }
+
+    #[test]
+    fn test_incremental_matches_refresh() {
+        let network = random_network(8, 2);
+
+        // castling both ways, en passant and a promotion with capture
+        for (fen, moves) in [
+            (
+                "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1",
+                ["e1g1", "e8c8"].as_slice(),
+            ),
+            (
+                "4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1",
+                ["e2e4", "d4e3"].as_slice(),
+            ),
+            ("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", ["a7b8q"].as_slice()),
+        ] {
+            let mut board = Board::from_fen(fen).unwrap();
+            let mut acc = network.refresh(&board);
+
+            for text in moves {
+                let m = board
+                    .generate_moves()
+                    .into_iter()
+                    .find(|m| m.stringify() == *text)
+                    .unwrap();
+
+                acc = network.update(&acc, &board, m);
+                board.apply_move(m);
+
+                let fresh = network.refresh(&board);
+                for (a, b) in acc
+                    .white
+                    .iter()
+                    .chain(acc.black.iter())
+                    .zip(fresh.white.iter().chain(fresh.black.iter()))
+                {
+                    assert!((a - b).abs() < 1e-4, "{} after {}", board.fen(), text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_with_network() {
+        let game = Board::from_fen("7k/8/8/3q4/4P3/8/8/7K w - - 0 1").unwrap();
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.nnue = Some(random_network(8, 4));
+
+        let limits = SearchLimits {
+            depth: Some(4),
+            infinite: true,
+            ..Default::default()
+        };
+        let res = engine.search(&game, &limits).unwrap();
+
+        // the only capture is forced, whatever the network thinks of it
+        assert_eq!(res.best_move.stringify(), "e4d5");
+        assert_eq!(engine.store.accumulators.len(), 1);
+    }
+
+    #[test]
+    fn test_symmetric_eval() {
+        let network = random_network(8, 3);
+
+        // the same position with the colors swapped should score the same for the side to move
+        let white = Board::from_fen("4k3/8/8/3q4/8/2N5/8/4K3 w - - 0 1").unwrap();
+        let black = Board::from_fen("4k3/8/2n5/8/3Q4/8/8/4K3 b - - 0 1").unwrap();
+
+        let score = network.evaluate(&network.refresh(&white), Player::White);
+        let flipped = network.evaluate(&network.refresh(&black), Player::Black);
+
+        assert!((score - flipped).abs() < 1e-3);
+        assert!((network.evaluate_board(&white) + network.evaluate_board(&black)).abs() < 1e-3);
+    }
+}
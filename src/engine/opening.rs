@@ -1,105 +1,1020 @@
-use pleco::BitMove;
-use std::collections::HashMap;
+use crate::engine::valid_moves;
+use crate::notation::move_to_san;
 
-pub struct OpeningBook {
-    openings: HashMap<String, BitMove>,
+use anyhow::{anyhow, bail, Result};
+use pleco::{BitMove, Board, Player, SQ};
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ACBK";
+const VERSION: u8 = 1;
+/// magic, version and the number of entries as a little endian u64
+const HEADER_LEN: usize = 13;
+/// position key (u64), move (u16), weight (u16), then wins, draws and losses
+/// (u32 each), all little endian
+const ENTRY_LEN: usize = 24;
+
+/// The book we ship with: positions by FEN and the raw move to play
+const BUILT_IN: &[(&str, u16)] = &[
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        1478,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/N7/PPPPPPPP/R1BQKBNR b KQkq - 1 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/6P1/PPPPPP1P/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/7P/PPPPPPP1/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/P7/8/1PPPPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/7N/PPPPPPPP/RNBQKB1R b KQkq - 1 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/4P3/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/5P2/8/PPPPP1PP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/P7/1PPPPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/1P6/P1PPPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/7P/8/PPPPPPP1/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/5P2/PPPPP1PP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/2P5/8/PP1PPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/2N5/PPPPPPPP/R1BQKBNR b KQkq - 1 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/3P4/PPP1PPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/1P6/8/P1PPPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/2P5/PP1PPPPP/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/6P1/8/PPPPPP1P/RNBQKBNR b KQkq - 0 1",
+        3070,
+    ),
+];
+
+/// How to choose between the moves the book has for a position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookPolicy {
+    /// always the move with the highest weight
+    Best,
+    /// any move, as likely as its share of the weights
+    Weighted,
+    /// any of the n heaviest moves, all as likely
+    TopN(usize),
 }
-impl OpeningBook {
-    pub fn new() -> OpeningBook {
-        let openings: HashMap<String, BitMove> = HashMap::from([
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/N7/PPPPPPPP/R1BQKBNR b KQkq - 1 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/6P1/PPPPPP1P/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/7P/PPPPPPP1/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/P7/8/1PPPPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/7N/PPPPPPPP/RNBQKB1R b KQkq - 1 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/4P3/PPPP1PPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/5P2/8/PPPPP1PP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/P7/1PPPPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/1P6/P1PPPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/7P/8/PPPPPPP1/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/5P2/PPPPP1PP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/2P5/8/PP1PPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/2N5/PPPPPPPP/R1BQKBNR b KQkq - 1 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/3P4/PPP1PPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/1P6/8/P1PPPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
-                BitMove::new(1478),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/8/2P5/PP1PPPPP/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-            (
-                String::from("rnbqkbnr/pppppppp/8/8/6P1/8/PPPPPP1P/RNBQKBNR b KQkq - 0 1"),
-                BitMove::new(3070),
-            ),
-        ]);
-        OpeningBook { openings }
-    }
-    pub fn get_move(&self, fen: &str) -> Option<BitMove> {
-        self.openings.get(fen).cloned()
+
+/// What to do with a position both books have when merging them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// all the moves of both, adding the weights and stats of moves in both
+    Sum,
+    /// only our moves
+    Keep,
+    /// only the other book's moves
+    Replace,
+}
+
+impl MergePolicy {
+    pub fn parse(text: &str) -> Result<MergePolicy> {
+        match text {
+            "sum" => Ok(MergePolicy::Sum),
+            "keep" => Ok(MergePolicy::Keep),
+            "replace" => Ok(MergePolicy::Replace),
+            _ => Err(anyhow!(
+                "merge policies are sum, keep or replace, got {}",
+                text
+            )),
+        }
     }
 }
+
+/// How the games went after a book move, for the side that played it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BookStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl BookStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// average result, 1.0 if every game was won. None before the first game.
+    pub fn score(&self) -> Option<f64> {
+        match self.games() {
+            0 => None,
+            games => Some((self.wins as f64 + 0.5 * self.draws as f64) / games as f64),
+        }
+    }
+
+    /// `result` is 1.0 for a win, 0.5 for a draw and 0.0 for a loss
+    pub fn record(&mut self, result: f64) {
+        if result > 0.5 {
+            self.wins += 1;
+        } else if result < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    fn add(&mut self, other: BookStats) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+impl BookPolicy {
+    /// `best`, `weighted` or `top-N`, e.g. `top-3`
+    pub fn parse(text: &str) -> Result<BookPolicy> {
+        match text {
+            "best" => Ok(BookPolicy::Best),
+            "weighted" => Ok(BookPolicy::Weighted),
+            _ => {
+                let n = text
+                    .strip_prefix("top-")
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| {
+                        anyhow!("book policies are best, weighted or top-N, got {}", text)
+                    })?;
+                Ok(BookPolicy::TopN(n))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BookMove {
+    pub chess_move: BitMove,
+    /// how often to play this move relative to the others in the position
+    pub weight: u16,
+    /// how games went after the move, ours or the ones the book was built from
+    pub stats: BookStats,
+}
+
+impl BookMove {
+    pub fn new(chess_move: BitMove, weight: u16) -> BookMove {
+        BookMove {
+            chess_move,
+            weight,
+            stats: BookStats::default(),
+        }
+    }
+
+    /// The weight scaled by how well the move did. A move that always won
+    /// gets up to twice its weight, one that always lost goes towards 0. The
+    /// score starts from a win and a loss, so a few games don't swing it much.
+    pub fn learned_weight(&self) -> f64 {
+        let stats = &self.stats;
+        let score =
+            (stats.wins as f64 + 0.5 * stats.draws as f64 + 1.0) / (stats.games() as f64 + 2.0);
+
+        2.0 * score * self.weight as f64
+    }
+}
+
+/// A stored move that can't be played in the position it's stored for
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidMove {
+    pub key: u64,
+    pub fen: String,
+    pub chess_move: BitMove,
+}
+
+/// What `OpeningBook::verify` found
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BookCheck {
+    /// positions reached from the starting position, whose moves were checked
+    pub checked: usize,
+    pub invalid: Vec<InvalidMove>,
+    /// keys of the positions that couldn't be reached, so we don't know what
+    /// position they are and can't check their moves
+    pub unreached: Vec<u64>,
+}
+
+/// A book position written out for people (or scripts) to read
+#[derive(Clone, Debug, Serialize)]
+pub struct PositionDump {
+    /// the `position_key` in hex
+    pub key: String,
+    /// only known for positions reached from the starting position
+    pub fen: Option<String>,
+    pub moves: Vec<MoveDump>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MoveDump {
+    pub uci: String,
+    /// these two need the position, so they are None without a fen
+    pub san: Option<String>,
+    pub valid: Option<bool>,
+    pub weight: u16,
+    pub learned_weight: f64,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    /// see `BookStats::score`
+    pub score: Option<f64>,
+}
+
+impl MoveDump {
+    fn new(book_move: &BookMove, board: Option<&Board>) -> MoveDump {
+        let valid = board.map(|board| valid_moves(board).contains(&book_move.chess_move));
+
+        MoveDump {
+            uci: book_move.chess_move.stringify(),
+            san: board
+                .filter(|_| valid == Some(true))
+                .map(|board| move_to_san(board, book_move.chess_move)),
+            valid,
+            weight: book_move.weight,
+            learned_weight: book_move.learned_weight(),
+            wins: book_move.stats.wins,
+            draws: book_move.stats.draws,
+            losses: book_move.stats.losses,
+            score: book_move.stats.score(),
+        }
+    }
+}
+
+/// Book moves keyed by the `position_key` of the position they are played in.
+/// The built-in book comes from `OpeningBook::new`, bigger ones are generated
+/// with the opening-gen binary and loaded with `OpeningBook::load`.
+#[derive(Clone, Debug)]
+pub struct OpeningBook {
+    positions: HashMap<u64, Vec<BookMove>>,
+}
+
 impl Default for OpeningBook {
     fn default() -> Self {
         Self::new()
     }
 }
+
+impl OpeningBook {
+    /// The built-in book
+    pub fn new() -> OpeningBook {
+        let mut book = OpeningBook::empty();
+
+        for (fen, raw) in BUILT_IN {
+            let board = Board::from_fen(fen).unwrap();
+            book.insert(&board, BitMove::new(*raw), 1);
+        }
+
+        book
+    }
+
+    pub fn empty() -> OpeningBook {
+        OpeningBook {
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Adds a move to the position, or adds to its weight if it's already there
+    pub fn insert(&mut self, board: &Board, chess_move: BitMove, weight: u16) {
+        self.insert_key(position_key(board), BookMove::new(chess_move, weight));
+    }
+
+    /// Adds a book move under its key, adding the weight and stats to the move
+    /// if it's already there
+    pub fn insert_key(&mut self, key: u64, book_move: BookMove) {
+        let moves = self.positions.entry(key).or_default();
+
+        match moves
+            .iter_mut()
+            .find(|m| m.chess_move == book_move.chess_move)
+        {
+            Some(m) => {
+                m.weight = m.weight.saturating_add(book_move.weight);
+                m.stats.add(book_move.stats);
+            }
+            None => moves.push(book_move),
+        }
+    }
+
+    /// Counts the result of a game for every book move played in it, by
+    /// either side. `moves` starts from the starting position and `result` is
+    /// from white's point of view, 1.0 for a white win. Returns how many book
+    /// moves were updated.
+    pub fn learn(&mut self, moves: &[BitMove], result: f64) -> usize {
+        let mut board = Board::start_pos();
+        let mut learned = 0;
+
+        for m in moves {
+            let mover_result = if board.turn() == Player::White {
+                result
+            } else {
+                1.0 - result
+            };
+
+            if let Some(book_move) = self
+                .positions
+                .get_mut(&position_key(&board))
+                .and_then(|moves| moves.iter_mut().find(|b| b.chess_move == *m))
+            {
+                book_move.stats.record(mover_result);
+                learned += 1;
+            }
+
+            board.apply_move(*m);
+        }
+
+        learned
+    }
+
+    /// Removes every move that lost too often: at least `min_games` games and
+    /// a score below `min_score`. Positions left without moves go too. Returns
+    /// how many moves were removed.
+    pub fn prune(&mut self, min_games: u32, min_score: f64) -> usize {
+        let mut removed = 0;
+
+        for moves in self.positions.values_mut() {
+            let before = moves.len();
+            moves.retain(|m| {
+                m.stats.games() < min_games || m.stats.score().is_none_or(|s| s >= min_score)
+            });
+            removed += before - moves.len();
+        }
+        self.positions.retain(|_, moves| !moves.is_empty());
+
+        removed
+    }
+
+    /// Every book move in this position that is actually legal here, in case
+    /// of a key collision or a book made for different rules
+    pub fn moves(&self, board: &Board) -> Vec<BookMove> {
+        let book_moves = match self.positions.get(&position_key(board)) {
+            Some(moves) => moves,
+            None => return Vec::new(),
+        };
+
+        let legal = valid_moves(board);
+        book_moves
+            .iter()
+            .filter(|m| legal.contains(&m.chess_move))
+            .copied()
+            .collect()
+    }
+
+    /// The move with the highest weight after learning, if the position is in
+    /// the book
+    pub fn get_move(&self, board: &Board) -> Option<BitMove> {
+        self.moves(board)
+            .into_iter()
+            .max_by(|a, b| a.learned_weight().total_cmp(&b.learned_weight()))
+            .map(|m| m.chess_move)
+    }
+
+    /// A random book move, each one as likely as its share of the learned
+    /// weights
+    pub fn weighted_move<R: Rng>(&self, board: &Board, rng: &mut R) -> Option<BitMove> {
+        pick_weighted(&self.moves(board), rng)
+    }
+
+    /// A book move chosen the way `policy` says. Moves with a weight of 0
+    /// are only ever played by `Best`, when there is nothing else.
+    pub fn select<R: Rng>(
+        &self,
+        board: &Board,
+        policy: BookPolicy,
+        rng: &mut R,
+    ) -> Option<BitMove> {
+        match policy {
+            BookPolicy::Best => self.get_move(board),
+            BookPolicy::Weighted => self.weighted_move(board, rng),
+            BookPolicy::TopN(n) => {
+                let mut moves = self.moves(board);
+                moves.retain(|m| m.weight > 0);
+                // heaviest first, ties in a fixed order so a seed always picks the same
+                moves.sort_by(|a, b| {
+                    b.learned_weight()
+                        .total_cmp(&a.learned_weight())
+                        .then(a.chess_move.get_raw().cmp(&b.chess_move.get_raw()))
+                });
+                moves.truncate(n);
+
+                moves.choose(rng).map(|m| m.chess_move)
+            }
+        }
+    }
+
+    /// Every position in the book with its moves, in no particular order
+    pub fn positions(&self) -> impl Iterator<Item = (u64, &[BookMove])> {
+        self.positions
+            .iter()
+            .map(|(key, moves)| (*key, moves.as_slice()))
+    }
+
+    /// Takes out one move, and the position if that was its last. Returns
+    /// whether the move was there.
+    pub fn remove(&mut self, key: u64, chess_move: BitMove) -> bool {
+        let moves = match self.positions.get_mut(&key) {
+            Some(moves) => moves,
+            None => return false,
+        };
+
+        let before = moves.len();
+        moves.retain(|m| m.chess_move != chess_move);
+        let removed = moves.len() < before;
+
+        if moves.is_empty() {
+            self.positions.remove(&key);
+        }
+        removed
+    }
+
+    /// Every book position reached from the starting position by playing
+    /// valid book moves, the starting position first and then by ply
+    pub fn reachable(&self) -> Vec<Board> {
+        let start = Board::start_pos();
+        if !self.positions.contains_key(&position_key(&start)) {
+            return Vec::new();
+        }
+
+        let mut seen = HashSet::from([position_key(&start)]);
+        let mut queue = VecDeque::from([start]);
+        let mut reached = Vec::new();
+
+        while let Some(board) = queue.pop_front() {
+            for m in self.moves(&board) {
+                let mut child = board.clone();
+                child.apply_move(m.chess_move);
+
+                if self.positions.contains_key(&position_key(&child))
+                    && seen.insert(position_key(&child))
+                {
+                    queue.push_back(child);
+                }
+            }
+            reached.push(board);
+        }
+
+        reached
+    }
+
+    /// Checks every stored move is valid under our rules. Moves are stored
+    /// raw, so one from a key collision or a book made for other rules would
+    /// otherwise only show up as a book move that never gets played. Only
+    /// positions in `reachable` can be checked.
+    pub fn verify(&self) -> BookCheck {
+        let reached = self.reachable();
+        let mut check = BookCheck {
+            checked: reached.len(),
+            ..Default::default()
+        };
+
+        for board in &reached {
+            let valid = valid_moves(board);
+
+            for m in &self.positions[&position_key(board)] {
+                if !valid.contains(&m.chess_move) {
+                    check.invalid.push(InvalidMove {
+                        key: position_key(board),
+                        fen: board.fen(),
+                        chess_move: m.chess_move,
+                    });
+                }
+            }
+        }
+
+        let reached: HashSet<u64> = reached.iter().map(position_key).collect();
+        check.unreached = self
+            .positions
+            .keys()
+            .filter(|key| !reached.contains(key))
+            .copied()
+            .collect();
+        check.unreached.sort_unstable();
+
+        check
+    }
+
+    /// One position with every move stored for it, valid or not, heaviest
+    /// first
+    pub fn dump_position(&self, board: &Board) -> Option<PositionDump> {
+        let moves = self.positions.get(&position_key(board))?;
+
+        Some(PositionDump {
+            key: format!("{:016x}", position_key(board)),
+            fen: Some(board.fen()),
+            moves: sorted(moves)
+                .iter()
+                .map(|m| MoveDump::new(m, Some(board)))
+                .collect(),
+        })
+    }
+
+    /// Every position, the reachable ones first in the order of `reachable`,
+    /// then the rest by key without a fen
+    pub fn dump(&self) -> Vec<PositionDump> {
+        let reached = self.reachable();
+        let mut dump: Vec<PositionDump> = reached
+            .iter()
+            .filter_map(|board| self.dump_position(board))
+            .collect();
+
+        let reached: HashSet<u64> = reached.iter().map(position_key).collect();
+        let mut rest: Vec<(&u64, &Vec<BookMove>)> = self
+            .positions
+            .iter()
+            .filter(|(key, _)| !reached.contains(key))
+            .collect();
+        rest.sort_unstable_by_key(|(key, _)| **key);
+
+        dump.extend(rest.into_iter().map(|(key, moves)| {
+            PositionDump {
+                key: format!("{:016x}", key),
+                fen: None,
+                moves: sorted(moves)
+                    .iter()
+                    .map(|m| MoveDump::new(m, None))
+                    .collect(),
+            }
+        }));
+
+        dump
+    }
+
+    /// number of positions in the book
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Adds every position of `other` that isn't in this book, so this book
+    /// wins wherever both have moves
+    pub fn with_fallback(mut self, other: OpeningBook) -> OpeningBook {
+        for (key, moves) in other.positions {
+            self.positions.entry(key).or_insert(moves);
+        }
+
+        self
+    }
+
+    /// Adds the other book's positions. For positions both have, `policy`
+    /// decides whose moves are kept. Returns how many positions both had.
+    pub fn merge(&mut self, other: OpeningBook, policy: MergePolicy) -> usize {
+        let mut conflicts = 0;
+
+        for (key, moves) in other.positions {
+            let ours = match self.positions.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(moves);
+                    continue;
+                }
+                Entry::Occupied(entry) => entry.into_mut(),
+            };
+
+            conflicts += 1;
+            match policy {
+                MergePolicy::Sum => {
+                    for m in moves {
+                        self.insert_key(key, m);
+                    }
+                }
+                MergePolicy::Keep => (),
+                MergePolicy::Replace => *ours = moves,
+            }
+        }
+
+        conflicts
+    }
+
+    /// The book as bytes: a header, then one entry per move sorted by key and
+    /// then by weight, heaviest first
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<(u64, BookMove)> = self
+            .positions
+            .iter()
+            .flat_map(|(key, moves)| moves.iter().map(move |m| (*key, *m)))
+            .collect();
+        entries.sort_by_key(|(key, m)| (*key, u16::MAX - m.weight, m.chess_move.get_raw()));
+
+        let mut out = Vec::with_capacity(HEADER_LEN + ENTRY_LEN * entries.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+        for (key, m) in entries {
+            out.extend_from_slice(&key.to_le_bytes());
+            out.extend_from_slice(&m.chess_move.get_raw().to_le_bytes());
+            out.extend_from_slice(&m.weight.to_le_bytes());
+            out.extend_from_slice(&m.stats.wins.to_le_bytes());
+            out.extend_from_slice(&m.stats.draws.to_le_bytes());
+            out.extend_from_slice(&m.stats.losses.to_le_bytes());
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<OpeningBook> {
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            bail!("not an opening book file");
+        }
+        if bytes[4] != VERSION {
+            bail!("unsupported opening book version {}", bytes[4]);
+        }
+
+        let count = u64::from_le_bytes(bytes[5..13].try_into()?);
+        let body = &bytes[HEADER_LEN..];
+        let expected = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(ENTRY_LEN));
+        if expected != Some(body.len()) {
+            bail!(
+                "opening book has {} bytes of entries, expected {} entries",
+                body.len(),
+                count
+            );
+        }
+
+        let u32_at =
+            |entry: &[u8], at: usize| u32::from_le_bytes(entry[at..at + 4].try_into().unwrap());
+
+        let mut book = OpeningBook::empty();
+        for entry in body.chunks(ENTRY_LEN) {
+            let key = u64::from_le_bytes(entry[0..8].try_into()?);
+            let raw = u16::from_le_bytes([entry[8], entry[9]]);
+            let weight = u16::from_le_bytes([entry[10], entry[11]]);
+
+            let mut book_move = BookMove::new(BitMove::new(raw), weight);
+            book_move.stats = BookStats {
+                wins: u32_at(entry, 12),
+                draws: u32_at(entry, 16),
+                losses: u32_at(entry, 20),
+            };
+            book.insert_key(key, book_move);
+        }
+
+        Ok(book)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<OpeningBook> {
+        OpeningBook::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Picks a move at random with probability proportional to its learned
+/// weight. Moves with a weight of 0 are never picked.
+pub fn pick_weighted<R: Rng>(moves: &[BookMove], rng: &mut R) -> Option<BitMove> {
+    let total: f64 = moves.iter().map(|m| m.learned_weight()).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut target = rng.gen_range(0.0..total);
+    for m in moves.iter().filter(|m| m.weight > 0) {
+        if target < m.learned_weight() {
+            return Some(m.chess_move);
+        }
+        target -= m.learned_weight();
+    }
+
+    // rounding can leave a tiny bit over at the end
+    moves
+        .iter()
+        .rev()
+        .find(|m| m.weight > 0)
+        .map(|m| m.chess_move)
+}
+
+/// The key a position is stored under: its zobrist key, which leaves out the
+/// move counters, so a position is found however the game got there. A fen can
+/// give an en passant square no pawn can take on, which pleco would hash but
+/// never sets itself after a double push, so that one is left out too.
+pub fn position_key(board: &Board) -> u64 {
+    if board.ep_square() == SQ::NONE
+        || board
+            .generate_pseudolegal_moves()
+            .iter()
+            .any(|m| m.is_en_passant())
+    {
+        return board.zobrist();
+    }
+
+    let fen = board.fen();
+    let mut fields: Vec<&str> = fen.split_whitespace().collect();
+    fields[3] = "-";
+
+    Board::from_fen(&fields.join(" "))
+        .map(|board| board.zobrist())
+        .unwrap_or_else(|_| board.zobrist())
+}
+
+/// heaviest first, ties by raw move so the order is always the same
+fn sorted(moves: &[BookMove]) -> Vec<BookMove> {
+    let mut moves = moves.to_vec();
+    moves.sort_by_key(|m| (u16::MAX - m.weight, m.chess_move.get_raw()));
+    moves
+}
+
+#[cfg(test)]
+mod opening_tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn find_move(board: &Board, text: &str) -> BitMove {
+        board
+            .generate_moves()
+            .into_iter()
+            .find(|m| m.stringify() == text)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_built_in_book() {
+        let book = OpeningBook::new();
+        assert_eq!(book.len(), BUILT_IN.len());
+        assert_eq!(OpeningBook::default().len(), BUILT_IN.len());
+
+        let mut board = Board::start_pos();
+        assert!(book.get_move(&board).is_some());
+
+        // positions are found however we got to them
+        board.apply_move(find_move(&board, "e2e4"));
+        assert_eq!(book.get_move(&board).unwrap().stringify(), "g8h6");
+        assert!(book
+            .get_move(&Board::from_fen("8/8/8/4k3/8/8/8/4K3 w - - 0 1").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_round_trip_and_fallback() {
+        let board = Board::start_pos();
+        let e4 = find_move(&board, "e2e4");
+        let d4 = find_move(&board, "d2d4");
+
+        let mut book = OpeningBook::empty();
+        book.insert(&board, e4, 3);
+        book.insert(&board, d4, 5);
+        book.insert(&board, e4, 4);
+
+        let loaded = OpeningBook::from_bytes(&book.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.moves(&board),
+            vec![BookMove::new(e4, 7), BookMove::new(d4, 5)]
+        );
+        assert_eq!(loaded.get_move(&board), Some(e4));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let picks: Vec<BitMove> = (0..200)
+            .filter_map(|_| loaded.weighted_move(&board, &mut rng))
+            .collect();
+        assert_eq!(picks.len(), 200);
+        assert!(picks.contains(&e4) && picks.contains(&d4));
+
+        // our moves win over the built-in ones, which fill in the rest
+        let merged = loaded.with_fallback(OpeningBook::new());
+        assert_eq!(merged.get_move(&board), Some(e4));
+        assert_eq!(merged.len(), BUILT_IN.len());
+
+        let mut bytes = book.to_bytes();
+        bytes.pop();
+        assert!(OpeningBook::from_bytes(&bytes).is_err());
+        assert!(OpeningBook::from_bytes(b"nope").is_err());
+
+        // a count that overflows the entry size
+        let mut bytes = book.to_bytes();
+        bytes[5..13].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(OpeningBook::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_policies() {
+        let board = Board::start_pos();
+        let mut book = OpeningBook::empty();
+        for (text, weight) in [("e2e4", 10), ("d2d4", 5), ("c2c4", 1), ("g1f3", 0)] {
+            book.insert(&board, find_move(&board, text), weight);
+        }
+
+        let picked = |policy: BookPolicy, seed: u64| -> Vec<String> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut picks: Vec<String> = (0..300)
+                .map(|_| book.select(&board, policy, &mut rng).unwrap().stringify())
+                .collect();
+            picks.sort();
+            picks.dedup();
+            picks
+        };
+
+        assert_eq!(picked(BookPolicy::Best, 1), vec!["e2e4"]);
+        assert_eq!(picked(BookPolicy::TopN(2), 1), vec!["d2d4", "e2e4"]);
+        assert_eq!(
+            picked(BookPolicy::Weighted, 1),
+            vec!["c2c4", "d2d4", "e2e4"]
+        );
+
+        // the same seed gives the same games
+        let mut first = StdRng::seed_from_u64(7);
+        let mut second = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            assert_eq!(
+                book.select(&board, BookPolicy::Weighted, &mut first),
+                book.select(&board, BookPolicy::Weighted, &mut second)
+            );
+        }
+
+        assert_eq!(BookPolicy::parse("top-3").unwrap(), BookPolicy::TopN(3));
+        assert_eq!(BookPolicy::parse("weighted").unwrap(), BookPolicy::Weighted);
+        assert!(BookPolicy::parse("top-0").is_err());
+        assert!(BookPolicy::parse("worst").is_err());
+    }
+
+    #[test]
+    fn test_learning() {
+        let mut board = Board::start_pos();
+        let e4 = find_move(&board, "e2e4");
+        let d4 = find_move(&board, "d2d4");
+
+        let mut book = OpeningBook::empty();
+        book.insert(&board, e4, 10);
+        book.insert(&board, d4, 10);
+        board.apply_move(e4);
+        let h6 = find_move(&board, "g8h6");
+        book.insert(&board, h6, 1);
+
+        // e4 h6 and then white wins, three times. The moves after the book
+        // don't matter.
+        let mut game = vec![e4, h6];
+        game.push(find_move(&board_after(&[e4, h6]), "d2d4"));
+        for _ in 0..3 {
+            assert_eq!(book.learn(&game, 1.0), 2);
+        }
+        book.learn(&[d4], 0.5);
+
+        let start = Board::start_pos();
+        let moves = book.moves(&start);
+        assert_eq!(
+            moves[0].stats,
+            BookStats {
+                wins: 3,
+                draws: 0,
+                losses: 0
+            }
+        );
+        assert_eq!(moves[1].stats.score(), Some(0.5));
+        assert_eq!(book.moves(&board)[0].stats.losses, 3);
+
+        // the winning move is played more now
+        assert_eq!(book.get_move(&start), Some(e4));
+        assert!(moves[0].learned_weight() > moves[1].learned_weight());
+
+        // and the stats survive a round trip
+        let loaded = OpeningBook::from_bytes(&book.to_bytes()).unwrap();
+        for m in &moves {
+            assert!(loaded.moves(&start).contains(m));
+        }
+
+        assert_eq!(book.prune(3, 0.25), 1);
+        assert!(book.moves(&board).is_empty());
+        assert_eq!(book.len(), 1);
+    }
+
+    #[test]
+    fn test_verify_and_dump() {
+        let mut board = Board::start_pos();
+        let e3 = find_move(&board, "e2e3");
+        let mut book = OpeningBook::empty();
+        book.insert(&board, e3, 5);
+        // e2e5 isn't a move at all
+        book.insert(&board, BitMove::new(12 | (36 << 6)), 1);
+
+        board.apply_move(e3);
+        book.insert(&board, find_move(&board, "b7b5"), 2);
+        book.insert_key(42, BookMove::new(e3, 1));
+
+        assert_eq!(book.reachable().len(), 2);
+
+        let check = book.verify();
+        assert_eq!(check.checked, 2);
+        assert_eq!(check.invalid.len(), 1);
+        assert_eq!(check.invalid[0].chess_move.stringify(), "e2e5");
+        assert_eq!(check.unreached, vec![42]);
+
+        let dump = book.dump();
+        assert_eq!(dump.len(), 3);
+        assert_eq!(dump[0].fen, Some(Board::start_pos().fen()));
+        assert_eq!(dump[0].moves[0].san.as_deref(), Some("e3"));
+        assert_eq!(dump[0].moves[1].valid, Some(false));
+        assert_eq!(dump[1].moves[0].uci, "b7b5");
+        assert_eq!(dump[2].key, format!("{:016x}", 42));
+        assert_eq!(dump[2].fen, None);
+
+        assert!(book.remove(check.invalid[0].key, check.invalid[0].chess_move));
+        assert!(book.verify().invalid.is_empty());
+        assert!(book.remove(42, e3));
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let start = Board::start_pos();
+        let e3 = find_move(&start, "e2e3");
+        let g3 = find_move(&start, "g2g3");
+
+        let mut ours = OpeningBook::empty();
+        ours.insert(&start, e3, 5);
+        let mut theirs = OpeningBook::empty();
+        theirs.insert(&start, e3, 2);
+        theirs.insert(&start, g3, 1);
+        theirs.insert_key(42, BookMove::new(e3, 1));
+
+        let weights = |book: &OpeningBook| -> Vec<u16> {
+            sorted(&book.moves(&start))
+                .iter()
+                .map(|m| m.weight)
+                .collect()
+        };
+
+        let mut sum = ours.clone();
+        assert_eq!(sum.merge(theirs.clone(), MergePolicy::Sum), 1);
+        assert_eq!(weights(&sum), vec![7, 1]);
+        assert_eq!(sum.len(), 2);
+
+        let mut keep = ours.clone();
+        keep.merge(theirs.clone(), MergePolicy::Keep);
+        assert_eq!(weights(&keep), vec![5]);
+        assert_eq!(keep.len(), 2);
+
+        let mut replace = ours;
+        replace.merge(theirs, MergePolicy::Replace);
+        assert_eq!(weights(&replace), vec![2, 1]);
+
+        assert_eq!(MergePolicy::parse("keep").unwrap(), MergePolicy::Keep);
+        assert!(MergePolicy::parse("max").is_err());
+    }
+
+    fn board_after(moves: &[BitMove]) -> Board {
+        let mut board = Board::start_pos();
+        for m in moves {
+            board.apply_move(*m);
+        }
+        board
+    }
+}
@@ -0,0 +1,217 @@
+use pleco::{Board, PieceType, Player};
+
+/// How many of each kind of pawn both sides have, `[white, black]`. This only
+/// depends on where the pawns are, so it can be cached by the pawn key, and
+/// it doesn't depend on the eval params, so the cache stays valid when they
+/// change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PawnStructure {
+    /// pawns without a friendly pawn on either neighbouring file
+    pub isolated: [u32; 2],
+    /// every pawn on a file after the first one
+    pub doubled: [u32; 2],
+    /// pawns that were left behind by their neighbours, and can't safely move up
+    pub backward: [u32; 2],
+    /// pawns next to or defended by another pawn
+    pub connected: [u32; 2],
+    /// pawns that aren't passed yet, but have enough support to get there
+    pub candidate_passers: [u32; 2],
+    /// pawns that have an enemy pawn right in front of them
+    pub blocked: [u32; 2],
+}
+
+/// `[file][rank]`, with the ranks counted from `perspective`'s side so ahead
+/// is always up
+type PawnGrid = [[bool; 8]; 8];
+
+fn pawn_grid(board: &Board, owner: Player, perspective: Player) -> PawnGrid {
+    let mut grid = [[false; 8]; 8];
+
+    for sq in board.piece_bb(owner, PieceType::P) {
+        let rank = if perspective == Player::White {
+            sq.rank_idx_of_sq()
+        } else {
+            7 - sq.rank_idx_of_sq()
+        };
+        grid[sq.file_idx_of_sq() as usize][rank as usize] = true;
+    }
+
+    grid
+}
+
+fn neighbour_files(file: usize) -> impl Iterator<Item = usize> {
+    [file.checked_sub(1), Some(file + 1).filter(|f| *f < 8)]
+        .into_iter()
+        .flatten()
+}
+
+/// number of pawns in the grid on `file` with a rank in `ranks`
+fn count(grid: &PawnGrid, file: usize, ranks: impl Iterator<Item = usize>) -> u32 {
+    ranks.filter(|r| grid[file][*r]).count() as u32
+}
+
+/// Works out the pawn structure of the board from scratch
+pub fn pawn_structure(board: &Board) -> PawnStructure {
+    let mut structure = PawnStructure::default();
+
+    for (side, player) in [Player::White, Player::Black].into_iter().enumerate() {
+        let ours = pawn_grid(board, player, player);
+        let theirs = pawn_grid(board, player.other_player(), player);
+
+        for file in 0..8 {
+            let on_file = count(&ours, file, 0..8);
+            structure.doubled[side] += on_file.saturating_sub(1);
+
+            for rank in (0..8).filter(|r| ours[file][*r]) {
+                let neighbours: u32 = neighbour_files(file).map(|f| count(&ours, f, 0..8)).sum();
+                let behind_or_level: u32 = neighbour_files(file)
+                    .map(|f| count(&ours, f, 0..rank + 1))
+                    .sum();
+                let sentries: u32 = neighbour_files(file)
+                    .map(|f| count(&theirs, f, rank + 1..8))
+                    .sum();
+                let in_front = count(&theirs, file, rank + 1..8);
+
+                // a pawn on the last rank would have promoted already
+                let stop = (rank < 7).then_some(rank + 1);
+
+                if neighbours == 0 {
+                    structure.isolated[side] += 1;
+                }
+
+                let connected =
+                    neighbour_files(file).any(|f| ours[f][rank] || (rank > 0 && ours[f][rank - 1]));
+                if connected {
+                    structure.connected[side] += 1;
+                }
+
+                if let Some(stop) = stop {
+                    if theirs[file][stop] {
+                        structure.blocked[side] += 1;
+                    }
+
+                    // an enemy pawn two ranks up on a neighbouring file attacks the stop square
+                    let stop_attacked =
+                        stop < 7 && neighbour_files(file).any(|f| theirs[f][stop + 1]);
+                    if neighbours > 0 && behind_or_level == 0 && stop_attacked {
+                        structure.backward[side] += 1;
+                    }
+                }
+
+                let passed = in_front == 0 && sentries == 0;
+                if !passed && in_front == 0 && behind_or_level >= sentries {
+                    structure.candidate_passers[side] += 1;
+                }
+            }
+        }
+    }
+
+    structure
+}
+
+/// number of entries in the pawn hash table
+pub const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+/// Caches the pawn structure by pawn key, so the search only works it out
+/// once for every pawn formation it sees.
+pub struct PawnTable {
+    entries: Vec<Option<(u64, PawnStructure)>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        PawnTable {
+            entries: vec![None; PAWN_TABLE_SIZE],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The pawn structure of the board, `key` has to be its pawn key. Entries
+    /// keep the whole key, so only two pawn setups with the same 64 bit key
+    /// get each other's structure, which is rare enough to live with.
+    pub fn probe(&mut self, board: &Board, key: u64) -> PawnStructure {
+        let ind = (key % PAWN_TABLE_SIZE as u64) as usize;
+
+        if let Some((entry_key, structure)) = self.entries[ind] {
+            if entry_key == key {
+                self.hits += 1;
+                return structure;
+            }
+        }
+
+        self.misses += 1;
+
+        let structure = pawn_structure(board);
+        self.entries[ind] = Some((key, structure));
+
+        structure
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod pawns_tests {
+    use super::*;
+    use crate::engine::eval_params::EvalParams;
+    use crate::engine::eval_state::EvalState;
+
+    fn structure(fen: &str) -> PawnStructure {
+        pawn_structure(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn test_isolated_and_doubled() {
+        // white: a2 isolated, c2 and c3 doubled (and isolated); black has a phalanx on f7 g7
+        let s = structure("4k3/5pp1/8/8/8/2P5/P1P5/4K3 w - - 0 1");
+
+        assert_eq!(s.isolated, [3, 0]);
+        assert_eq!(s.doubled, [1, 0]);
+        assert_eq!(s.connected, [0, 2]);
+    }
+
+    #[test]
+    fn test_backward_blocked_and_candidates() {
+        // c4 and c5 block each other, and d3 defends c4
+        let s = structure("4k3/8/8/2p5/2P5/3P4/8/4K3 w - - 0 1");
+        assert_eq!(s.blocked, [1, 1]);
+        assert_eq!(s.connected, [1, 0]);
+
+        // d3 is behind e4, and c5 controls d4
+        let s = structure("4k3/8/8/2p5/4P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(s.backward, [1, 0]);
+
+        // nothing controls d4 with the black pawn on c6
+        let s = structure("4k3/8/2p5/8/4P3/3P4/8/4K3 w - - 0 1");
+        assert_eq!(s.backward, [0, 0]);
+
+        // the d pawn has c and e pawns next to it against a single c pawn, the
+        // e pawn is already passed
+        let s = structure("4k3/2p5/8/8/8/2PPP3/8/4K3 w - - 0 1");
+        assert_eq!(s.candidate_passers, [1, 0]);
+    }
+
+    #[test]
+    fn test_pawn_table() {
+        let params = EvalParams::default();
+        let mut table = PawnTable::new();
+
+        let board = Board::from_fen("4k3/5pp1/8/8/8/2P5/P1P5/4K3 w - - 0 1").unwrap();
+        let key = EvalState::new(&board, &params).pawn_key;
+
+        let first = table.probe(&board, key);
+        let second = table.probe(&board, key);
+
+        assert_eq!(first, second);
+        assert_eq!(first, pawn_structure(&board));
+        assert_eq!(table.hits, 1);
+        assert_eq!(table.misses, 1);
+    }
+}
@@ -1,87 +1,121 @@
-use pleco::{Player, SQ};
+use crate::engine::eval_params::{taper, EvalParams};
 
-/// more points the closer it is to promotion
-pub fn pawn_position_score(pos: SQ, color: Player) -> f64 {
-    let score = [
-        [6, 7, 7, 7, 7, 7, 7, 6],
-        [5, 6, 6, 6, 6, 6, 6, 5],
-        [4, 5, 5, 5, 5, 5, 5, 4],
-        [3, 4, 4, 4, 4, 4, 4, 3],
-        [2, 3, 3, 3, 3, 3, 3, 2],
-        [1, 2, 2, 2, 2, 2, 2, 1],
-        [0, 1, 1, 1, 1, 1, 1, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0],
-    ];
+use pleco::{PieceType, Player, SQ};
 
-    let row = if color == Player::White {
-        7 - pos.rank_idx_of_sq()
-    } else {
-        pos.rank_idx_of_sq()
-    };
-    let col = pos.file_idx_of_sq();
+/// A piece square table, written from white's point of view with the 8th rank
+/// on top. Values are on a 0 to 7 scale, see `EvalParams::pst_scale`.
+pub type PieceTable = [[f64; 8]; 8];
 
-    ((score[row as usize][col as usize] as f64) * 100.0) / 7.0
-}
+/// more points the closer it is to promotion
+pub const PAWN_TABLE: PieceTable = [
+    [6.0, 7.0, 7.0, 7.0, 7.0, 7.0, 7.0, 6.0],
+    [5.0, 6.0, 6.0, 6.0, 6.0, 6.0, 6.0, 5.0],
+    [4.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 4.0],
+    [3.0, 4.0, 4.0, 4.0, 4.0, 4.0, 4.0, 3.0],
+    [2.0, 3.0, 3.0, 3.0, 3.0, 3.0, 3.0, 2.0],
+    [1.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 1.0],
+    [0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+];
 
 /// more points the closer it is to the center
-pub fn knight_position_score(pos: SQ, color: Player) -> f64 {
-    let score = [
-        [0, 1, 1, 1, 1, 1, 1, 0],
-        [1, 2, 5, 3, 3, 5, 2, 1],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [1, 2, 3, 3, 3, 3, 2, 1],
-        [0, -3, 0, 0, 0, 0, -3, 0],
-    ];
+pub const KNIGHT_TABLE: PieceTable = [
+    [0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0],
+    [1.0, 2.0, 5.0, 3.0, 3.0, 5.0, 2.0, 1.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 1.0],
+    [0.0, -3.0, 0.0, 0.0, 0.0, 0.0, -3.0, 0.0],
+];
 
-    let row = if color == Player::White {
-        7 - pos.rank_idx_of_sq()
-    } else {
-        pos.rank_idx_of_sq()
-    };
-    let col = pos.file_idx_of_sq();
+/// more points if closer to long diagonals
+pub const BISHOP_TABLE: PieceTable = [
+    [1.0, 1.0, 2.0, 0.0, 0.0, 2.0, 1.0, 1.0],
+    [1.0, 2.0, 3.0, 2.0, 2.0, 3.0, 2.0, 1.0],
+    [2.0, 3.0, 4.0, 5.0, 5.0, 4.0, 3.0, 2.0],
+    [3.0, 4.0, 5.0, 6.0, 6.0, 5.0, 4.0, 3.0],
+    [3.0, 4.0, 5.0, 6.0, 6.0, 5.0, 4.0, 3.0],
+    [2.0, 3.0, 4.0, 5.0, 5.0, 4.0, 3.0, 2.0],
+    [1.0, 2.0, 3.0, 4.0, 4.0, 3.0, 2.0, 1.0],
+    [2.0, 1.0, 0.0, 3.0, 3.0, 0.0, 1.0, 2.0],
+];
 
-    ((score[row as usize][col as usize] as f64) * 100.0) / 7.0
-}
+/// kings early in the game, more points if closer to safety in the back
+pub const KING_TABLE: PieceTable = [
+    [-1.0, -2.0, -2.0, -2.0, -2.0, -2.0, -2.0, -1.0],
+    [-1.0, -3.0, -3.0, -3.0, -3.0, -3.0, -3.0, -1.0],
+    [-1.0, -2.0, -3.0, -3.0, -3.0, -3.0, -2.0, -1.0],
+    [-1.0, -2.0, -3.0, -3.0, -3.0, -3.0, -2.0, -1.0],
+    [-1.0, -2.0, -2.0, -3.0, -3.0, -2.0, -2.0, -1.0],
+    [0.0, 0.0, -2.0, -1.0, -1.0, -2.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, -1.0, -1.0, 0.0, 0.0, 0.0],
+    [6.0, 6.0, 5.0, 2.0, 5.0, 6.0, 7.0, 6.0],
+];
 
-/// more points if closer to long diagonals
-pub fn bishop_position_score(pos: SQ, color: Player) -> f64 {
-    let score = [
-        [1, 1, 2, 0, 0, 2, 1, 1],
-        [1, 2, 3, 2, 2, 3, 2, 1],
-        [2, 3, 4, 5, 5, 4, 3, 2],
-        [3, 4, 5, 6, 6, 5, 4, 3],
-        [3, 4, 5, 6, 6, 5, 4, 3],
-        [2, 3, 4, 5, 5, 4, 3, 2],
-        [1, 2, 3, 4, 4, 3, 2, 1],
-        [2, 1, 0, 3, 3, 0, 1, 2],
-    ];
+/// kings late in the game, where they have to come out and fight
+pub const KING_END_TABLE: PieceTable = [
+    [1.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 1.0],
+    [1.0, 3.0, 3.0, 3.0, 3.0, 3.0, 3.0, 1.0],
+    [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 1.0],
+    [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 1.0],
+    [1.0, 2.0, 2.0, 3.0, 3.0, 2.0, 2.0, 1.0],
+    [0.0, 0.0, 2.0, 1.0, 1.0, 2.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+    [-6.0, -6.0, -5.0, -2.0, -5.0, -6.0, -7.0, -6.0],
+];
 
-    let row = if color == Player::White {
-        7 - pos.rank_idx_of_sq()
-    } else {
-        pos.rank_idx_of_sq()
-    };
-    let col = pos.file_idx_of_sq();
+/// rooks early in the game
+pub const ROOK_START_TABLE: PieceTable = [
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0],
+    [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0],
+    [2.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 2.0],
+];
 
-    ((score[row as usize][col as usize] as f64) * 100.0) / 7.0
-}
+/// rooks late in the game
+pub const ROOK_END_TABLE: PieceTable = [
+    [0.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 0.0],
+    [1.0, 2.0, 5.0, 3.0, 3.0, 5.0, 2.0, 1.0],
+    [1.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 1.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [1.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 1.0],
+    [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 1.0],
+    [0.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 0.0],
+];
+
+/// queens early in the game
+pub const QUEEN_START_TABLE: PieceTable = [
+    [5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0],
+    [3.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0, 3.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+    [2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0, 2.0],
+    [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0],
+    [2.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 2.0],
+];
 
-/// more points if closer to safety in the back
-pub fn king_position_score(pos: SQ, color: Player, turn_counter: i64) -> f64 {
-    let score = [
-        [-1, -2, -2, -2, -2, -2, -2, -1],
-        [-1, -3, -3, -3, -3, -3, -3, -1],
-        [-1, -2, -3, -3, -3, -3, -2, -1],
-        [-1, -2, -3, -3, -3, -3, -2, -1],
-        [-1, -2, -2, -3, -3, -2, -2, -1],
-        [0, 0, -2, -1, -1, -2, 0, 0],
-        [0, 0, 0, -1, -1, 0, 0, 0],
-        [6, 6, 5, 2, 5, 6, 7, 6],
-    ];
+/// queens late in the game
+pub const QUEEN_END_TABLE: PieceTable = [
+    [0.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 0.0],
+    [1.0, 2.0, 5.0, 3.0, 3.0, 5.0, 2.0, 1.0],
+    [1.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 1.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [2.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 2.0],
+    [1.0, 3.0, 5.0, 5.0, 5.0, 5.0, 3.0, 1.0],
+    [1.0, 2.0, 3.0, 3.0, 3.0, 3.0, 2.0, 1.0],
+    [0.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 0.0],
+];
 
+/// the table entry for a square, seen from the side of `color`
+fn table_value(table: &PieceTable, pos: SQ, color: Player) -> f64 {
     let row = if color == Player::White {
         7 - pos.rank_idx_of_sq()
     } else {
@@ -89,97 +123,115 @@ pub fn king_position_score(pos: SQ, color: Player, turn_counter: i64) -> f64 {
     };
     let col = pos.file_idx_of_sq();
 
-    let scale = if turn_counter < 5 {
-        1.0
-    } else if turn_counter < 25 {
-        -0.1 * (turn_counter as f64) + 1.5
-    } else {
-        -1.0
-    };
+    table[row as usize][col as usize]
+}
 
-    (scale * (score[row as usize][col as usize] as f64 * 100.0)) / 7.0
+pub fn pawn_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.pawn_table_mg,
+        &params.pawn_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
 }
 
-pub fn rook_position_score(pos: SQ, color: Player, turn_counter: i64) -> f64 {
-    let score_start = [
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [2, 2, 2, 2, 2, 2, 2, 2],
-        [2, 0, 0, 0, 0, 0, 0, 2],
-        [2, 2, 3, 3, 3, 3, 2, 2],
-    ];
-
-    let score_end = [
-        [0, 1, 1, 2, 2, 1, 1, 0],
-        [1, 2, 5, 3, 3, 5, 2, 1],
-        [1, 3, 5, 5, 5, 5, 3, 1],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [1, 3, 5, 5, 5, 5, 3, 1],
-        [1, 2, 3, 3, 3, 3, 2, 1],
-        [0, 1, 1, 2, 2, 1, 1, 0],
-    ];
+pub fn knight_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.knight_table_mg,
+        &params.knight_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
+}
 
-    let row = if color == Player::White {
-        7 - pos.rank_idx_of_sq()
-    } else {
-        pos.rank_idx_of_sq()
-    };
-    let col = pos.file_idx_of_sq();
+pub fn bishop_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.bishop_table_mg,
+        &params.bishop_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
+}
 
-    let scale = if turn_counter < 5 {
-        1.0
-    } else {
-        (-0.1 * (turn_counter as f64) + 1.5).max(0.0)
-    };
+pub fn king_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.king_table_mg,
+        &params.king_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
+}
 
-    ((scale * (score_start[row as usize][col as usize] as f64 * 100.0)) / 7.0
-        + (scale * (score_end[row as usize][col as usize] as f64 * 100.0)) / 7.0)
-        / 2.0
+pub fn rook_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.rook_table_mg,
+        &params.rook_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
 }
 
-pub fn queen_position_score(pos: SQ, color: Player, turn_counter: i64) -> f64 {
-    let score_start = [
-        [5, 5, 5, 5, 5, 5, 5, 5],
-        [3, 5, 5, 5, 5, 5, 5, 3],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [1, 1, 1, 1, 1, 1, 1, 1],
-        [2, 2, 2, 2, 2, 2, 2, 2],
-        [2, 0, 0, 0, 0, 0, 0, 2],
-        [2, 2, 3, 3, 3, 3, 2, 2],
-    ];
-
-    let score_end = [
-        [0, 1, 1, 2, 2, 1, 1, 0],
-        [1, 2, 5, 3, 3, 5, 2, 1],
-        [1, 3, 5, 5, 5, 5, 3, 1],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [2, 3, 5, 5, 5, 5, 3, 2],
-        [1, 3, 5, 5, 5, 5, 3, 1],
-        [1, 2, 3, 3, 3, 3, 2, 1],
-        [0, 1, 1, 2, 2, 1, 1, 0],
-    ];
+pub fn queen_position_score(pos: SQ, color: Player, phase: f64, params: &EvalParams) -> f64 {
+    tapered_score(
+        &params.queen_table_mg,
+        &params.queen_table_eg,
+        pos,
+        color,
+        phase,
+        params,
+    )
+}
 
-    let row = if color == Player::White {
-        7 - pos.rank_idx_of_sq()
-    } else {
-        pos.rank_idx_of_sq()
+/// The middlegame and endgame table values for a piece, before blending. The
+/// incremental eval keeps these two sums separately, see `EvalState`.
+pub fn piece_square_values(
+    piece_type: PieceType,
+    pos: SQ,
+    color: Player,
+    params: &EvalParams,
+) -> (f64, f64) {
+    let (start, end) = match piece_type {
+        PieceType::P => (&params.pawn_table_mg, &params.pawn_table_eg),
+        PieceType::N => (&params.knight_table_mg, &params.knight_table_eg),
+        PieceType::B => (&params.bishop_table_mg, &params.bishop_table_eg),
+        PieceType::R => (&params.rook_table_mg, &params.rook_table_eg),
+        PieceType::Q => (&params.queen_table_mg, &params.queen_table_eg),
+        PieceType::K => (&params.king_table_mg, &params.king_table_eg),
+        _ => return (0.0, 0.0),
     };
-    let col = pos.file_idx_of_sq();
 
-    let scale = if turn_counter < 5 {
-        1.0
-    } else {
-        (-0.1 * (turn_counter as f64) + 1.5).max(0.0)
-    };
+    (
+        (table_value(start, pos, color) * params.pst_scale) / 7.0,
+        (table_value(end, pos, color) * params.pst_scale) / 7.0,
+    )
+}
+
+/// blends the middlegame and endgame tables by the game phase, see `game_phase`
+fn tapered_score(
+    start: &PieceTable,
+    end: &PieceTable,
+    pos: SQ,
+    color: Player,
+    phase: f64,
+    params: &EvalParams,
+) -> f64 {
+    let value = taper(
+        table_value(start, pos, color),
+        table_value(end, pos, color),
+        phase,
+    );
 
-    ((scale * (score_start[row as usize][col as usize] as f64 * 100.0)) / 7.0
-        + (scale * (score_end[row as usize][col as usize] as f64 * 100.0)) / 7.0)
-        / 2.0
+    (value * params.pst_scale) / 7.0
 }
 
 #[cfg(test)]
@@ -190,7 +242,7 @@ mod position_score_tests {
 
     #[test]
     fn test_right_orientation() {
-        let pawn_score = pawn_position_score(SQ(23), Player::White);
+        let pawn_score = pawn_position_score(SQ(23), Player::White, 1.0, &EvalParams::default());
         let expected_score = (1.0 * 100.0) / 7.0;
         println!("pawn score: {}", pawn_score);
         println!("expected score: {}", expected_score);
@@ -199,25 +251,44 @@ mod position_score_tests {
 
     #[test]
     fn position_score_mirror() {
+        let params = EvalParams::default();
+
         assert_eq!(
-            pawn_position_score(SQ::make(File::B, Rank::R1), Player::White),
-            pawn_position_score(SQ::make(File::B, Rank::R8), Player::Black)
+            pawn_position_score(SQ::make(File::B, Rank::R1), Player::White, 0.5, &params),
+            pawn_position_score(SQ::make(File::B, Rank::R8), Player::Black, 0.5, &params)
         );
         assert_eq!(
-            knight_position_score(SQ::make(File::B, Rank::R1), Player::White),
-            knight_position_score(SQ::make(File::B, Rank::R8), Player::Black)
+            knight_position_score(SQ::make(File::B, Rank::R1), Player::White, 0.5, &params),
+            knight_position_score(SQ::make(File::B, Rank::R8), Player::Black, 0.5, &params)
         );
         assert_eq!(
-            bishop_position_score(SQ::make(File::B, Rank::R1), Player::White),
-            bishop_position_score(SQ::make(File::B, Rank::R8), Player::Black)
+            bishop_position_score(SQ::make(File::B, Rank::R1), Player::White, 0.5, &params),
+            bishop_position_score(SQ::make(File::B, Rank::R8), Player::Black, 0.5, &params)
         );
         assert_eq!(
-            rook_position_score(SQ::make(File::B, Rank::R1), Player::White, 10),
-            rook_position_score(SQ::make(File::B, Rank::R8), Player::Black, 10)
+            rook_position_score(SQ::make(File::B, Rank::R1), Player::White, 0.5, &params),
+            rook_position_score(SQ::make(File::B, Rank::R8), Player::Black, 0.5, &params)
         );
         assert_eq!(
-            queen_position_score(SQ::make(File::B, Rank::R1), Player::White, 10),
-            queen_position_score(SQ::make(File::B, Rank::R8), Player::Black, 10)
+            queen_position_score(SQ::make(File::B, Rank::R1), Player::White, 0.5, &params),
+            queen_position_score(SQ::make(File::B, Rank::R8), Player::Black, 0.5, &params)
+        );
+    }
+
+    #[test]
+    fn test_king_tables_taper() {
+        let params = EvalParams::default();
+        let center = SQ::make(File::E, Rank::R4);
+        let corner = SQ::make(File::G, Rank::R1);
+
+        // hide in the corner while there is material around, come out once it's gone
+        assert!(
+            king_position_score(corner, Player::White, 1.0, &params)
+                > king_position_score(center, Player::White, 1.0, &params)
+        );
+        assert!(
+            king_position_score(center, Player::White, 0.0, &params)
+                > king_position_score(corner, Player::White, 0.0, &params)
         );
     }
 }
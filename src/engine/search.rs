@@ -0,0 +1,140 @@
+use crate::engine::AlphaBetaParams;
+
+use pleco::BitMove;
+
+use std::time::Duration;
+
+/// The clock of the side to move, as given by a match runner or GUI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    /// time left on our clock
+    pub time_left: Duration,
+    /// time added to our clock after every move
+    pub increment: Duration,
+}
+
+/// Limits for a single search. Anything left unset falls back to the
+/// matching value in `AlphaBetaParams`, so `SearchLimits::default()` searches
+/// exactly like `Engine::generate_move` always has.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// the usual depth to search to, same meaning as `AlphaBetaParams::depth`
+    pub depth: Option<i32>,
+    /// stop searching after this many nodes
+    pub nodes: Option<u64>,
+    /// spend at most this much time on the move, the search can stop sooner
+    /// once it reaches its depth
+    pub movetime: Option<Duration>,
+    /// budget the move from the remaining clock time instead
+    pub clock: Option<Clock>,
+    /// ignore every time limit, only depth and nodes stop the search
+    pub infinite: bool,
+    /// look for a forced win in this many moves, searches as deep as that
+    /// takes whatever `depth` says
+    pub mate_in: Option<u32>,
+}
+
+impl SearchLimits {
+    /// How long we are allowed to think for this move. `None` means there is
+    /// no time limit at all.
+    pub fn time_budget(&self, params: &AlphaBetaParams) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+
+        if let Some(movetime) = self.movetime {
+            return Some(movetime);
+        }
+
+        if let Some(clock) = self.clock {
+            // assume there are around 30 moves left in the game, and always
+            // keep a small safety margin so we never flag
+            let budget = clock.time_left / 30 + clock.increment * 3 / 4;
+            let hard_limit = clock.time_left.saturating_sub(Duration::from_millis(50));
+
+            return Some(budget.min(hard_limit));
+        }
+
+        Some(params.max_time)
+    }
+}
+
+/// Everything we learned from a single search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    /// the move to play
+    pub best_move: BitMove,
+    /// the reply we expect from the opponent, if we have one
+    pub ponder_move: Option<BitMove>,
+    /// score from the point of view of the side to move
+    pub score: f64,
+    /// the last depth that was fully searched
+    pub depth: i32,
+    /// number of nodes searched
+    pub nodes: u64,
+    /// principal variation, starting with `best_move`
+    pub pv: Vec<BitMove>,
+    /// how long the search took
+    pub elapsed: Duration,
+    /// whether the move came straight out of the opening book
+    pub from_book: bool,
+    /// whether the move came from an earlier search in the analysis cache
+    pub from_cache: bool,
+    /// what the search thought after every finished iteration, in order
+    pub iterations: Vec<IterationInfo>,
+}
+
+/// Snapshot of the search after one iteration of iterative deepening.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IterationInfo {
+    pub depth: i32,
+    pub best_move: BitMove,
+    pub score: f64,
+    pub nodes: u64,
+    /// time since the search started
+    pub elapsed: Duration,
+}
+
+#[cfg(test)]
+mod search_limits_tests {
+    use super::*;
+
+    #[test]
+    fn test_time_budget() {
+        let params = AlphaBetaParams::default();
+
+        assert_eq!(
+            SearchLimits::default().time_budget(&params),
+            Some(params.max_time)
+        );
+
+        let infinite = SearchLimits {
+            infinite: true,
+            movetime: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert_eq!(infinite.time_budget(&params), None);
+
+        let clock = SearchLimits {
+            clock: Some(Clock {
+                time_left: Duration::from_secs(60),
+                increment: Duration::from_secs(0),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(clock.time_budget(&params), Some(Duration::from_secs(2)));
+
+        // never use more time than we have left
+        let low_clock = SearchLimits {
+            clock: Some(Clock {
+                time_left: Duration::from_millis(100),
+                increment: Duration::from_secs(1),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            low_clock.time_budget(&params),
+            Some(Duration::from_millis(50))
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use crate::engine::eval_params::piece_index;
+
+use pleco::{BitBoard, BitMove, Board, PieceType, Player, SQ};
+
+/// Piece values the exchanges are counted in. Taking the king ends the game,
+/// so it's worth more than everything else put together.
+pub const SEE_VALUES: [f64; 6] = [100.0, 300.0, 300.0, 500.0, 900.0, 10000.0];
+
+pub fn see_value(piece_type: PieceType) -> f64 {
+    match piece_index(piece_type) {
+        Some(ind) => SEE_VALUES[ind],
+        None => 0.0,
+    }
+}
+
+/// Static exchange evaluation: how much material the side playing `m` ends up
+/// with once every capture on the destination square has been played out.
+/// Anything that isn't a capture is 0.
+///
+/// In regular chess either side can stop taking back once it would lose
+/// material, here captures are forced so the other side has to recapture
+/// whenever it can. The exchange only ends when one side runs out of
+/// attackers. Captures elsewhere on the board, pins and checks are ignored.
+pub fn see(board: &Board, m: BitMove) -> f64 {
+    if !m.is_capture() {
+        return 0.0;
+    }
+
+    let src = m.get_src();
+    let dest = m.get_dest();
+
+    if m.is_en_passant() {
+        // the pawn that gets taken is next to us, not on the square we go to
+        let captured_sq = SQ((src.0 & !7) | (dest.0 & 7));
+        let occupied = board.occupied() ^ captured_sq.to_bb();
+        return exchange(board, src, dest, PieceType::P, occupied);
+    }
+
+    exchange(
+        board,
+        src,
+        dest,
+        board.piece_at_sq(dest).type_of(),
+        board.occupied(),
+    )
+}
+
+/// The same as `see` for the piece on `from` taking the one on `to`, whoever's
+/// move it is. Both squares have to have a piece on them.
+pub fn see_capture(board: &Board, from: SQ, to: SQ) -> f64 {
+    exchange(
+        board,
+        from,
+        to,
+        board.piece_at_sq(to).type_of(),
+        board.occupied(),
+    )
+}
+
+fn exchange(board: &Board, from: SQ, to: SQ, victim: PieceType, occupied: BitBoard) -> f64 {
+    let (us, mut on_square) = board.piece_at_sq(from).player_piece_lossy();
+    let mut side = us;
+    let mut occupied = occupied ^ from.to_bb();
+
+    let mut score = see_value(victim) + promotion_gain(on_square, to);
+    on_square = promoted(on_square, to);
+
+    loop {
+        side = side.other_player();
+
+        let attackers = board.attackers_to(to, occupied) & occupied;
+        let (sq, attacker) = match least_valuable(board, attackers, side) {
+            Some(lva) => lva,
+            None => break,
+        };
+
+        // the king can't take a piece that is still defended
+        if attacker == PieceType::K {
+            let without_king = occupied ^ sq.to_bb();
+            let defenders = board.attackers_to(to, without_king)
+                & without_king
+                & board.get_occupied_player(side.other_player());
+            if defenders.is_not_empty() {
+                break;
+            }
+        }
+
+        let sign = if side == us { 1.0 } else { -1.0 };
+        score += sign * (see_value(on_square) + promotion_gain(attacker, to));
+
+        if on_square == PieceType::K {
+            break;
+        }
+
+        occupied ^= sq.to_bb();
+        on_square = promoted(attacker, to);
+    }
+
+    score
+}
+
+fn least_valuable(board: &Board, attackers: BitBoard, side: Player) -> Option<(SQ, PieceType)> {
+    [
+        PieceType::P,
+        PieceType::N,
+        PieceType::B,
+        PieceType::R,
+        PieceType::Q,
+        PieceType::K,
+    ]
+    .into_iter()
+    .find_map(|piece_type| {
+        let bb = attackers & board.piece_bb(side, piece_type);
+        if bb.is_empty() {
+            None
+        } else {
+            Some((bb.bit_scan_forward(), piece_type))
+        }
+    })
+}
+
+fn is_last_rank(sq: SQ) -> bool {
+    sq.rank_idx_of_sq() == 0 || sq.rank_idx_of_sq() == 7
+}
+
+/// pawns that take on the last rank always promote to a queen here
+fn promoted(piece_type: PieceType, to: SQ) -> PieceType {
+    if piece_type == PieceType::P && is_last_rank(to) {
+        PieceType::Q
+    } else {
+        piece_type
+    }
+}
+
+fn promotion_gain(piece_type: PieceType, to: SQ) -> f64 {
+    see_value(promoted(piece_type, to)) - see_value(piece_type)
+}
+
+#[cfg(test)]
+mod see_tests {
+    use super::*;
+
+    fn see_of(fen: &str, text: &str) -> f64 {
+        let board = Board::from_fen(fen).unwrap();
+        let m = board
+            .generate_moves()
+            .into_iter()
+            .find(|m| m.stringify() == text)
+            .unwrap();
+
+        see(&board, m)
+    }
+
+    #[test]
+    fn test_forced_recaptures() {
+        // regular chess would stop after Nxd5, black has to take back here
+        let fen = "3q3k/8/8/3p4/4P3/2N5/8/7K w - - 0 1";
+        assert_eq!(see_of(fen, "c3d5"), 100.0 - 300.0 + 900.0);
+        assert_eq!(see_of(fen, "e4d5"), 100.0 - 100.0 + 900.0);
+
+        // nothing defends it
+        assert_eq!(see_of("7k/8/8/3n4/4P3/8/8/7K w - - 0 1", "e4d5"), 300.0);
+    }
+
+    #[test]
+    fn test_king_and_xrays() {
+        // the rook behind the queen stops the king from taking back
+        assert_eq!(see_of("8/8/4k3/3p4/3Q4/8/8/3R3K w - - 0 1", "d4d5"), 100.0);
+        assert_eq!(
+            see_of("8/8/4k3/3p4/3Q4/8/8/7K w - - 0 1", "d4d5"),
+            100.0 - 900.0
+        );
+    }
+
+    #[test]
+    fn test_quiet_move() {
+        assert_eq!(see_of(Board::start_pos().fen().as_str(), "e2e4"), 0.0);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::engine::tablebase::{Tablebase, Wdl};
+use crate::engine::valid_moves;
+
+use pleco::{BitMove, Board, Player};
+
+use std::time::{Duration, Instant};
+
+/// proof and disproof numbers never go above this
+const INF: u32 = u32::MAX;
+
+/// longest line we report for a proven win
+const MAX_LINE: usize = 128;
+
+/// What the solver found out about a position, for the side to move
+#[derive(Clone, Debug, PartialEq)]
+pub enum Solution {
+    /// a forced win, with the line the solver found. The first move is the
+    /// one to play, the defence is just one of the ways to lose.
+    Win(Vec<BitMove>),
+    /// every move loses against the right reply
+    Loss,
+    /// neither side has a forced win that the solver found in its budget
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolveResult {
+    pub solution: Solution,
+    /// number of positions the solver looked at
+    pub nodes: u64,
+}
+
+struct Node {
+    /// the move from the parent, None for the root
+    chess_move: Option<BitMove>,
+    parent: usize,
+    children: Vec<usize>,
+    /// whether the attacker is to move here, so a single proven child proves it
+    attacker_to_move: bool,
+    /// how many leaves still have to be proven to prove this node
+    proof: u32,
+    /// how many leaves still have to be disproven to disprove it
+    disproof: u32,
+    expanded: bool,
+}
+
+/// Proof-number search: grows a tree towards the leaves that are cheapest to
+/// prove or disprove a win for `attacker` with. Positions where the defender
+/// has few moves are cheap to prove, which with forced captures finds long,
+/// narrow wins the alpha beta search can't get deep enough for.
+struct ProofSearch<'a> {
+    nodes: Vec<Node>,
+    attacker: Player,
+    tablebase: Option<&'a Tablebase>,
+}
+
+impl<'a> ProofSearch<'a> {
+    fn new(board: &Board, attacker: Player, tablebase: Option<&'a Tablebase>) -> Self {
+        let mut search = ProofSearch {
+            nodes: Vec::new(),
+            attacker,
+            tablebase,
+        };
+
+        let (proof, disproof) = search.initial_numbers(board, &[]);
+        search.nodes.push(Node {
+            chess_move: None,
+            parent: 0,
+            children: Vec::new(),
+            attacker_to_move: board.turn() == attacker,
+            proof,
+            disproof,
+            expanded: false,
+        });
+
+        // the tablebase can settle the root without a single move, but we
+        // still need the move that wins
+        if !board.checkmate() && !board.stalemate() {
+            search.expand(0, &mut board.clone(), &[board.zobrist()]);
+            search.update_ancestors(0);
+        }
+
+        search
+    }
+
+    /// Searches until the root is proven or disproven, the tree has
+    /// `max_nodes` nodes or `deadline` has passed. Returns whether the
+    /// attacker wins, if we know.
+    fn run(&mut self, root: &Board, max_nodes: usize, deadline: Option<Instant>) -> Option<bool> {
+        while self.nodes[0].proof != 0
+            && self.nodes[0].disproof != 0
+            && self.nodes.len() < max_nodes
+            && deadline.is_none_or(|deadline| Instant::now() < deadline)
+        {
+            let mut board = root.clone();
+            let mut path = vec![board.zobrist()];
+
+            // walk down to the most proving leaf
+            let mut node = 0;
+            while self.nodes[node].expanded {
+                node = self.most_proving_child(node);
+                board.apply_move(self.nodes[node].chess_move.unwrap());
+                path.push(board.zobrist());
+            }
+
+            self.expand(node, &mut board, &path);
+            self.update_ancestors(node);
+        }
+
+        match (self.nodes[0].proof, self.nodes[0].disproof) {
+            (0, _) => Some(true),
+            (_, 0) => Some(false),
+            _ => None,
+        }
+    }
+
+    fn most_proving_child(&self, node: usize) -> usize {
+        let children = &self.nodes[node].children;
+
+        if self.nodes[node].attacker_to_move {
+            *children
+                .iter()
+                .min_by_key(|c| self.nodes[**c].proof)
+                .unwrap()
+        } else {
+            *children
+                .iter()
+                .min_by_key(|c| self.nodes[**c].disproof)
+                .unwrap()
+        }
+    }
+
+    fn expand(&mut self, node: usize, board: &mut Board, path: &[u64]) {
+        for m in valid_moves(board) {
+            board.apply_move(m);
+            let (proof, disproof) = self.initial_numbers(board, path);
+
+            self.nodes.push(Node {
+                chess_move: Some(m),
+                parent: node,
+                children: Vec::new(),
+                attacker_to_move: board.turn() == self.attacker,
+                proof,
+                disproof,
+                expanded: false,
+            });
+            let child = self.nodes.len() - 1;
+            self.nodes[node].children.push(child);
+
+            board.undo_move();
+        }
+
+        self.nodes[node].expanded = true;
+    }
+
+    /// Proof and disproof numbers of a position we haven't searched yet. Game
+    /// overs and tablebase positions are settled right away, everything else
+    /// starts off as hard as the number of moves the side to move has, since
+    /// all of them have to be answered.
+    fn initial_numbers(&self, board: &Board, path: &[u64]) -> (u32, u32) {
+        const PROVEN: (u32, u32) = (0, INF);
+        const DISPROVEN: (u32, u32) = (INF, 0);
+
+        let attacker_to_move = board.turn() == self.attacker;
+
+        if board.checkmate() {
+            return if attacker_to_move { DISPROVEN } else { PROVEN };
+        }
+
+        // a draw is as good as a loss when we are looking for a win
+        if board.stalemate() || board.rule_50() >= 100 || path.contains(&board.zobrist()) {
+            return DISPROVEN;
+        }
+
+        if let Some(probe) = self.tablebase.and_then(|tb| tb.probe(board)) {
+            return match (probe.wdl, attacker_to_move) {
+                (Wdl::Win, true) | (Wdl::Loss, false) => PROVEN,
+                _ => DISPROVEN,
+            };
+        }
+
+        let moves = valid_moves(board).len() as u32;
+        if attacker_to_move {
+            (1, moves)
+        } else {
+            (moves, 1)
+        }
+    }
+
+    fn update_ancestors(&mut self, mut node: usize) {
+        loop {
+            let children = &self.nodes[node].children;
+
+            let (proof, disproof) = if self.nodes[node].attacker_to_move {
+                (
+                    children
+                        .iter()
+                        .map(|c| self.nodes[*c].proof)
+                        .min()
+                        .unwrap_or(INF),
+                    sum(children.iter().map(|c| self.nodes[*c].disproof)),
+                )
+            } else {
+                (
+                    sum(children.iter().map(|c| self.nodes[*c].proof)),
+                    children
+                        .iter()
+                        .map(|c| self.nodes[*c].disproof)
+                        .min()
+                        .unwrap_or(INF),
+                )
+            };
+
+            self.nodes[node].proof = proof;
+            self.nodes[node].disproof = disproof;
+
+            if node == 0 {
+                break;
+            }
+            node = self.nodes[node].parent;
+        }
+    }
+
+    /// One line through the proof tree of a proven root. When the root is in
+    /// the tablebase the first move is one of its `root_moves`, so we don't
+    /// pick a slower win than it knows.
+    fn winning_line(&self, root: &Board) -> Vec<BitMove> {
+        let best = self
+            .tablebase
+            .and_then(|tb| tb.root_moves(root, &valid_moves(root)));
+
+        let mut line = Vec::new();
+        let mut node = 0;
+
+        while self.nodes[node].expanded && line.len() < MAX_LINE {
+            let children = &self.nodes[node].children;
+            let next = if self.nodes[node].attacker_to_move {
+                let mut proven = children.iter().filter(|c| self.nodes[**c].proof == 0);
+                match (&best, node) {
+                    (Some(best), 0) => proven
+                        .clone()
+                        .find(|c| best.contains(&self.nodes[**c].chess_move.unwrap()))
+                        .or_else(|| proven.next()),
+                    _ => proven.next(),
+                }
+            } else {
+                // every defence loses, show the one that puts up the most fight
+                children.iter().max_by_key(|c| self.subtree_size(**c))
+            };
+
+            match next {
+                Some(next) => {
+                    node = *next;
+                    line.push(self.nodes[node].chess_move.unwrap());
+                }
+                None => break,
+            }
+        }
+
+        line
+    }
+
+    fn subtree_size(&self, node: usize) -> usize {
+        1 + self.nodes[node]
+            .children
+            .iter()
+            .map(|c| self.subtree_size(*c))
+            .sum::<usize>()
+    }
+}
+
+fn sum(numbers: impl Iterator<Item = u32>) -> u32 {
+    numbers.fold(0, |total, n| total.saturating_add(n))
+}
+
+/// Tries to prove a forced win or loss for the side to move, looking at about
+/// `max_nodes` positions (the last expansion can go a little over) and taking
+/// at most about `max_time`. Half of both goes to looking for a win, the rest
+/// to looking for a loss.
+pub fn solve(
+    board: &Board,
+    max_nodes: u64,
+    max_time: Option<Duration>,
+    tablebase: Option<&Tablebase>,
+) -> SolveResult {
+    let start = Instant::now();
+    let mut nodes = 0;
+
+    let mut win = ProofSearch::new(board, board.turn(), tablebase);
+    let proven = win.run(
+        board,
+        (max_nodes / 2).max(1) as usize,
+        max_time.map(|time| start + time / 2),
+    );
+    nodes += win.nodes.len() as u64;
+
+    if proven == Some(true) {
+        return SolveResult {
+            solution: Solution::Win(win.winning_line(board)),
+            nodes,
+        };
+    }
+    drop(win);
+
+    let mut loss = ProofSearch::new(board, board.turn().other_player(), tablebase);
+    let budget = max_nodes.saturating_sub(nodes).max(1) as usize;
+    let proven = loss.run(board, budget, max_time.map(|time| start + time));
+    nodes += loss.nodes.len() as u64;
+
+    SolveResult {
+        solution: if proven == Some(true) {
+            Solution::Loss
+        } else {
+            Solution::Unknown
+        },
+        nodes,
+    }
+}
+
+#[cfg(test)]
+mod solver_tests {
+    use super::*;
+    use crate::engine::search::SearchLimits;
+    use crate::engine::Engine;
+
+    fn solve_fen(fen: &str, max_nodes: u64) -> SolveResult {
+        solve(&Board::from_fen(fen).unwrap(), max_nodes, None, None)
+    }
+
+    #[test]
+    fn test_mate_in_one() {
+        let res = solve_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1", 1000);
+
+        match res.solution {
+            Solution::Win(line) => assert_eq!(line[0].stringify(), "h1h8"),
+            other => panic!("expected a win, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_forced_loss() {
+        // Kb8 is the only move, then Rh8 mates
+        let res = solve_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1", 1000);
+        assert_eq!(res.solution, Solution::Loss);
+
+        // nobody wins in the starting position any time soon
+        let res = solve_fen(&Board::start_pos().fen(), 2000);
+        assert_eq!(res.solution, Solution::Unknown);
+        assert!(res.nodes <= 2000 + 50);
+    }
+
+    #[test]
+    fn test_search_calls_solver() {
+        let board = Board::from_fen("k7/8/1K6/8/8/8/8/7R b - - 0 1").unwrap();
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.params.solve_nodes = 1000;
+
+        // one ply only sees that black is a rook down, the solver sees the mate
+        let limits = SearchLimits {
+            depth: Some(2),
+            infinite: true,
+            ..Default::default()
+        };
+        let res = engine.search(&board, &limits).unwrap();
+        assert_eq!(res.score, f64::NEG_INFINITY);
+    }
+}
@@ -1,8 +1,13 @@
+use crate::engine::eval_state::EvalState;
+use crate::engine::nnue::Accumulator;
+use crate::engine::pawns::PawnTable;
+use crate::error::ChessError;
+
 use anyhow::Result;
 
 use pleco::{BitMove, Board};
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use std::collections::{HashMap, HashSet};
 
@@ -36,6 +41,37 @@ pub struct AlphaBetaStore {
     pub pv: Vec<BitMove>,
 
     pub total_search_time_ms: u128,
+
+    /// how long the current search may run for, `None` for no limit
+    pub time_limit: Option<Duration>,
+
+    /// how many nodes the current search may visit, `None` for no limit
+    pub node_limit: Option<u64>,
+
+    /// number of nodes visited during the current search
+    pub nodes: u64,
+
+    /// number of those nodes that were past the nominal depth of the iteration,
+    /// only reachable through the forced move extensions. This is the closest
+    /// thing we have to a quiescence search.
+    pub qnodes: u64,
+
+    /// number of positions the search found in the tablebase
+    pub tb_hits: u64,
+
+    /// ply of the board the current search started from
+    pub root_ply: u16,
+
+    /// the network accumulator for every board on the current search path,
+    /// only used when the engine has a network
+    pub accumulators: Vec<Accumulator>,
+
+    /// the incremental eval state for every board on the current search path
+    pub eval_states: Vec<EvalState>,
+
+    /// pawn structures seen so far, kept between searches since they don't
+    /// depend on anything but the pawns
+    pub pawn_table: PawnTable,
 }
 
 impl AlphaBetaStore {
@@ -46,6 +82,15 @@ impl AlphaBetaStore {
             transposition_table: HashMap::new(),
             pv: Vec::new(),
             total_search_time_ms: 0,
+            time_limit: None,
+            node_limit: None,
+            nodes: 0,
+            qnodes: 0,
+            tb_hits: 0,
+            root_ply: 0,
+            accumulators: Vec::new(),
+            eval_states: Vec::new(),
+            pawn_table: PawnTable::new(),
         }
     }
     pub fn store_transposition(
@@ -93,9 +138,39 @@ impl AlphaBetaStore {
 
     pub fn start_turn(&mut self) {
         self.transposition_table.clear();
+        self.nodes = 0;
+        self.qnodes = 0;
+        self.tb_hits = 0;
         self.start_time = Some(Instant::now());
     }
 
+    /// Checks whether the current search has used up its time or node budget.
+    /// The first iteration is always allowed to finish so we have a move to play.
+    pub fn limit_reached(&self) -> Result<bool> {
+        let start_time = match self.start_time {
+            Some(t) => t,
+            None => return Err(ChessError::NoStartTime.into()),
+        };
+
+        if self.curr_depth <= 1 {
+            return Ok(false);
+        }
+
+        if let Some(time_limit) = self.time_limit {
+            if start_time.elapsed() > time_limit {
+                return Ok(true);
+            }
+        }
+
+        if let Some(node_limit) = self.node_limit {
+            if self.nodes >= node_limit {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     pub fn end_turn(&mut self) {
         self.start_time = None;
     }
@@ -145,6 +220,9 @@ impl AlphaBetaStore {
             transpo = self.get_transposition(board);
         }
 
+        // drop whatever is left over from a longer line in an earlier iteration
+        self.pv.truncate(move_ind);
+
         for _ in 0..move_ind {
             board.undo_move();
         }
@@ -0,0 +1,1241 @@
+use crate::engine::valid_moves;
+
+use anyhow::{anyhow, bail, Result};
+use pleco::{core::CastleType, BitMove, Board, Piece, PieceType, Player, SQ};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Every table file starts with this, followed by a version byte and the
+/// kind of file, `W` for win/draw/loss or `D` for distance to mate
+pub const MAGIC: &[u8; 4] = b"ACTB";
+pub const VERSION: u8 = 1;
+
+/// most pieces a table can have, kings included
+pub const MAX_PIECES: usize = 5;
+
+/// What the search scores a won tablebase position, less the distance to
+/// mate so it still makes progress. Mates the search finds itself stay at
+/// infinity.
+pub const TB_WIN_SCORE: f64 = 50_000.0;
+
+const LOSS: u8 = 0;
+const DRAW: u8 = 1;
+const WIN: u8 = 2;
+const INVALID: u8 = 3;
+/// only used while generating
+const UNKNOWN: u8 = 4;
+
+/// piece types in the order they're listed in table names
+const ORDER: [PieceType; 5] = [
+    PieceType::Q,
+    PieceType::R,
+    PieceType::B,
+    PieceType::N,
+    PieceType::P,
+];
+
+/// the squares the white king is moved to in tables without pawns, a1-d1-d4
+const TRIANGLE: [u8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wdl {
+    Loss,
+    Draw,
+    Win,
+}
+
+/// What the tablebase knows about a position, for the side to move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+    pub wdl: Wdl,
+    /// plies until the losing side is checkmated, 0 for draws. Only there
+    /// if the distance to mate file was loaded.
+    pub dtm: Option<u16>,
+}
+
+impl Probe {
+    /// search score from the side to move's point of view
+    pub fn score(&self) -> f64 {
+        let dtm = self.dtm.unwrap_or(0) as f64;
+        match self.wdl {
+            Wdl::Win => TB_WIN_SCORE - dtm,
+            Wdl::Draw => 0.0,
+            Wdl::Loss => -(TB_WIN_SCORE - dtm),
+        }
+    }
+
+    /// The result of a position given the results of every position its
+    /// moves lead to, which are from the opponent's point of view
+    fn from_children(children: impl Iterator<Item = Probe>) -> Probe {
+        let mut best: Option<Probe> = None;
+
+        for child in children {
+            let ours = child.negate();
+            if best.is_none_or(|b| ours.rank() > b.rank()) {
+                best = Some(ours);
+            }
+        }
+
+        best.unwrap_or(Probe {
+            wdl: Wdl::Draw,
+            dtm: Some(0),
+        })
+    }
+
+    /// the same position from the other side, one ply earlier
+    fn negate(&self) -> Probe {
+        let wdl = match self.wdl {
+            Wdl::Win => Wdl::Loss,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::Loss => Wdl::Win,
+        };
+        let dtm = match wdl {
+            Wdl::Draw => self.dtm.map(|_| 0),
+            _ => self.dtm.map(|d| d + 1),
+        };
+
+        Probe { wdl, dtm }
+    }
+
+    /// higher is better, quicker wins and slower losses first
+    fn rank(&self) -> (u8, i32) {
+        let dtm = self.dtm.unwrap_or(0) as i32;
+        match self.wdl {
+            Wdl::Win => (2, -dtm),
+            Wdl::Draw => (1, 0),
+            Wdl::Loss => (0, dtm),
+        }
+    }
+}
+
+/// The pieces of a table besides the two kings, strongest first for both sides
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub white: Vec<PieceType>,
+    pub black: Vec<PieceType>,
+}
+
+impl Material {
+    /// Parses a table name like `KRvKP`
+    pub fn parse(name: &str) -> Result<Material> {
+        let (white, black) = name
+            .split_once('v')
+            .ok_or_else(|| anyhow!("table names look like KRvK, got {}", name))?;
+
+        let side = |text: &str| -> Result<Vec<PieceType>> {
+            let pieces = text
+                .strip_prefix('K')
+                .ok_or_else(|| anyhow!("both sides of {} need a king", name))?;
+
+            let mut pieces = pieces
+                .chars()
+                .map(|c| match c {
+                    'Q' => Ok(PieceType::Q),
+                    'R' => Ok(PieceType::R),
+                    'B' => Ok(PieceType::B),
+                    'N' => Ok(PieceType::N),
+                    'P' => Ok(PieceType::P),
+                    _ => Err(anyhow!("unknown piece {} in {}", c, name)),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            sort_pieces(&mut pieces);
+
+            Ok(pieces)
+        };
+
+        let material = Material {
+            white: side(white)?,
+            black: side(black)?,
+        };
+
+        if material.piece_count() > MAX_PIECES {
+            bail!("{} has more than {} pieces", name, MAX_PIECES);
+        }
+
+        Ok(material)
+    }
+
+    pub fn name(&self) -> String {
+        let side = |pieces: &[PieceType]| -> String {
+            std::iter::once('K')
+                .chain(pieces.iter().map(|p| piece_char(*p)))
+                .collect()
+        };
+
+        format!("{}v{}", side(&self.white), side(&self.black))
+    }
+
+    /// every piece on the board, kings included
+    pub fn piece_count(&self) -> usize {
+        2 + self.white.len() + self.black.len()
+    }
+
+    pub fn flipped(&self) -> Material {
+        Material {
+            white: self.black.clone(),
+            black: self.white.clone(),
+        }
+    }
+
+    /// The way round the table is stored, with the stronger side as white
+    pub fn canonical(&self) -> Material {
+        let strength = |pieces: &[PieceType]| {
+            (
+                pieces.len(),
+                pieces
+                    .iter()
+                    .map(|p| 5 - ORDER.iter().position(|o| o == p).unwrap_or(5))
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        if strength(&self.black) > strength(&self.white) {
+            self.flipped()
+        } else {
+            self.clone()
+        }
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.white.contains(&PieceType::P) || self.black.contains(&PieceType::P)
+    }
+
+    /// the pieces in the order their squares are in the index, without the kings
+    fn pieces(&self) -> Vec<Piece> {
+        self.white
+            .iter()
+            .map(|p| Piece::make_lossy(Player::White, *p))
+            .chain(
+                self.black
+                    .iter()
+                    .map(|p| Piece::make_lossy(Player::Black, *p)),
+            )
+            .collect()
+    }
+
+    /// number of squares the white king can be on in the index
+    fn king_squares(&self) -> usize {
+        if self.has_pawns() {
+            32
+        } else {
+            TRIANGLE.len()
+        }
+    }
+
+    /// number of positions in the table, valid or not
+    fn size(&self) -> usize {
+        2 * self.king_squares() * 64 * 64usize.pow((self.piece_count() - 2) as u32)
+    }
+
+    /// Every material a single move can lead to: a capture, a promotion, or
+    /// both. Always in the way round the tables are stored.
+    fn reachable(&self) -> Vec<Material> {
+        let mut out = Vec::new();
+
+        for (us, them, flip) in [
+            (&self.white, &self.black, false),
+            (&self.black, &self.white, true),
+        ] {
+            let mut next = Vec::new();
+
+            for ind in 0..them.len() {
+                let mut captured = them.clone();
+                captured.remove(ind);
+                next.push((us.clone(), captured));
+            }
+
+            if let Some(pawn) = us.iter().position(|p| *p == PieceType::P) {
+                for promo in [PieceType::Q, PieceType::R, PieceType::B, PieceType::N] {
+                    let mut promoted = us.clone();
+                    promoted[pawn] = promo;
+                    sort_pieces(&mut promoted);
+
+                    next.push((promoted.clone(), them.clone()));
+                    for ind in 0..them.len() {
+                        let mut captured = them.clone();
+                        captured.remove(ind);
+                        next.push((promoted.clone(), captured));
+                    }
+                }
+            }
+
+            for (us, them) in next {
+                let material = if flip {
+                    Material {
+                        white: them,
+                        black: us,
+                    }
+                } else {
+                    Material {
+                        white: us,
+                        black: them,
+                    }
+                };
+                out.push(material.canonical());
+            }
+        }
+
+        out
+    }
+}
+
+/// Every material with at most `max_pieces` pieces and more than the two
+/// kings, each once in the way round it's stored
+pub fn all_materials(max_pieces: usize) -> Vec<Material> {
+    let kinds: Vec<(Player, PieceType)> = [Player::White, Player::Black]
+        .into_iter()
+        .flat_map(|player| ORDER.into_iter().map(move |p| (player, p)))
+        .collect();
+
+    // every multiset of kinds, as lists of indices that never go down
+    let mut sets: Vec<Vec<usize>> = vec![Vec::new()];
+    let mut out: Vec<Material> = Vec::new();
+
+    for _ in 2..max_pieces.min(MAX_PIECES) {
+        let mut next = Vec::new();
+        for set in &sets {
+            for kind in set.last().copied().unwrap_or(0)..kinds.len() {
+                let mut set = set.clone();
+                set.push(kind);
+
+                let side = |player: Player| {
+                    set.iter()
+                        .map(|ind| kinds[*ind])
+                        .filter(|(p, _)| *p == player)
+                        .map(|(_, piece_type)| piece_type)
+                        .collect()
+                };
+                let material = Material {
+                    white: side(Player::White),
+                    black: side(Player::Black),
+                }
+                .canonical();
+
+                if !out.contains(&material) {
+                    out.push(material);
+                }
+                next.push(set);
+            }
+        }
+        sets = next;
+    }
+
+    out
+}
+
+fn piece_char(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Q => 'Q',
+        PieceType::R => 'R',
+        PieceType::B => 'B',
+        PieceType::N => 'N',
+        PieceType::P => 'P',
+        _ => 'K',
+    }
+}
+
+fn sort_pieces(pieces: &mut [PieceType]) {
+    pieces.sort_by_key(|p| ORDER.iter().position(|o| o == p));
+}
+
+/// Where the pieces are: the white king, the black king, and then the rest
+/// in the same order as `Material::pieces`
+#[derive(Clone, Debug)]
+struct Placement {
+    turn: Player,
+    pieces: Vec<(Piece, SQ)>,
+}
+
+impl Placement {
+    fn from_board(board: &Board) -> Placement {
+        let mut pieces = Vec::new();
+        for (sq, piece) in board.get_piece_locations() {
+            if piece != Piece::None {
+                pieces.push((piece, sq));
+            }
+        }
+        pieces.sort_by_key(|(piece, sq)| placement_order(*piece, *sq));
+
+        Placement {
+            turn: board.turn(),
+            pieces,
+        }
+    }
+
+    fn material(&self) -> Material {
+        let side = |player: Player| {
+            self.pieces
+                .iter()
+                .filter(|(piece, _)| piece.player() == Some(player))
+                .map(|(piece, _)| piece.type_of())
+                .filter(|p| *p != PieceType::K)
+                .collect()
+        };
+
+        Material {
+            white: side(Player::White),
+            black: side(Player::Black),
+        }
+    }
+
+    /// the same position with the colors swapped and the board turned around
+    fn flipped(&self) -> Placement {
+        let mut pieces: Vec<(Piece, SQ)> = self
+            .pieces
+            .iter()
+            .map(|(piece, sq)| {
+                let (player, piece_type) = piece.player_piece_lossy();
+                (
+                    Piece::make_lossy(player.other_player(), piece_type),
+                    SQ(sq.0 ^ 56),
+                )
+            })
+            .collect();
+        pieces.sort_by_key(|(piece, sq)| placement_order(*piece, *sq));
+
+        Placement {
+            turn: self.turn.other_player(),
+            pieces,
+        }
+    }
+
+    /// Position of this placement in the table for `material`, which has to
+    /// be its material. The board is mirrored so the white king is on one
+    /// of `king_squares` squares.
+    fn index(&self, material: &Material) -> usize {
+        let pawns = material.has_pawns();
+        let wk = self.pieces[0].1;
+
+        let mirror_file = wk.file_idx_of_sq() > 3;
+        let mirror_rank = !pawns && wk.rank_idx_of_sq() > 3;
+        let transform = |sq: SQ| {
+            let mut file = sq.file_idx_of_sq();
+            let mut rank = sq.rank_idx_of_sq();
+            if mirror_file {
+                file = 7 - file;
+            }
+            if mirror_rank {
+                rank = 7 - rank;
+            }
+            (file, rank)
+        };
+
+        let (wk_file, wk_rank) = transform(wk);
+        let transpose = !pawns && wk_rank > wk_file;
+        let square = |sq: SQ| {
+            let (file, rank) = transform(sq);
+            if transpose {
+                (file * 8 + rank) as usize
+            } else {
+                (rank * 8 + file) as usize
+            }
+        };
+
+        let king = if pawns {
+            wk_rank as usize * 4 + wk_file as usize
+        } else {
+            let wk = square(wk) as u8;
+            TRIANGLE.iter().position(|s| *s == wk).unwrap()
+        };
+
+        let mut ind = self.turn as usize * material.king_squares() + king;
+        for (_, sq) in &self.pieces[1..] {
+            ind = ind * 64 + square(*sq);
+        }
+
+        ind
+    }
+
+    /// The placement at `ind` in the table for `material`, if it's a real
+    /// position. Whether the side not to move is in check is only known once
+    /// it's a board.
+    fn decode(material: &Material, mut ind: usize) -> Option<Placement> {
+        let others = material.pieces();
+
+        let mut squares = vec![0u8; others.len() + 1];
+        for sq in squares.iter_mut().rev() {
+            *sq = (ind % 64) as u8;
+            ind /= 64;
+        }
+
+        let king = ind % material.king_squares();
+        let turn = if ind / material.king_squares() == 0 {
+            Player::White
+        } else {
+            Player::Black
+        };
+
+        let wk = if material.has_pawns() {
+            (king / 4 * 8 + king % 4) as u8
+        } else {
+            TRIANGLE[king]
+        };
+
+        let mut pieces = vec![
+            (Piece::WhiteKing, SQ(wk)),
+            (Piece::BlackKing, SQ(squares[0])),
+        ];
+        pieces.extend(others.into_iter().zip(squares[1..].iter().map(|s| SQ(*s))));
+
+        for (ind, (piece, sq)) in pieces.iter().enumerate() {
+            if pieces[..ind].iter().any(|(_, other)| other == sq) {
+                return None;
+            }
+            if piece.type_of() == PieceType::P
+                && (sq.rank_idx_of_sq() == 0 || sq.rank_idx_of_sq() == 7)
+            {
+                return None;
+            }
+        }
+
+        let (wk, bk) = (pieces[0].1, pieces[1].1);
+        let file_gap = (wk.file_idx_of_sq() as i8 - bk.file_idx_of_sq() as i8).abs();
+        let rank_gap = (wk.rank_idx_of_sq() as i8 - bk.rank_idx_of_sq() as i8).abs();
+        if file_gap <= 1 && rank_gap <= 1 {
+            return None;
+        }
+
+        Some(Placement { turn, pieces })
+    }
+
+    fn to_board(&self) -> Option<Board> {
+        let mut squares = [None; 64];
+        for (piece, sq) in &self.pieces {
+            squares[sq.0 as usize] = piece.character();
+        }
+
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match squares[rank * 8 + file] {
+                    Some(c) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(c);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+
+        let turn = if self.turn == Player::White { "w" } else { "b" };
+        let board = Board::from_fen(&format!("{} {} - - 0 1", placement, turn)).ok()?;
+
+        // the side that just moved can't have left its king in check
+        let their_king = board.king_sq(self.turn.other_player());
+        let checkers =
+            board.attackers_to(their_king, board.occupied()) & board.get_occupied_player(self.turn);
+        if checkers.is_not_empty() {
+            return None;
+        }
+
+        Some(board)
+    }
+}
+
+/// kings first, then every other piece by color, type and square
+fn placement_order(piece: Piece, sq: SQ) -> (usize, usize, u8) {
+    let (player, piece_type) = piece.player_piece_lossy();
+
+    if piece_type == PieceType::K {
+        return (0, player as usize, sq.0);
+    }
+
+    let type_order = ORDER.iter().position(|p| *p == piece_type).unwrap_or(5);
+    (1 + player as usize, type_order, sq.0)
+}
+
+/// One material signature's results, one entry per index
+#[derive(Clone)]
+struct Table {
+    material: Material,
+    wdl: Vec<u8>,
+    /// empty if the distance to mate file wasn't loaded
+    dtm: Vec<u16>,
+}
+
+impl Table {
+    fn get(&self, ind: usize) -> Option<Probe> {
+        let wdl = match *self.wdl.get(ind)? {
+            LOSS => Wdl::Loss,
+            DRAW => Wdl::Draw,
+            WIN => Wdl::Win,
+            _ => return None,
+        };
+
+        Some(Probe {
+            wdl,
+            dtm: self.dtm.get(ind).copied(),
+        })
+    }
+}
+
+/// How a position's moves that leave its table turn out
+#[derive(Clone, Copy, Default)]
+struct Exits {
+    /// quickest loss for the opponent we can move into
+    min_loss: Option<u16>,
+    /// slowest win for the opponent we can move into
+    max_win: Option<u16>,
+    has_draw: bool,
+}
+
+/// Win/draw/loss and distance to mate tables for endgames with few pieces,
+/// under our rules: captures are forced, checkmate wins and stalemate is a
+/// draw. Positions with castling rights aren't in the tables, and neither
+/// is the 50 move rule.
+#[derive(Clone, Default)]
+pub struct Tablebase {
+    tables: HashMap<String, Table>,
+    max_pieces: usize,
+}
+
+impl Tablebase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// names of the tables we have, smallest first
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<&Table> = self.tables.values().collect();
+        names.sort_by_key(|t| (t.material.piece_count(), t.material.name()));
+        names.iter().map(|t| t.material.name()).collect()
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// The result of the board for the side to move, if we have a table for it
+    pub fn probe(&self, board: &Board) -> Option<Probe> {
+        // bare kings are always a draw, we don't need a table for that
+        if board.occupied().count_bits() as usize > self.max_pieces.max(2) {
+            return None;
+        }
+
+        for player in [Player::White, Player::Black] {
+            for castle in [CastleType::KingSide, CastleType::QueenSide] {
+                if board.can_castle(player, castle) {
+                    return None;
+                }
+            }
+        }
+
+        if board.ep_square() != SQ::NONE {
+            if let Some(probe) = self.probe_by_captures(board) {
+                return Some(probe);
+            }
+        }
+
+        self.probe_placement(&Placement::from_board(board))
+    }
+
+    /// The moves that keep the best result the tablebase knows of, quickest
+    /// mates first. None if any of them can't be probed.
+    pub fn root_moves(&self, board: &Board, moves: &[BitMove]) -> Option<Vec<BitMove>> {
+        self.probe(board)?;
+
+        let mut ranked = Vec::new();
+        for m in moves {
+            let mut next = board.clone();
+            next.apply_move(*m);
+
+            let ours = self.probe_after_move(&next)?.negate();
+            ranked.push((*m, ours.rank()));
+        }
+
+        let best = ranked.iter().map(|(_, rank)| *rank).max()?;
+
+        Some(
+            ranked
+                .into_iter()
+                .filter(|(_, rank)| *rank == best)
+                .map(|(m, _)| m)
+                .collect(),
+        )
+    }
+
+    fn probe_placement(&self, placement: &Placement) -> Option<Probe> {
+        let material = placement.material();
+        if material.piece_count() == 2 {
+            // two kings can never mate each other
+            return Some(Probe {
+                wdl: Wdl::Draw,
+                dtm: Some(0),
+            });
+        }
+
+        if let Some(table) = self.tables.get(&material.name()) {
+            return table.get(placement.index(&material));
+        }
+
+        let flipped = placement.flipped();
+        let table = self.tables.get(&material.flipped().name())?;
+        table.get(flipped.index(&table.material))
+    }
+
+    /// probes a board a move was just played on, which may be over already
+    fn probe_after_move(&self, board: &Board) -> Option<Probe> {
+        if board.checkmate() {
+            return Some(Probe {
+                wdl: Wdl::Loss,
+                dtm: Some(0),
+            });
+        }
+
+        self.probe(board)
+    }
+
+    /// The tables don't know about en passant, but when it's possible it's a
+    /// capture, and so is every other move. Those all lead to smaller tables.
+    fn probe_by_captures(&self, board: &Board) -> Option<Probe> {
+        let moves = valid_moves(board);
+        if moves.iter().any(|m| !board.is_capture(*m)) {
+            return None;
+        }
+
+        let mut children = Vec::new();
+        for m in moves {
+            let mut next = board.clone();
+            next.apply_move(m);
+            children.push(self.probe_after_move(&next)?);
+        }
+
+        Some(Probe::from_children(children.into_iter()))
+    }
+
+    /// Generates the table for `name` (e.g. `KRvK`) and every smaller table
+    /// it needs, skipping any we already have
+    pub fn generate(&mut self, name: &str) -> Result<()> {
+        let material = Material::parse(name)?.canonical();
+        self.generate_material(&material)
+    }
+
+    fn generate_material(&mut self, material: &Material) -> Result<()> {
+        if material.piece_count() == 2 || self.tables.contains_key(&material.name()) {
+            return Ok(());
+        }
+
+        for smaller in material.reachable() {
+            self.generate_material(&smaller)?;
+        }
+
+        let table = self.build_table(material)?;
+        self.insert(table);
+
+        Ok(())
+    }
+
+    fn insert(&mut self, table: Table) {
+        self.max_pieces = self.max_pieces.max(table.material.piece_count());
+        self.tables.insert(table.material.name(), table);
+    }
+
+    /// Retrograde analysis: first every position's moves are generated once,
+    /// moves that stay in the table as edges and ones that leave it looked
+    /// up in the smaller tables. Then positions are resolved one ply at a
+    /// time, a position is won in n if a move leads to a loss in n - 1, and
+    /// lost in n if every move leads to a win, the slowest in n - 1. Whatever
+    /// is left at the end is a draw.
+    fn build_table(&self, material: &Material) -> Result<Table> {
+        let size = material.size();
+
+        let mut wdl = vec![INVALID; size];
+        let mut dtm = vec![0u16; size];
+        let mut exits = vec![Exits::default(); size];
+        let mut offsets = vec![0usize; size + 1];
+        let mut edges: Vec<u32> = Vec::new();
+
+        for ind in 0..size {
+            offsets[ind] = edges.len();
+
+            let mut board = match Placement::decode(material, ind).and_then(|p| p.to_board()) {
+                Some(board) => board,
+                None => continue,
+            };
+
+            if board.checkmate() {
+                wdl[ind] = LOSS;
+                continue;
+            }
+            if board.stalemate() {
+                wdl[ind] = DRAW;
+                continue;
+            }
+
+            wdl[ind] = UNKNOWN;
+
+            for m in valid_moves(&board) {
+                board.apply_move(m);
+
+                let next = Placement::from_board(&board);
+                let by_captures = if board.ep_square() != SQ::NONE {
+                    self.probe_by_captures(&board)
+                } else {
+                    None
+                };
+
+                if by_captures.is_none() && next.material() == *material {
+                    edges.push(next.index(material) as u32);
+                } else {
+                    let child = by_captures
+                        .or_else(|| self.probe_after_move(&board))
+                        .ok_or_else(|| {
+                            anyhow!("no table for the position after {} in {}", m, board.fen())
+                        })?;
+
+                    let exit = &mut exits[ind];
+                    let dtm = child.dtm.unwrap_or(0);
+                    match child.wdl {
+                        Wdl::Loss => {
+                            exit.min_loss = Some(exit.min_loss.map_or(dtm, |d| d.min(dtm)))
+                        }
+                        Wdl::Win => exit.max_win = Some(exit.max_win.map_or(dtm, |d| d.max(dtm))),
+                        Wdl::Draw => exit.has_draw = true,
+                    }
+                }
+
+                board.undo_move();
+            }
+        }
+        offsets[size] = edges.len();
+
+        let last_exit = exits
+            .iter()
+            .flat_map(|e| [e.min_loss, e.max_win])
+            .flatten()
+            .max()
+            .unwrap_or(0);
+
+        let mut n: u16 = 1;
+        loop {
+            let mut changed = false;
+
+            for ind in 0..size {
+                if wdl[ind] != UNKNOWN {
+                    continue;
+                }
+
+                // only positions resolved in earlier passes count
+                let exit = exits[ind];
+                let mut win = exit.min_loss.is_some_and(|d| d < n);
+                let mut all_win =
+                    !exit.has_draw && exit.min_loss.is_none() && exit.max_win.is_none_or(|d| d < n);
+
+                for &next in &edges[offsets[ind]..offsets[ind + 1]] {
+                    let next = next as usize;
+                    match wdl[next] {
+                        LOSS if dtm[next] < n => win = true,
+                        WIN if dtm[next] < n => (),
+                        _ => all_win = false,
+                    }
+                }
+
+                if win {
+                    wdl[ind] = WIN;
+                    dtm[ind] = n;
+                    changed = true;
+                } else if all_win {
+                    wdl[ind] = LOSS;
+                    dtm[ind] = n;
+                    changed = true;
+                }
+            }
+
+            if !changed && n > last_exit {
+                break;
+            }
+            n += 1;
+        }
+
+        for w in wdl.iter_mut() {
+            if *w == UNKNOWN {
+                *w = DRAW;
+            }
+        }
+
+        Ok(Table {
+            material: material.clone(),
+            wdl,
+            dtm,
+        })
+    }
+
+    /// Checks every position of a table against the positions its moves
+    /// lead to, and returns how many real positions there are
+    pub fn verify(&self, name: &str) -> Result<u64> {
+        let material = Material::parse(name)?.canonical();
+        let table = self
+            .tables
+            .get(&material.name())
+            .ok_or_else(|| anyhow!("no table for {}", material.name()))?;
+
+        let mut checked = 0;
+
+        for ind in 0..material.size() {
+            let stored = table.get(ind);
+
+            let board = match Placement::decode(&material, ind).and_then(|p| p.to_board()) {
+                Some(board) => board,
+                None => {
+                    if stored.is_some() {
+                        bail!("{} has a result for impossible position {}", name, ind);
+                    }
+                    continue;
+                }
+            };
+
+            let expected = if board.checkmate() {
+                Probe {
+                    wdl: Wdl::Loss,
+                    dtm: Some(0),
+                }
+            } else {
+                let mut children = Vec::new();
+                for m in valid_moves(&board) {
+                    let mut next = board.clone();
+                    next.apply_move(m);
+                    children.push(
+                        self.probe_after_move(&next)
+                            .ok_or_else(|| anyhow!("can't probe {}", next.fen()))?,
+                    );
+                }
+                Probe::from_children(children.into_iter())
+            };
+
+            let stored = stored.ok_or_else(|| anyhow!("{} is missing {}", name, board.fen()))?;
+            let dtm_matches = stored.dtm.is_none() || stored.dtm == expected.dtm;
+            if stored.wdl != expected.wdl || !dtm_matches {
+                bail!(
+                    "{} has {:?} for {}, but its moves give {:?}",
+                    name,
+                    stored,
+                    board.fen(),
+                    expected
+                );
+            }
+
+            checked += 1;
+        }
+
+        Ok(checked)
+    }
+
+    /// Writes `<name>.wdl` and `<name>.dtm` for every table into `dir`
+    pub fn save_dir(&self, dir: &str) -> Result<()> {
+        fs::create_dir_all(dir)?;
+
+        for table in self.tables.values() {
+            let name = table.material.name();
+
+            let mut wdl = header(b'W', table.wdl.len());
+            wdl.extend_from_slice(&table.wdl);
+            fs::write(Path::new(dir).join(format!("{}.wdl", name)), wdl)?;
+
+            let mut dtm = header(b'D', table.dtm.len());
+            for d in &table.dtm {
+                dtm.extend_from_slice(&d.to_le_bytes());
+            }
+            fs::write(Path::new(dir).join(format!("{}.dtm", name)), dtm)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads every `.wdl` file in `dir`, with its `.dtm` file if there is one
+    pub fn load_dir(dir: &str) -> Result<Tablebase> {
+        let mut tablebase = Tablebase::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("wdl") {
+                continue;
+            }
+
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow!("bad table file name {:?}", path))?;
+            let material = Material::parse(name)?;
+
+            let wdl = read_body(&fs::read(&path)?, b'W', material.size())?.to_vec();
+
+            let dtm_path = path.with_extension("dtm");
+            let dtm = if dtm_path.exists() {
+                read_body(&fs::read(&dtm_path)?, b'D', 2 * material.size())?
+                    .chunks(2)
+                    .map(|d| u16::from_le_bytes([d[0], d[1]]))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            tablebase.insert(Table { material, wdl, dtm });
+        }
+
+        Ok(tablebase)
+    }
+}
+
+/// magic, version, kind and the number of entries as a little endian u64
+fn header(kind: u8, entries: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(kind);
+    out.extend_from_slice(&(entries as u64).to_le_bytes());
+    out
+}
+
+fn read_body(bytes: &[u8], kind: u8, len: usize) -> Result<&[u8]> {
+    if bytes.len() < 14 || &bytes[0..4] != MAGIC || bytes[5] != kind {
+        bail!("not a tablebase file");
+    }
+    if bytes[4] != VERSION {
+        bail!("unsupported tablebase file version {}", bytes[4]);
+    }
+    if bytes.len() != 14 + len {
+        bail!(
+            "tablebase file has {} bytes, expected {}",
+            bytes.len(),
+            14 + len
+        );
+    }
+
+    Ok(&bytes[14..])
+}
+
+#[cfg(test)]
+mod tablebase_tests {
+    use super::*;
+    use crate::engine::analysis::AnalysisCache;
+    use crate::engine::search::{SearchLimits, SearchResult};
+    use crate::engine::solver::{solve, Solution};
+    use crate::engine::Engine;
+
+    use std::time::Duration;
+
+    use std::sync::OnceLock;
+
+    /// generating takes a few seconds in debug builds, so the tests share one
+    fn krk() -> &'static Tablebase {
+        static KRK: OnceLock<Tablebase> = OnceLock::new();
+        KRK.get_or_init(|| {
+            let mut tablebase = Tablebase::new();
+            tablebase.generate("KRvK").unwrap();
+            tablebase
+        })
+    }
+
+    fn probe(tablebase: &Tablebase, fen: &str) -> Option<Probe> {
+        tablebase.probe(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn test_material() {
+        let material = Material::parse("KvKRP").unwrap();
+        assert_eq!(material.canonical().name(), "KRPvK");
+        assert!(Material::parse("KQRRvKP").is_err());
+        assert!(Material::parse("QvK").is_err());
+
+        let names: Vec<String> = Material::parse("KPvK")
+            .unwrap()
+            .reachable()
+            .iter()
+            .map(|m| m.name())
+            .collect();
+        assert_eq!(names, ["KQvK", "KRvK", "KBvK", "KNvK", "KvK"]);
+    }
+
+    #[test]
+    fn test_all_materials() {
+        let names: Vec<String> = all_materials(3).iter().map(|m| m.name()).collect();
+        assert_eq!(names, ["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+
+        // 5 with one extra piece, 15 with two on the same side and 15 with one
+        // each, since KRvKN is KNvKR the other way round
+        assert_eq!(all_materials(4).len(), 5 + 15 + 15);
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let material = Material::parse("KRvK").unwrap();
+        let board = Board::from_fen("8/8/8/4k3/8/8/1R6/6K1 b - - 0 1").unwrap();
+        let placement = Placement::from_board(&board);
+
+        let decoded = Placement::decode(&material, placement.index(&material)).unwrap();
+        let back = decoded.to_board().unwrap();
+
+        // the board is mirrored, but it's the same position
+        assert_eq!(
+            Placement::from_board(&back).index(&material),
+            placement.index(&material)
+        );
+        assert_eq!(back.turn(), Player::Black);
+    }
+
+    #[test]
+    fn test_krk() {
+        let tablebase = krk();
+
+        // mated already
+        assert_eq!(
+            probe(tablebase, "k6R/8/1K6/8/8/8/8/8 b - - 0 1"),
+            Some(Probe {
+                wdl: Wdl::Loss,
+                dtm: Some(0)
+            })
+        );
+        // mate in one
+        assert_eq!(
+            probe(tablebase, "k7/8/1K6/8/8/8/8/7R w - - 0 1"),
+            Some(Probe {
+                wdl: Wdl::Win,
+                dtm: Some(1)
+            })
+        );
+        // the black king has to take the rook
+        assert_eq!(
+            probe(tablebase, "8/8/8/8/8/8/5Rk1/K7 b - - 0 1").map(|p| p.wdl),
+            Some(Wdl::Draw)
+        );
+        // the same from black's side
+        assert_eq!(
+            probe(tablebase, "r7/8/8/8/8/1k6/8/K7 w - - 0 1").map(|p| p.wdl),
+            Some(Wdl::Loss)
+        );
+
+        let moves = tablebase
+            .root_moves(
+                &Board::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap(),
+                &valid_moves(&Board::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap()),
+            )
+            .unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].stringify(), "h1h8");
+
+        // too many pieces
+        assert_eq!(probe(tablebase, &Board::start_pos().fen()), None);
+    }
+
+    #[test]
+    fn test_search_with_tablebase() {
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.tablebase = Some(krk().clone());
+
+        let limits = SearchLimits {
+            depth: Some(3),
+            infinite: true,
+            ..Default::default()
+        };
+        let res = engine.search(&board, &limits).unwrap();
+
+        let best = krk().root_moves(&board, &valid_moves(&board)).unwrap();
+        assert!(best.contains(&res.best_move));
+        assert!(res.score > TB_WIN_SCORE - 100.0);
+        assert!(engine.store.tb_hits > 0);
+    }
+
+    #[test]
+    fn test_solver_with_tablebase() {
+        // the tablebase settles the root straight away, the solver still has
+        // to come up with a move
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        let best = krk().root_moves(&board, &valid_moves(&board)).unwrap();
+
+        match solve(&board, 1000, None, Some(krk())).solution {
+            Solution::Win(line) => assert!(best.contains(&line[0])),
+            other => panic!("expected a win, got {:?}", other),
+        }
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.params.solve_nodes = 1000;
+        engine.tablebase = Some(krk().clone());
+
+        let limits = SearchLimits {
+            depth: Some(3),
+            infinite: true,
+            ..Default::default()
+        };
+        let res = engine.search(&board, &limits).unwrap();
+
+        assert!(best.contains(&res.best_move));
+        assert_eq!(res.score, f64::INFINITY);
+        assert_eq!(res.pv.first(), Some(&res.best_move));
+    }
+
+    #[test]
+    fn test_cache_keeps_tablebase_moves() {
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        let moves = valid_moves(&board);
+        let best = krk().root_moves(&board, &moves).unwrap();
+        let worse = *moves.iter().find(|m| !best.contains(m)).unwrap();
+
+        let mut engine = Engine::new();
+        engine.params.debug_print = -1;
+        engine.tablebase = Some(krk().clone());
+
+        // a deep enough entry with a move that throws the win away
+        let mut cache = AnalysisCache::new(engine.settings_fingerprint());
+        cache.store(
+            &board,
+            &SearchResult {
+                best_move: worse,
+                ponder_move: None,
+                score: 10.0,
+                depth: 20,
+                nodes: 100,
+                pv: vec![worse],
+                elapsed: Duration::from_millis(1),
+                from_book: false,
+                from_cache: false,
+                iterations: Vec::new(),
+            },
+        );
+        engine.set_analysis_cache(cache).unwrap();
+
+        let limits = SearchLimits {
+            depth: Some(3),
+            ..Default::default()
+        };
+        let res = engine.search(&board, &limits).unwrap();
+
+        assert!(!res.from_cache);
+        assert!(best.contains(&res.best_move));
+    }
+
+    #[test]
+    fn test_verify_and_files() {
+        let tablebase = krk();
+        assert!(tablebase.verify("KRvK").unwrap() > 0);
+
+        let dir =
+            std::env::temp_dir().join(format!("antichess_tablebase_test_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        tablebase.save_dir(dir).unwrap();
+
+        let loaded = Tablebase::load_dir(dir).unwrap();
+        assert_eq!(loaded.names(), ["KRvK"]);
+        assert_eq!(loaded.max_pieces(), 3);
+
+        let fen = "k7/8/1K6/8/8/8/8/7R w - - 0 1";
+        assert_eq!(probe(&loaded, fen), probe(tablebase, fen));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 #[allow(clippy::enum_variant_names)]
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
 pub enum ChessError {
     #[error("no start time set")]
     NoStartTime,
@@ -7,4 +7,6 @@ pub enum ChessError {
     NoMoveGenerated,
     #[error("no valid moves to try")]
     NoValidMoves,
+    #[error("search limit reached")]
+    SearchLimitReached,
 }
@@ -1,2 +1,7 @@
+pub mod book_gen;
 pub mod engine;
 pub mod error;
+pub mod notation;
+pub mod pgn;
+pub mod selfplay;
+pub mod tuning;
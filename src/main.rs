@@ -1,16 +1,30 @@
-mod engine;
-mod error;
-
-use crate::engine::{opening::OpeningBook, Engine};
+use antichess_engine::book_gen::{build_from_games, BookBuildOptions};
+use antichess_engine::engine::{
+    analysis::AnalysisCache,
+    bench::{bench, BENCH_SIGNATURE, DEFAULT_BENCH_DEPTH, SIGNATURE_DEPTH},
+    epd::{load_epd, run_suite},
+    eval_params::EvalParams,
+    evaluate_game::evaluate_trace,
+    nnue::Network,
+    opening::{BookPolicy, MergePolicy, OpeningBook, PositionDump},
+    polyglot::{is_polyglot_path, load_book, save_book},
+    search::SearchLimits,
+    solver::Solution,
+    tablebase::Tablebase,
+    Engine,
+};
+use antichess_engine::pgn::load_pgn;
 
 use anyhow::Result;
 
 use pleco::{BitMove, Board, Player};
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use std::io::{self, BufRead};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-fn print_move_list(moves: &Vec<BitMove>) {
+fn print_move_list(moves: &[BitMove]) {
     eprintln!("valid moves: ");
     for m in moves {
         eprint!("{} ", m);
@@ -43,51 +57,545 @@ fn print_winner(player: Player, is_draw: bool) {
     }
 }
 
-fn main() {
-    // we are now using clap 3, so we have to downgrade from clap 4
-    let matches = App::new("Anti-fish")
-        .version("0.1.0")
-        .author("Victor Wang, Patrick Wang, Jae Yoon Cha")
-        .about("The Anti-fish engine")
-        .arg(
-            Arg::with_name("color")
-                .value_name("COLOR")
-                .help("the color of the engine")
-                .takes_value(true)
-                .possible_values(&["black", "white"])
-                .default_value("white"),
-        )
-        .arg(
-            Arg::with_name("debug")
-                .long("debug")
-                .value_name("DEBUG")
-                .help("Debug level, -1, 0, 1, or 2. -1 is zero debug output, this is for actually playing games. 0 is for no search progress output, 1 is for basic search progress output, 2 is for verbose")
-                .takes_value(true)
-                .default_value("-1"),
-        )
-        .get_matches();
+fn run_bench(matches: &ArgMatches) {
+    let depth = match matches.value_of("depth").unwrap().parse::<i32>() {
+        Ok(n) => n,
+        Err(_) => panic!("Invalid depth"),
+    };
 
-    let your_color = match matches.value_of("color") {
-        Some("black") => Player::Black,
-        Some("white") => Player::White,
-        _ => panic!("Invalid color"),
+    let nodes = match bench(depth) {
+        Ok(res) => {
+            println!("Total time (ms) : {}", res.elapsed.as_millis());
+            println!("Nodes searched  : {}", res.nodes);
+            println!("QNodes searched : {}", res.qnodes);
+            println!("Nodes/second    : {}", res.nps());
+            res.nodes
+        }
+        Err(e) => {
+            eprintln!("bench failed: {}", e);
+            std::process::exit(1);
+        }
     };
 
-    let debug_level = match matches.value_of("debug").unwrap().parse::<i8>() {
-        Ok(n) => n,
-        Err(_) => panic!("Invalid debug level"),
+    // the signature is always taken at the depth the tests check it at
+    let signature = if depth == SIGNATURE_DEPTH {
+        Ok(nodes)
+    } else {
+        bench(SIGNATURE_DEPTH).map(|res| res.nodes)
     };
+    match signature {
+        Ok(nodes) if nodes == BENCH_SIGNATURE => {
+            println!("Signature       : {} (matches)", nodes)
+        }
+        Ok(nodes) => {
+            println!(
+                "Signature       : {} (expected {}, see BENCH_SIGNATURE)",
+                nodes, BENCH_SIGNATURE
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("bench failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let mut board = Board::start_pos();
+fn run_epd(matches: &ArgMatches, engine: &mut Engine) {
+    let mut limits = SearchLimits::default();
 
-    let stdin = io::stdin();
+    if let Some(depth) = matches.value_of("depth") {
+        match depth.parse::<i32>() {
+            Ok(n) => limits.depth = Some(n),
+            Err(_) => panic!("Invalid depth"),
+        }
+    }
+
+    if let Some(movetime) = matches.value_of("movetime") {
+        match movetime.parse::<u64>() {
+            Ok(n) => limits.movetime = Some(Duration::from_millis(n)),
+            Err(_) => panic!("Invalid movetime"),
+        }
+    }
+
+    if let Some(nodes) = matches.value_of("nodes") {
+        match nodes.parse::<u64>() {
+            Ok(n) => limits.nodes = Some(n),
+            Err(_) => panic!("Invalid node limit"),
+        }
+    }
+
+    let entries = match load_epd(matches.value_of("file").unwrap()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("could not load suite: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let report = run_suite(engine, &entries, &limits);
+
+    for outcome in &report.outcomes {
+        if let Some(e) = &outcome.error {
+            println!("FAIL {} search failed: {}", outcome.id, e);
+            continue;
+        }
+
+        let solve_time = match outcome.solve_time {
+            Some(t) => format!("{} ms", t.as_millis()),
+            None => String::from("-"),
+        };
+
+        println!(
+            "{} {} played {} solved in {}",
+            if outcome.passed { "PASS" } else { "FAIL" },
+            outcome.id,
+            outcome.played.unwrap(),
+            solve_time
+        );
+    }
+
+    println!(
+        "passed {} of {}, failed {}",
+        report.passed(),
+        report.outcomes.len(),
+        report.failed()
+    );
+
+    // so a CI run notices
+    if report.failed() > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Applies the eval related options, `--eval-params`, `--eval-option` and `--nnue`
+fn load_eval_settings(matches: &ArgMatches, engine: &mut Engine) {
+    if let Some(path) = matches.value_of("eval-params") {
+        match EvalParams::load(path) {
+            Ok(params) => engine.eval_params = params,
+            Err(e) => panic!("Invalid eval params file: {}", e),
+        }
+    }
+
+    if let Some(options) = matches.values_of("eval-option") {
+        for option in options {
+            let res = match option.split_once('=') {
+                Some((name, value)) => engine.eval_params.set(name, value),
+                None => Err(anyhow::anyhow!("expected NAME=VALUE")),
+            };
 
+            if let Err(e) = res {
+                panic!("Invalid eval option {}: {}", option, e);
+            }
+        }
+    }
+
+    if let Some(path) = matches.value_of("nnue") {
+        match Network::load(path) {
+            Ok(network) => engine.nnue = Some(network),
+            Err(e) => panic!("Invalid network file: {}", e),
+        }
+    }
+}
+
+/// Loads a book, and says how much of a Polyglot book couldn't be converted
+fn load_book_reporting(path: &str) -> Result<OpeningBook> {
+    let (book, dropped) = load_book(path)?;
+    if dropped > 0 {
+        eprintln!(
+            "left out {} entries of {} that aren't reachable from the starting position with forced captures",
+            dropped, path
+        );
+    }
+
+    Ok(book)
+}
+
+/// An engine set up from the global options: book, eval settings, tablebase,
+/// limits and seed
+fn configure_engine(matches: &ArgMatches) -> Engine {
     let mut engine = Engine::new();
-    engine.opening_book = Some(OpeningBook::new());
-    engine.params.debug_print = debug_level;
+    engine.opening_book = match matches.value_of("book") {
+        Some(path) => match load_book_reporting(path) {
+            Ok(book) => Some(book.with_fallback(OpeningBook::new())),
+            Err(e) => panic!("Invalid opening book file: {}", e),
+        },
+        None => Some(OpeningBook::new()),
+    };
+    if matches.is_present("deterministic") {
+        engine.set_deterministic();
+    }
+
+    if let Some(nodes) = matches.value_of("nodes") {
+        match nodes.parse::<u64>() {
+            Ok(n) => engine.params.nodes = Some(n),
+            Err(_) => panic!("Invalid node limit"),
+        }
+    }
+
+    match BookPolicy::parse(matches.value_of("book-policy").unwrap()) {
+        Ok(policy) => engine.params.book_policy = policy,
+        Err(e) => panic!("Invalid book policy: {}", e),
+    }
+
+    match matches.value_of("book-max-ply").unwrap().parse::<u16>() {
+        Ok(n) => engine.params.book_max_ply = n,
+        Err(_) => panic!("Invalid book ply limit"),
+    }
+
+    load_eval_settings(matches, &mut engine);
+
+    load_tablebase(matches, &mut engine);
+
+    if let Some(solve_nodes) = matches.value_of("solve-nodes") {
+        match solve_nodes.parse::<u64>() {
+            Ok(n) => engine.params.solve_nodes = n,
+            Err(_) => panic!("Invalid solver node limit"),
+        }
+    }
+
+    if let Some(seed) = matches.value_of("seed") {
+        match seed.parse::<u64>() {
+            Ok(n) => engine.seed(n),
+            Err(_) => panic!("Invalid seed"),
+        }
+    }
+
+    engine
+}
+
+fn run_eval(matches: &ArgMatches, engine: &Engine) {
+    let fen = matches.value_of("fen").unwrap();
+
+    let board = match Board::from_fen(fen) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("invalid fen {}: {:?}", fen, e);
+            return;
+        }
+    };
+
+    println!("{}", evaluate_trace(&board, &engine.eval_params));
+
+    if let Some(network) = &engine.nnue {
+        println!("{:<16} {:>29.2}", "network", network.evaluate_board(&board));
+    }
+}
+
+fn load_tablebase(matches: &ArgMatches, engine: &mut Engine) {
+    if let Some(dir) = matches.value_of("tablebase") {
+        match Tablebase::load_dir(dir) {
+            Ok(tablebase) => engine.tablebase = Some(tablebase),
+            Err(e) => panic!("Invalid tablebase directory: {}", e),
+        }
+    }
+}
+
+/// Loads the `--analysis-cache` file into the engine, or starts an empty cache
+/// if there is no file yet. Returns the path to save it back to. A file made
+/// with other engine settings is refused, its results don't hold for these.
+fn load_analysis_cache<'a>(matches: &'a ArgMatches, engine: &mut Engine) -> Option<&'a str> {
+    let path = matches.value_of("analysis-cache")?;
+
+    let cache = if Path::new(path).exists() {
+        match AnalysisCache::load(path) {
+            Ok(cache) => cache,
+            Err(e) => panic!("Invalid analysis cache: {}", e),
+        }
+    } else {
+        AnalysisCache::new(engine.settings_fingerprint())
+    };
+    if let Err(e) = engine.set_analysis_cache(cache) {
+        panic!("Invalid analysis cache: {}: {}", path, e);
+    }
+
+    Some(path)
+}
+
+fn save_analysis_cache(engine: &Engine, path: Option<&str>) {
+    if let (Some(cache), Some(path)) = (&engine.analysis_cache, path) {
+        if let Err(e) = cache.save(path) {
+            eprintln!("could not save the analysis cache to {}: {}", path, e);
+        }
+    }
+}
+
+fn run_book(matches: &ArgMatches) {
+    let (command, command_matches) = match matches.subcommand() {
+        Some(subcommand) => subcommand,
+        None => {
+            eprintln!("missing book command, see book --help");
+            return;
+        }
+    };
+
+    // the only command that doesn't start from a book
+    if command == "build" {
+        build_book(command_matches);
+        return;
+    }
+
+    let input = command_matches.value_of("input").unwrap();
+    let mut book = match load_book_reporting(input) {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("could not load {}: {}", input, e);
+            return;
+        }
+    };
+
+    let output = match command {
+        "convert" => command_matches.value_of("output").unwrap(),
+        "inspect" => {
+            inspect_book(command_matches, &book);
+            return;
+        }
+        "merge" => {
+            let policy = match MergePolicy::parse(command_matches.value_of("conflict").unwrap()) {
+                Ok(policy) => policy,
+                Err(e) => panic!("Invalid merge policy: {}", e),
+            };
+            let other_path = command_matches.value_of("other").unwrap();
+            let other = match load_book_reporting(other_path) {
+                Ok(other) => other,
+                Err(e) => {
+                    eprintln!("could not load {}: {}", other_path, e);
+                    return;
+                }
+            };
+
+            println!("{} positions in both books", book.merge(other, policy));
+            command_matches.value_of("output").unwrap()
+        }
+        "verify" => {
+            let check = book.verify();
+            for invalid in &check.invalid {
+                println!(
+                    "{:016x} {} can't play {}",
+                    invalid.key,
+                    invalid.fen,
+                    invalid.chess_move.stringify()
+                );
+            }
+            println!(
+                "checked {} positions, {} invalid moves, {} positions not reachable from the start",
+                check.checked,
+                check.invalid.len(),
+                check.unreached.len()
+            );
+
+            if check.invalid.is_empty() {
+                return;
+            }
+            if !command_matches.is_present("fix") {
+                std::process::exit(1);
+            }
+            for invalid in &check.invalid {
+                book.remove(invalid.key, invalid.chess_move);
+            }
+            command_matches.value_of("output").unwrap_or(input)
+        }
+        "prune" => {
+            let min_games = match command_matches
+                .value_of("min-games")
+                .unwrap()
+                .parse::<u32>()
+            {
+                Ok(n) => n,
+                Err(_) => panic!("Invalid game count"),
+            };
+            let min_score = match command_matches
+                .value_of("min-score")
+                .unwrap()
+                .parse::<f64>()
+            {
+                Ok(n) => n,
+                Err(_) => panic!("Invalid score"),
+            };
+
+            println!("removed {} moves", book.prune(min_games, min_score));
+            command_matches.value_of("output").unwrap_or(input)
+        }
+        _ => return,
+    };
+
+    match save_book(&book, output) {
+        Ok(()) => println!("wrote {} positions to {}", book.len(), output),
+        Err(e) => eprintln!("could not write {}: {}", output, e),
+    }
+}
+
+/// Builds a book out of the openings of PGN game collections
+fn build_book(matches: &ArgMatches) {
+    let count = |name: &str, what: &str| -> u32 {
+        match matches.value_of(name).unwrap().parse::<u32>() {
+            Ok(n) => n,
+            Err(_) => panic!("Invalid {}", what),
+        }
+    };
+    let min_score = match matches.value_of("min-score").unwrap().parse::<f64>() {
+        Ok(n) if n >= 0.0 => n,
+        _ => panic!("Invalid score"),
+    };
+    let options = BookBuildOptions {
+        plies: count("plies", "ply count"),
+        min_count: count("min-count", "game count"),
+        min_score,
+    };
+
+    let mut games = Vec::new();
+    for path in matches.values_of("input").unwrap() {
+        match load_pgn(path) {
+            Ok(read) => {
+                let unreadable = read.iter().filter(|g| g.unreadable.is_some()).count();
+                if unreadable > 0 {
+                    eprintln!(
+                        "{}: {} of {} games have moves we can't play, they are cut off there",
+                        path,
+                        unreadable,
+                        read.len()
+                    );
+                }
+                games.extend(read);
+            }
+            Err(e) => {
+                eprintln!("could not read {}: {}", path, e);
+                return;
+            }
+        }
+    }
+
+    let book = build_from_games(&games, &options);
+    let output = matches.value_of("output").unwrap();
+
+    match save_book(&book, output) {
+        Ok(()) => println!(
+            "wrote {} positions from {} games to {}",
+            book.len(),
+            games.len(),
+            output
+        ),
+        Err(e) => eprintln!("could not write {}: {}", output, e),
+    }
+}
+
+/// Prints the moves of one position, every position with `--all`, or else
+/// the moves learned from games, as text or JSON
+fn inspect_book(matches: &ArgMatches, book: &OpeningBook) {
+    let dump: Vec<PositionDump> = if let Some(fen) = matches.value_of("fen") {
+        let board = match Board::from_fen(fen) {
+            Ok(board) => board,
+            Err(e) => {
+                eprintln!("invalid fen {}: {:?}", fen, e);
+                return;
+            }
+        };
+        book.dump_position(&board).into_iter().collect()
+    } else if matches.is_present("all") {
+        book.dump()
+    } else {
+        book.dump()
+            .into_iter()
+            .filter_map(|mut position| {
+                position.moves.retain(|m| m.score.is_some());
+                (!position.moves.is_empty()).then_some(position)
+            })
+            .collect()
+    };
+
+    if matches.is_present("json") {
+        match serde_json::to_string_pretty(&dump) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("could not write json: {}", e),
+        }
+        return;
+    }
+
+    for PositionDump { key, fen, moves } in &dump {
+        println!("{} {}", key, fen.as_deref().unwrap_or("(not reachable)"));
+
+        for m in moves {
+            let name = match (&m.san, m.valid) {
+                (Some(san), _) => format!("{} ({})", san, m.uci),
+                (None, Some(false)) => format!("{} (invalid)", m.uci),
+                (None, _) => m.uci.clone(),
+            };
+            let score = match m.score {
+                Some(score) => format!("{:.2}", score),
+                None => String::from("-"),
+            };
+            println!(
+                "    {:<16} weight {:<5} learned {:<8.1} +{} ={} -{} score {}",
+                name, m.weight, m.learned_weight, m.wins, m.draws, m.losses, score
+            );
+        }
+    }
+    println!("{} of {} positions", dump.len(), book.len());
+}
+
+/// 1.0 if white won the finished game, 0.5 for a draw and 0.0 if black won
+fn game_result(board: &Board) -> f64 {
+    if board.stalemate() {
+        0.5
+    } else if board.turn() == Player::Black {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// A result the opponent sends when the game ends some other way, e.g. a
+/// draw by the fifty move rule, on time or by agreement
+fn parse_result(line: &str) -> Option<f64> {
+    match line.trim() {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        _ => None,
+    }
+}
+
+/// Prints the result if the game on the board is over
+fn check_game_over(engine: &Engine, board: &Board) -> Option<f64> {
+    if !board.checkmate() && !board.stalemate() {
+        return None;
+    }
+
+    if engine.params.debug_print > -1 {
+        println!("Game over. winner: {}", board.turn().other_player());
+        eprintln!("{}", board);
+        eprintln!("ply: {}", board.ply());
+        eprintln!("total time: {}", engine.store.total_search_time_ms);
+    }
+
+    print_winner(board.turn().other_player(), board.stalemate());
+    Some(game_result(board))
+}
+
+/// Plays a game against the moves read from stdin, until it ends in mate or
+/// stalemate, we resign or stdin is closed. With `read_results` the opponent
+/// can also end it with `resign` or the result (`1-0`, `0-1` or `1/2-1/2`),
+/// which is how games that end some other way get learned from. Gives back
+/// the moves played and the result, if there was one.
+fn play_game(
+    engine: &mut Engine,
+    your_color: Player,
+    cache_path: Option<&str>,
+    read_results: bool,
+) -> (Vec<BitMove>, Option<f64>) {
+    let debug_level = engine.params.debug_print;
+    let mut board = Board::start_pos();
+    let mut game_moves = Vec::new();
+
+    // the result if we resign
+    let lost = if your_color == Player::White {
+        0.0
+    } else {
+        1.0
+    };
 
     if your_color == Player::White {
-        let m = match generate_with_fallback(&mut engine, &board) {
+        let m = match generate_with_fallback(engine, &board) {
             Ok(m) => {
                 println!("{}", m);
                 m
@@ -97,11 +605,13 @@ fn main() {
                     eprintln!("encountered error while generating move: {}", e);
                     println!("No moves available");
                 }
-                return;
+                return (game_moves, Some(lost));
             }
         };
 
         board.apply_move(m);
+        game_moves.push(m);
+        save_analysis_cache(engine, cache_path);
     }
 
     if debug_level > -1 {
@@ -111,30 +621,32 @@ fn main() {
         print_move_list(&opp_valid_moves);
     }
 
+    let stdin = io::stdin();
     for line in stdin.lock().lines() {
         match line {
             Ok(line) => {
+                if read_results {
+                    if let Some(result) = parse_result(&line) {
+                        return (game_moves, Some(result));
+                    }
+                    if line.trim() == "resign" {
+                        return (game_moves, Some(1.0 - lost));
+                    }
+                }
+
                 // we can just error if we can't parse move because we assume the opponent always
                 // returns valid moves. If they don't, we'll just error out.
                 if !board.apply_uci_move(&line) {
                     eprintln!("Invalid move: {}", line);
                     continue;
                 }
+                game_moves.extend(board.last_move());
 
-                if board.checkmate() || board.stalemate() {
-                    if debug_level > -1 {
-                        println!("Game over. winner: {}", board.turn().other_player());
-                        eprintln!("{}", board);
-                        eprintln!("ply: {}", board.ply());
-                        eprintln!("total time: {}", engine.store.total_search_time_ms);
-                    }
-
-                    print_winner(board.turn().other_player(), board.stalemate());
-
-                    return;
+                if let Some(result) = check_game_over(engine, &board) {
+                    return (game_moves, Some(result));
                 }
 
-                let m = match generate_with_fallback(&mut engine, &board) {
+                let m = match generate_with_fallback(engine, &board) {
                     Ok(m) => {
                         println!("{}", m);
                         m
@@ -144,23 +656,16 @@ fn main() {
                             eprintln!("encountered error in move gen: {}", e);
                             eprintln!("resign");
                         }
-                        return;
+                        return (game_moves, Some(lost));
                     }
                 };
 
                 board.apply_move(m);
+                game_moves.push(m);
+                save_analysis_cache(engine, cache_path);
 
-                if board.checkmate() || board.stalemate() {
-                    if debug_level > -1 {
-                        println!("Game over. winner: {}", board.turn().other_player());
-                        eprintln!("{}", board);
-                        eprintln!("ply: {}", board.ply());
-                        eprintln!("total time: {}", engine.store.total_search_time_ms);
-                    }
-
-                    print_winner(board.turn().other_player(), board.stalemate());
-
-                    return;
+                if let Some(result) = check_game_over(engine, &board) {
+                    return (game_moves, Some(result));
                 }
 
                 if debug_level > -1 {
@@ -177,4 +682,509 @@ fn main() {
             }
         }
     }
+
+    (game_moves, None)
+}
+
+/// Counts a finished game for the book moves played in it, straight in the
+/// book file so the next game starts from it
+fn learn_from_game(path: &str, moves: &[BitMove], result: f64, debug_level: i8) {
+    let mut book = match OpeningBook::load(path) {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("could not load {} to learn: {}", path, e);
+            return;
+        }
+    };
+
+    let learned = book.learn(moves, result);
+
+    if let Err(e) = book.save(path) {
+        eprintln!("could not save what we learned to {}: {}", path, e);
+    } else if debug_level > -1 {
+        eprintln!("learned from {} book moves", learned);
+    }
+}
+
+fn run_solve(matches: &ArgMatches, engine: &Engine) {
+    let fen = matches.value_of("fen").unwrap();
+
+    let board = match Board::from_fen(fen) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("invalid fen {}: {:?}", fen, e);
+            return;
+        }
+    };
+
+    let max_nodes = match matches.value_of("nodes").unwrap().parse::<u64>() {
+        Ok(n) => n,
+        Err(_) => panic!("Invalid node limit"),
+    };
+
+    let start = Instant::now();
+    let res = engine.solve(&board, max_nodes, None);
+
+    match res.solution {
+        Solution::Win(line) => {
+            let line: Vec<String> = line.iter().map(|m| m.to_string()).collect();
+            println!("win: {}", line.join(" "));
+        }
+        Solution::Loss => println!("loss"),
+        Solution::Unknown => println!("unknown"),
+    }
+    println!("{} nodes in {} ms", res.nodes, start.elapsed().as_millis());
+}
+
+fn main() {
+    let bench_depth = DEFAULT_BENCH_DEPTH.to_string();
+
+    // we are now using clap 3, so we have to downgrade from clap 4
+    let matches = App::new("Anti-fish")
+        .version("0.1.0")
+        .author("Victor Wang, Patrick Wang, Jae Yoon Cha")
+        .about("The Anti-fish engine")
+        .arg(
+            Arg::with_name("color")
+                .value_name("COLOR")
+                .help("the color of the engine")
+                .takes_value(true)
+                .possible_values(["black", "white"])
+                .default_value("white"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .value_name("DEBUG")
+                .help("Debug level, -1, 0, 1, or 2. -1 is zero debug output, this is for actually playing games. 0 is for no search progress output, 1 is for basic search progress output, 2 is for verbose")
+                .takes_value(true)
+                .default_value("-1"),
+        )
+        .arg(
+            Arg::with_name("nodes")
+                .long("nodes")
+                .value_name("NODES")
+                .help("Maximum number of nodes to search per move")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed for every random choice the engine makes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eval-params")
+                .long("eval-params")
+                .value_name("FILE")
+                .help("Load the evaluation weights from a TOML or JSON file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eval-option")
+                .long("eval-option")
+                .value_name("NAME=VALUE")
+                .help("Change a single evaluation weight, e.g. material_mg.knight=320. Can be given more than once")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::with_name("nnue")
+                .long("nnue")
+                .value_name("FILE")
+                .help("Evaluate with the neural network in this file instead of the classical eval")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tablebase")
+                .long("tablebase")
+                .value_name("DIR")
+                .help("Probe the endgame tables in this directory, see the tbgen binary")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("analysis-cache")
+                .long("analysis-cache")
+                .value_name("FILE")
+                .help("Reuse the search results kept in this file for positions searched before, and add the new ones to it. The file is created if it isn't there, and has to be made with the same eval, network, tablebase and solver settings")
+                .conflicts_with("deterministic")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("book")
+                .long("book")
+                .value_name("FILE")
+                .help("Play from the opening book in this file, see the opening-gen binary. Files ending in .bin are Polyglot books. The built-in book is used for positions it doesn't have")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("book-policy")
+                .long("book-policy")
+                .value_name("POLICY")
+                .help("How to pick between book moves: best, weighted (random by weight) or top-N (any of the N best). Use --seed to repeat the choices")
+                .takes_value(true)
+                .default_value("weighted"),
+        )
+        .arg(
+            Arg::with_name("book-max-ply")
+                .long("book-max-ply")
+                .value_name("PLY")
+                .help("Only play book moves before this many plies into the game")
+                .takes_value(true)
+                .default_value("40"),
+        )
+        .arg(
+            Arg::with_name("learn")
+                .long("learn")
+                .requires("book")
+                .help("After every game, update the win/draw/loss counts of the book moves played in it in the --book file, so moves that worked get played more"),
+        )
+        .arg(
+            Arg::with_name("solve-nodes")
+                .long("solve-nodes")
+                .value_name("NODES")
+                .help("Let the proof-number solver check decisive looking positions, looking at up to this many nodes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("deterministic")
+                .long("deterministic")
+                .help("Ignore the clock and seed with 0 unless --seed is given, so the same position always gives the same move. Use with --nodes"),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Searches a fixed set of positions and reports the node count and speed")
+                .arg(
+                    Arg::with_name("depth")
+                        .value_name("DEPTH")
+                        .help("depth to search every position to")
+                        .takes_value(true)
+                        .default_value(&bench_depth),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("epd")
+                .about("Runs an EPD test suite and reports which positions were solved")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("the EPD file to run")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("depth")
+                        .long("depth")
+                        .value_name("DEPTH")
+                        .help("depth to search every position to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("movetime")
+                        .long("movetime")
+                        .value_name("MS")
+                        .help("time to search every position for, in milliseconds")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nodes")
+                        .long("nodes")
+                        .value_name("NODES")
+                        .help("nodes to search every position for")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("eval")
+                .about("Prints every term of the static eval of a position, from white's point of view")
+                .arg(
+                    Arg::with_name("fen")
+                        .value_name("FEN")
+                        .help("the position to evaluate")
+                        .takes_value(true)
+                        .default_value("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("book")
+                .about("Opening book tools, for our books and Polyglot books (.bin)")
+                .subcommand(
+                    SubCommand::with_name("convert")
+                        .about("Converts a book between our format and Polyglot, going by the file extensions")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("INPUT")
+                                .help("the book to read")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .value_name("OUTPUT")
+                                .help("the book to write")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("build")
+                        .about("Builds a book from the openings of PGN game collections")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("PGN")
+                                .help("the games to read")
+                                .required(true)
+                                .multiple(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("plies")
+                                .long("plies")
+                                .value_name("N")
+                                .help("how many plies of every game go in the book")
+                                .takes_value(true)
+                                .default_value("20"),
+                        )
+                        .arg(
+                            Arg::with_name("min-count")
+                                .long("min-count")
+                                .value_name("N")
+                                .help("leave out moves played in fewer games than this")
+                                .takes_value(true)
+                                .default_value("2"),
+                        )
+                        .arg(
+                            Arg::with_name("min-score")
+                                .long("min-score")
+                                .value_name("SCORE")
+                                .help("leave out moves scoring less than this for the side playing them, 1.0 is winning every game")
+                                .takes_value(true)
+                                .default_value("0.0"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .value_name("FILE")
+                                .help("where to write the book, .bin for a Polyglot book")
+                                .takes_value(true)
+                                .default_value("opening.book"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("inspect")
+                        .about("Prints the book moves of a position, every position, or every move learned from games")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("FILE")
+                                .help("the book to read")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("fen")
+                                .long("fen")
+                                .value_name("FEN")
+                                .help("only show this position")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("all")
+                                .long("all")
+                                .help("show every position with all its moves, not just the learned ones")
+                                .conflicts_with("fen"),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("print JSON instead of text"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("merge")
+                        .about("Merges two books into one")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("FILE")
+                                .help("the first book")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("other")
+                                .value_name("OTHER")
+                                .help("the book to merge into it")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("conflict")
+                                .long("conflict")
+                                .value_name("POLICY")
+                                .help("for positions in both books: sum adds up the moves, keep takes the first book's and replace the other's")
+                                .takes_value(true)
+                                .default_value("sum"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .value_name("FILE")
+                                .help("where to write the merged book")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Checks that every book move is valid under our rules")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("FILE")
+                                .help("the book to check")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("fix")
+                                .long("fix")
+                                .help("remove the invalid moves and save the book"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .value_name("FILE")
+                                .help("where to write the fixed book, the input file by default")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Removes the book moves that kept losing")
+                        .arg(
+                            Arg::with_name("input")
+                                .value_name("FILE")
+                                .help("the book to prune")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("min-games")
+                                .long("min-games")
+                                .value_name("N")
+                                .help("only moves played in at least this many games can go")
+                                .takes_value(true)
+                                .default_value("10"),
+                        )
+                        .arg(
+                            Arg::with_name("min-score")
+                                .long("min-score")
+                                .value_name("SCORE")
+                                .help("moves scoring less than this go, 1.0 is winning every game")
+                                .takes_value(true)
+                                .default_value("0.3"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .value_name("FILE")
+                                .help("where to write the pruned book, the input file by default")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("solve")
+                .about("Tries to prove a forced win or loss for the side to move with proof-number search")
+                .arg(
+                    Arg::with_name("fen")
+                        .value_name("FEN")
+                        .help("the position to solve")
+                        .takes_value(true)
+                        .default_value("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+                )
+                .arg(
+                    Arg::with_name("nodes")
+                        .long("nodes")
+                        .value_name("NODES")
+                        .help("maximum number of nodes to look at")
+                        .takes_value(true)
+                        .default_value("1000000"),
+                ),
+        )
+        .get_matches();
+
+    // learning saves the counts back into the book file, before any game is
+    // played for nothing
+    if matches.is_present("learn") && is_polyglot_path(matches.value_of("book").unwrap()) {
+        panic!("Invalid book for learning: Polyglot books can't keep the game counts");
+    }
+
+    if let Some(eval_matches) = matches.subcommand_matches("eval") {
+        let mut engine = Engine::new();
+        load_eval_settings(&matches, &mut engine);
+        run_eval(eval_matches, &engine);
+        return;
+    }
+
+    if let Some(book_matches) = matches.subcommand_matches("book") {
+        run_book(book_matches);
+        return;
+    }
+
+    if let Some(solve_matches) = matches.subcommand_matches("solve") {
+        let mut engine = Engine::new();
+        load_tablebase(&matches, &mut engine);
+        run_solve(solve_matches, &engine);
+        return;
+    }
+
+    if let Some(epd_matches) = matches.subcommand_matches("epd") {
+        let mut engine = configure_engine(&matches);
+        engine.params.debug_print = -1;
+        // a suite position only comes from the book if one was asked for
+        if !matches.is_present("book") {
+            engine.opening_book = None;
+        }
+        run_epd(epd_matches, &mut engine);
+        return;
+    }
+
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        run_bench(bench_matches);
+        return;
+    }
+
+    let your_color = match matches.value_of("color") {
+        Some("black") => Player::Black,
+        Some("white") => Player::White,
+        _ => panic!("Invalid color"),
+    };
+
+    let debug_level = match matches.value_of("debug").unwrap().parse::<i8>() {
+        Ok(n) => n,
+        Err(_) => panic!("Invalid debug level"),
+    };
+
+    let mut engine = configure_engine(&matches);
+    engine.params.debug_print = debug_level;
+
+    let learn_path = if matches.is_present("learn") {
+        matches.value_of("book")
+    } else {
+        None
+    };
+
+    let cache_path = load_analysis_cache(&matches, &mut engine);
+
+    let (game_moves, result) = play_game(&mut engine, your_color, cache_path, learn_path.is_some());
+
+    if let Some(path) = learn_path {
+        match result {
+            Some(result) => learn_from_game(path, &game_moves, result, debug_level),
+            None if debug_level > -1 => {
+                eprintln!("the game ended without a result, nothing to learn")
+            }
+            None => {}
+        }
+    }
 }
@@ -0,0 +1,145 @@
+use pleco::{BitMove, Board, PieceType};
+
+/// Writes a move in standard algebraic notation (SAN), e.g. `Nbxd7+` or `O-O`.
+/// The move has to be legal on the board.
+pub fn move_to_san(board: &Board, m: BitMove) -> String {
+    let mut san = san_without_suffix(board, m);
+
+    let mut after = board.clone();
+    after.apply_move(m);
+
+    if after.checkmate() {
+        san.push('#');
+    } else if after.in_check() {
+        san.push('+');
+    }
+
+    san
+}
+
+/// Finds the legal move described by either SAN (`exd5`, `Nf3+`, `O-O`) or
+/// UCI (`e4d5`) notation. Check marks and annotations like `!?` are ignored.
+pub fn parse_move(board: &Board, text: &str) -> Option<BitMove> {
+    let cleaned = text
+        .trim()
+        .trim_end_matches(['+', '#', '!', '?'])
+        .replace('0', "O");
+
+    board.generate_moves().into_iter().find(|m| {
+        let san = san_without_suffix(board, *m);
+        san == cleaned
+            // some tools leave out the '=' for promotions
+            || san.replace('=', "") == cleaned
+            || m.stringify() == text.trim()
+    })
+}
+
+fn san_without_suffix(board: &Board, m: BitMove) -> String {
+    if m.is_king_castle() {
+        return String::from("O-O");
+    }
+
+    if m.is_queen_castle() {
+        return String::from("O-O-O");
+    }
+
+    let src = m.get_src();
+    let dest = m.get_dest();
+    let piece_type = board.piece_at_sq(src).type_of();
+    let is_capture = board.is_capture(m);
+
+    let src_str = src.to_string();
+
+    let mut san = String::new();
+
+    if piece_type == PieceType::P {
+        if is_capture {
+            san.push_str(&src_str[..1]);
+        }
+    } else {
+        san.push(piece_type.char_upper());
+
+        // other pieces of the same type that could also go to the same square
+        let others: Vec<BitMove> = board
+            .generate_moves()
+            .into_iter()
+            .filter(|o| {
+                o.get_dest() == dest
+                    && o.get_src() != src
+                    && board.piece_at_sq(o.get_src()).type_of() == piece_type
+            })
+            .collect();
+
+        if !others.is_empty() {
+            let same_file = others.iter().any(|o| o.get_src().file() == src.file());
+            let same_rank = others.iter().any(|o| o.get_src().rank() == src.rank());
+
+            if !same_file {
+                san.push_str(&src_str[..1]);
+            } else if !same_rank {
+                san.push_str(&src_str[1..]);
+            } else {
+                san.push_str(&src_str);
+            }
+        }
+    }
+
+    if is_capture {
+        san.push('x');
+    }
+
+    san.push_str(&dest.to_string());
+
+    if m.is_promo() {
+        san.push('=');
+        san.push(m.promo_piece().char_upper());
+    }
+
+    san
+}
+
+#[cfg(test)]
+mod notation_tests {
+    use super::*;
+
+    #[test]
+    fn test_san_round_trip() {
+        let game = Board::start_pos();
+
+        for m in game.generate_moves() {
+            let san = move_to_san(&game, m);
+            assert_eq!(parse_move(&game, &san), Some(m));
+        }
+
+        let e4 = parse_move(&game, "e4").unwrap();
+        assert_eq!(e4.stringify(), "e2e4");
+        assert_eq!(parse_move(&game, "g1f3"), parse_move(&game, "Nf3"));
+        assert_eq!(parse_move(&game, "Ke2"), None);
+    }
+
+    #[test]
+    fn test_san_captures_and_disambiguation() {
+        let game = Board::from_fen("1n5k/P7/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+
+        let promo = parse_move(&game, "axb8=Q").unwrap();
+        assert!(promo.is_capture());
+        assert_eq!(promo.promo_piece(), PieceType::Q);
+        assert_eq!(move_to_san(&game, promo), "axb8=Q+");
+        assert_eq!(parse_move(&game, "axb8Q"), Some(promo));
+
+        // both rooks can go to d1
+        let rad1 = parse_move(&game, "Rad1").unwrap();
+        assert_eq!(rad1.stringify(), "a1d1");
+        assert_eq!(move_to_san(&game, rad1), "Rad1");
+        assert_eq!(parse_move(&game, "Rd1"), None);
+        assert_eq!(
+            move_to_san(&game, parse_move(&game, "Rab1").unwrap()),
+            "Rab1"
+        );
+        assert_eq!(move_to_san(&game, parse_move(&game, "Ra2").unwrap()), "Ra2");
+
+        let game = Board::from_fen("7k/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        assert!(parse_move(&game, "O-O").unwrap().is_king_castle());
+        assert!(parse_move(&game, "0-0-0").unwrap().is_queen_castle());
+    }
+}
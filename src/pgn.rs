@@ -0,0 +1,290 @@
+use crate::notation::parse_move;
+
+use anyhow::Result;
+use pleco::{BitMove, Board};
+
+use std::fs;
+use std::path::Path;
+
+/// A game read from a PGN file
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnGame {
+    /// the tag pairs, like `("White", "someone")`, in file order
+    pub tags: Vec<(String, String)>,
+    /// the moves from the starting position, up to the first one we couldn't read
+    pub moves: Vec<BitMove>,
+    /// 1.0 if white won, 0.5 for a draw, 0.0 if black won, None if unknown
+    pub result: Option<f64>,
+    /// the first move that isn't legal under our rules, or couldn't be read.
+    /// Everything from there on is left out of `moves`.
+    pub unreadable: Option<String>,
+}
+
+impl PgnGame {
+    fn new() -> PgnGame {
+        PgnGame {
+            tags: Vec::new(),
+            moves: Vec::new(),
+            result: None,
+            unreadable: None,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Games set up from some other position can't go in a book that starts
+    /// from the usual one
+    pub fn from_start_pos(&self) -> bool {
+        self.tag("FEN")
+            .is_none_or(|fen| fen == Board::start_pos().fen())
+    }
+}
+
+fn parse_result(token: &str) -> Option<Option<f64>> {
+    match token {
+        "1-0" => Some(Some(1.0)),
+        "0-1" => Some(Some(0.0)),
+        "1/2-1/2" => Some(Some(0.5)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+/// Reads every game in a PGN collection. Comments, variations, NAGs and move
+/// numbers are skipped, the moves can be SAN or UCI. A game stops being read
+/// at the first move that isn't valid, but the moves before it are kept.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::new();
+    let mut board = Board::start_pos();
+    let mut in_moves = false;
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            '[' => {
+                // a tag after the moves means the last game had no result
+                if in_moves {
+                    games.push(std::mem::replace(&mut game, PgnGame::new()));
+                    board = Board::start_pos();
+                    in_moves = false;
+                }
+
+                let end = tag_end(&chars, i);
+                if let Some(tag) = parse_tag(&chars[i + 1..end]) {
+                    game.tags.push(tag);
+                }
+                i = end + 1;
+            }
+            '{' => {
+                i = skip_to(&chars, i, '}') + 1;
+            }
+            ';' => {
+                i = skip_to(&chars, i, '\n') + 1;
+            }
+            '%' if i == 0 || chars[i - 1] == '\n' => {
+                i = skip_to(&chars, i, '\n') + 1;
+            }
+            '(' => {
+                i = skip_variation(&chars, i) + 1;
+            }
+            c if c.is_whitespace() => {
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !"[]{}();".contains(chars[i])
+                {
+                    i += 1;
+                }
+                let token: String = chars[start..i].iter().collect();
+
+                if let Some(result) = parse_result(&token) {
+                    game.result = result;
+                    games.push(std::mem::replace(&mut game, PgnGame::new()));
+                    board = Board::start_pos();
+                    in_moves = false;
+                    continue;
+                }
+
+                if !in_moves {
+                    in_moves = true;
+                    if let Some(fen) = game.tag("FEN") {
+                        if let Ok(start) = Board::from_fen(fen) {
+                            board = start;
+                        }
+                    }
+                }
+
+                read_move(&mut game, &mut board, &token);
+            }
+        }
+    }
+
+    if in_moves || !game.tags.is_empty() {
+        games.push(game);
+    }
+
+    games
+}
+
+pub fn load_pgn<P: AsRef<Path>>(path: P) -> Result<Vec<PgnGame>> {
+    Ok(parse_pgn(&fs::read_to_string(path)?))
+}
+
+/// Applies a move token, skipping move numbers (`12.`, `12...`, `12.e4`) and
+/// NAGs (`$1`)
+fn read_move(game: &mut PgnGame, board: &mut Board, token: &str) {
+    let text = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if text.is_empty() || text.starts_with('$') || game.unreadable.is_some() {
+        return;
+    }
+
+    let valid = crate::engine::valid_moves(board);
+    match parse_move(board, text).filter(|m| valid.contains(m)) {
+        Some(m) => {
+            board.apply_move(m);
+            game.moves.push(m);
+        }
+        None => game.unreadable = Some(text.to_string()),
+    }
+}
+
+/// index of the `]` closing the tag that starts at `start`, ignoring any in
+/// the quoted value
+fn tag_end(chars: &[char], start: usize) -> usize {
+    let mut in_quotes = false;
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if in_quotes => i += 1,
+            '"' => in_quotes = !in_quotes,
+            ']' if !in_quotes => return i,
+            _ => (),
+        }
+        i += 1;
+    }
+
+    chars.len()
+}
+
+fn parse_tag(chars: &[char]) -> Option<(String, String)> {
+    let text: String = chars.iter().collect();
+    let (name, value) = text.trim().split_once(char::is_whitespace)?;
+
+    let value = value
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\");
+
+    Some((name.to_string(), value))
+}
+
+/// index of the next `end`, or the end of the text
+fn skip_to(chars: &[char], start: usize, end: char) -> usize {
+    (start + 1..chars.len())
+        .find(|i| chars[*i] == end)
+        .unwrap_or(chars.len())
+}
+
+/// index of the `)` closing the variation at `start`, variations can nest and
+/// have comments with brackets in them
+fn skip_variation(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < chars.len() {
+        match chars[i] {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            '{' => i = skip_to(chars, i, '}'),
+            ';' => i = skip_to(chars, i, '\n'),
+            _ => (),
+        }
+        i += 1;
+    }
+
+    chars.len()
+}
+
+#[cfg(test)]
+mod pgn_tests {
+    use super::*;
+
+    const GAMES: &str = r#"[Event "Casual \"blitz\" game"]
+[Site "?"]
+[Result "0-1"]
+
+1. e3 {the usual} b5 2. Bxb5 $1 (2. Qh5 Bb7 (2... e6) 3. Qxh7) 2... Bb7
+3. Bxd7+ Nxd7 ; black has to take back
+4. Qg4 0-1
+
+[Event "second"]
+[Result "1/2-1/2"]
+
+1.g3 e6 2.Bg2 1/2-1/2
+
+[Event "set up"]
+[FEN "8/8/8/8/8/8/P7/K6k w - - 0 1"]
+
+1. a4 *
+"#;
+
+    #[test]
+    fn test_parse_games() {
+        let games = parse_pgn(GAMES);
+        assert_eq!(games.len(), 3);
+
+        let first = &games[0];
+        assert_eq!(first.tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(first.result, Some(0.0));
+        assert_eq!(first.unreadable, None);
+
+        let moves: Vec<String> = first.moves.iter().map(|m| m.stringify()).collect();
+        assert_eq!(
+            moves,
+            vec!["e2e3", "b7b5", "f1b5", "c8b7", "b5d7", "b8d7", "d1g4"]
+        );
+
+        assert_eq!(games[1].result, Some(0.5));
+        assert_eq!(games[1].moves.len(), 3);
+        assert!(games[1].from_start_pos());
+
+        assert!(!games[2].from_start_pos());
+        assert_eq!(games[2].result, None);
+        assert_eq!(games[2].moves[0].stringify(), "a2a4");
+    }
+
+    #[test]
+    fn test_invalid_moves() {
+        // after 1. e4 d5 white has to take, so Nf3 is where we stop reading
+        let games = parse_pgn("1. e4 d5 2. Nf3 dxe4 1-0");
+
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves.len(), 2);
+        assert_eq!(games[0].unreadable.as_deref(), Some("Nf3"));
+        assert_eq!(games[0].result, Some(1.0));
+
+        // a game cut off without a result still counts
+        let games = parse_pgn("[Event \"x\"]\n1. e4 e5");
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves.len(), 2);
+    }
+}
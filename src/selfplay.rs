@@ -0,0 +1,366 @@
+use crate::engine::search::SearchLimits;
+use crate::engine::Engine;
+
+use anyhow::{anyhow, bail, Result};
+use pleco::{core::CastleType, BitMove, Board, Player, SQ};
+use rand::Rng;
+
+use std::fs;
+use std::io::Write;
+
+/// Every self-play file starts with this, followed by a version byte
+pub const MAGIC: &[u8; 4] = b"ACSP";
+pub const VERSION: u8 = 1;
+
+/// size of a single encoded record
+pub const RECORD_SIZE: usize = 32;
+
+/// score we store for a forced win, since the search uses infinity
+pub const WIN_SCORE: i16 = 30000;
+
+const PIECE_CHARS: [char; 12] = ['P', 'N', 'B', 'R', 'Q', 'K', 'p', 'n', 'b', 'r', 'q', 'k'];
+
+/// One position from a self-play game.
+#[derive(Clone)]
+pub struct TrainingRecord {
+    pub board: Board,
+    /// search score from white's point of view, in centipawns
+    pub score: i16,
+    /// the move the search picked
+    pub best_move: BitMove,
+    /// how the game ended for white, 1.0 win, 0.5 draw, 0.0 loss
+    pub result: f64,
+}
+
+impl TrainingRecord {
+    /// Packs the record into 32 bytes:
+    ///
+    /// - 8 bytes occupancy bitboard
+    /// - 16 bytes of 4 bit piece codes, one for every occupied square in order
+    /// - 1 byte side to move (bit 0) and castling rights (bits 1 to 4)
+    /// - 1 byte en passant square, 64 if there is none
+    /// - 1 byte half move clock
+    /// - 2 bytes score, 2 bytes best move, 1 byte result (0, 1 or 2 halves)
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut out = [0u8; RECORD_SIZE];
+
+        let mut occupancy = 0u64;
+        let mut codes = Vec::new();
+
+        for sq in 0..64u8 {
+            if let Some(c) = self.board.piece_at_sq(SQ(sq)).character() {
+                occupancy |= 1 << sq;
+                let code = PIECE_CHARS.iter().position(|p| *p == c).unwrap() as u8 + 1;
+                codes.push(code);
+            }
+        }
+
+        out[0..8].copy_from_slice(&occupancy.to_le_bytes());
+
+        // there are never more than 32 pieces on the board
+        for (ind, code) in codes.iter().enumerate().take(32) {
+            out[8 + ind / 2] |= code << (4 * (ind % 2));
+        }
+
+        let mut flags = 0u8;
+        if self.board.turn() == Player::Black {
+            flags |= 1;
+        }
+        let castles = [
+            (Player::White, CastleType::KingSide),
+            (Player::White, CastleType::QueenSide),
+            (Player::Black, CastleType::KingSide),
+            (Player::Black, CastleType::QueenSide),
+        ];
+        for (ind, (player, castle)) in castles.iter().enumerate() {
+            if self.board.can_castle(*player, *castle) {
+                flags |= 1 << (ind + 1);
+            }
+        }
+        out[24] = flags;
+
+        let ep = self.board.ep_square();
+        out[25] = if ep == SQ::NONE { 64 } else { ep.0 };
+        out[26] = self.board.rule_50().clamp(0, 255) as u8;
+
+        out[27..29].copy_from_slice(&self.score.to_le_bytes());
+        out[29..31].copy_from_slice(&self.best_move.get_raw().to_le_bytes());
+        out[31] = (self.result * 2.0).round() as u8;
+
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<TrainingRecord> {
+        if bytes.len() != RECORD_SIZE {
+            bail!("record has {} bytes, expected {}", bytes.len(), RECORD_SIZE);
+        }
+
+        let occupancy = u64::from_le_bytes(bytes[0..8].try_into()?);
+
+        let mut squares = [None; 64];
+        let mut piece_ind = 0;
+        for (sq, square) in squares.iter_mut().enumerate() {
+            if occupancy & (1 << sq) != 0 {
+                let code = (bytes[8 + piece_ind / 2] >> (4 * (piece_ind % 2))) & 0xF;
+                *square = Some(
+                    *PIECE_CHARS
+                        .get((code as usize).wrapping_sub(1))
+                        .ok_or_else(|| anyhow!("bad piece code {}", code))?,
+                );
+                piece_ind += 1;
+            }
+        }
+
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match squares[rank * 8 + file] {
+                    Some(c) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(c);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+
+        let flags = bytes[24];
+        let turn = if flags & 1 != 0 { "b" } else { "w" };
+        let mut castling: String = ['K', 'Q', 'k', 'q']
+            .iter()
+            .enumerate()
+            .filter(|(ind, _)| flags & (1 << (ind + 1)) != 0)
+            .map(|(_, c)| *c)
+            .collect();
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let ep = if bytes[25] < 64 {
+            SQ(bytes[25]).to_string()
+        } else {
+            String::from("-")
+        };
+
+        let fen = format!("{} {} {} {} {} 1", placement, turn, castling, ep, bytes[26]);
+        let board = Board::from_fen(&fen).map_err(|e| anyhow!("bad record {}: {:?}", fen, e))?;
+
+        Ok(TrainingRecord {
+            board,
+            score: i16::from_le_bytes(bytes[27..29].try_into()?),
+            best_move: BitMove::new(u16::from_le_bytes(bytes[29..31].try_into()?)),
+            result: bytes[31] as f64 / 2.0,
+        })
+    }
+}
+
+/// Writes the file header, records can be appended right after it
+pub fn write_header(out: &mut impl Write) -> Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    Ok(())
+}
+
+/// Writes self-play records as text for an external trainer, one position per
+/// line as `<fen> | <score> | <result>`, with the score in centipawns and the
+/// result as 1.0, 0.5 or 0.0, both from white's point of view.
+pub fn write_training_text(records: &[TrainingRecord], out: &mut impl Write) -> Result<()> {
+    for record in records {
+        writeln!(
+            out,
+            "{} | {} | {:.1}",
+            record.board.fen(),
+            record.score,
+            record.result
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Whether the bytes look like the start of a self-play file
+pub fn has_header(bytes: &[u8]) -> bool {
+    bytes.len() >= 5 && &bytes[0..4] == MAGIC
+}
+
+pub fn read_records(path: &str) -> Result<Vec<TrainingRecord>> {
+    let bytes = fs::read(path)?;
+
+    if !has_header(&bytes) {
+        bail!("{} is not a self-play file", path);
+    }
+    if bytes[4] != VERSION {
+        bail!("unsupported self-play file version {}", bytes[4]);
+    }
+
+    bytes[5..]
+        .chunks(RECORD_SIZE)
+        .map(TrainingRecord::decode)
+        .collect()
+}
+
+/// Clamps a search score into what fits in a record
+pub fn score_to_record(score: f64) -> i16 {
+    if score.is_infinite() {
+        if score > 0.0 {
+            WIN_SCORE
+        } else {
+            -WIN_SCORE
+        }
+    } else {
+        score.clamp(-(WIN_SCORE as f64 - 1.0), WIN_SCORE as f64 - 1.0) as i16
+    }
+}
+
+#[derive(Clone)]
+pub struct SelfPlayOptions {
+    /// number of random moves played at the start of every game
+    pub random_plies: u32,
+    /// games longer than this are scored as draws
+    pub max_plies: u32,
+    /// nodes searched for every move
+    pub nodes: u64,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> Self {
+        SelfPlayOptions {
+            random_plies: 8,
+            max_plies: 400,
+            nodes: 20_000,
+        }
+    }
+}
+
+/// Plays one game of the engine against itself and returns every searched
+/// position, labelled with the result. The random opening moves are not
+/// recorded. Seed the engine first to get the same game back.
+pub fn play_game(engine: &mut Engine, options: &SelfPlayOptions) -> Result<Vec<TrainingRecord>> {
+    engine.params.deterministic = true;
+    engine.params.debug_print = -1;
+
+    let limits = SearchLimits {
+        nodes: Some(options.nodes),
+        ..Default::default()
+    };
+
+    let mut board = Board::start_pos();
+    let mut records = Vec::new();
+
+    // leave the opening to chance, so we don't play the same game every time
+    for _ in 0..options.random_plies {
+        let moves = engine.generate_valid_moves(&board);
+        if moves.is_empty() || board.checkmate() || board.stalemate() {
+            break;
+        }
+        let m = moves[engine.rng.gen_range(0..moves.len())];
+        board.apply_move(m);
+    }
+
+    let result = loop {
+        if board.checkmate() {
+            // the side to move has lost
+            break if board.turn() == Player::White {
+                0.0
+            } else {
+                1.0
+            };
+        }
+
+        if board.stalemate() || board.rule_50() >= 100 || board.ply() as u32 >= options.max_plies {
+            break 0.5;
+        }
+
+        let res = engine.search(&board, &limits)?;
+
+        let pov = if board.turn() == Player::White {
+            1.0
+        } else {
+            -1.0
+        };
+
+        records.push(TrainingRecord {
+            board: board.clone(),
+            score: score_to_record(pov * res.score),
+            best_move: res.best_move,
+            result: 0.0,
+        });
+
+        board.apply_move(res.best_move);
+    };
+
+    for record in records.iter_mut() {
+        record.result = result;
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod selfplay_tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "7k/8/8/8/pP6/8/8/7K b - b3 0 3",
+            "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 b kq - 2 2",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let best_move = board.generate_moves()[0];
+            let record = TrainingRecord {
+                board,
+                score: -1234,
+                best_move,
+                result: 0.5,
+            };
+
+            let decoded = TrainingRecord::decode(&record.encode()).unwrap();
+
+            // the full move counter isn't stored
+            let without_counter = |fen: String| fen.rsplit_once(' ').unwrap().0.to_string();
+            assert_eq!(
+                without_counter(decoded.board.fen()),
+                without_counter(record.board.fen())
+            );
+            assert_eq!(decoded.score, -1234);
+            assert_eq!(decoded.best_move, best_move);
+            assert_eq!(decoded.result, 0.5);
+        }
+    }
+
+    #[test]
+    fn test_play_game() {
+        let options = SelfPlayOptions {
+            random_plies: 4,
+            max_plies: 16,
+            nodes: 50,
+        };
+
+        let mut engine = Engine::new();
+        engine.seed(3);
+        let records = play_game(&mut engine, &options).unwrap();
+
+        let mut engine = Engine::new();
+        engine.seed(3);
+        let again = play_game(&mut engine, &options).unwrap();
+
+        assert!(!records.is_empty());
+        assert_eq!(records.len(), again.len());
+        assert_eq!(records[0].board.fen(), again[0].board.fen());
+
+        let result = records[0].result;
+        assert!(records.iter().all(|r| r.result == result));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::engine::eval_params::EvalParams;
+use crate::engine::evaluate_game::evaluate;
+use crate::selfplay;
+
+use anyhow::{anyhow, bail, Result};
+use pleco::Board;
+
+use std::fs;
+
+/// A position together with how the game it came from ended, from white's
+/// point of view: 1.0 is a white win, 0.5 a draw and 0.0 a black win.
+pub struct LabelledPosition {
+    pub board: Board,
+    pub result: f64,
+}
+
+/// Parses a `<fen>;<result>` line. The result can be written as `1-0`, `0-1`,
+/// `1/2-1/2` or as a number. Blank lines and `#` comments give `None`.
+pub fn parse_labelled_line(line: &str) -> Result<Option<LabelledPosition>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (fen, result) = match line.rsplit_once(';') {
+        Some(parts) => parts,
+        None => bail!("missing result in line: {}", line),
+    };
+
+    let result = match result.trim().trim_matches('"') {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        r => r
+            .parse::<f64>()
+            .ok()
+            .filter(|r| (0.0..=1.0).contains(r))
+            .ok_or_else(|| anyhow!("invalid result in line: {}", line))?,
+    };
+
+    let board =
+        Board::from_fen(fen.trim()).map_err(|e| anyhow!("bad fen in line {}: {:?}", line, e))?;
+
+    Ok(Some(LabelledPosition { board, result }))
+}
+
+/// Whether the static eval of the board means anything. In our variant that
+/// means nobody is forced to capture, and the game isn't already over.
+pub fn is_quiet(board: &Board) -> bool {
+    if board.checkmate() || board.stalemate() {
+        return false;
+    }
+
+    !board.generate_moves().iter().any(|m| board.is_capture(*m))
+}
+
+/// Loads a dataset, dropping every position that isn't quiet. The file can
+/// either be text with one `<fen>;<result>` per line, or the output of the
+/// selfplay binary.
+pub fn load_labelled_positions(path: &str) -> Result<Vec<LabelledPosition>> {
+    let bytes = fs::read(path)?;
+
+    if selfplay::has_header(&bytes) {
+        return Ok(selfplay::read_records(path)?
+            .into_iter()
+            .filter(|r| is_quiet(&r.board))
+            .map(|r| LabelledPosition {
+                board: r.board,
+                result: r.result,
+            })
+            .collect());
+    }
+
+    let content = String::from_utf8(bytes)?;
+
+    let mut positions = Vec::new();
+    for line in content.lines() {
+        if let Some(pos) = parse_labelled_line(line)? {
+            if is_quiet(&pos.board) {
+                positions.push(pos);
+            }
+        }
+    }
+
+    Ok(positions)
+}
+
+/// Turns an eval in centipawns into an expected game result for white
+pub fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+/// How far the eval is from the actual results, on average
+pub fn mean_squared_error(positions: &[LabelledPosition], params: &EvalParams, k: f64) -> f64 {
+    if positions.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = positions
+        .iter()
+        .map(|pos| {
+            let error = pos.result - sigmoid(evaluate(&pos.board, params), k);
+            error * error
+        })
+        .sum();
+
+    total / positions.len() as f64
+}
+
+/// Finds the scaling constant that makes the current eval fit the results
+/// best. This has to be fixed before tuning, otherwise the tuner would just
+/// scale every weight up or down.
+pub fn find_k(positions: &[LabelledPosition], params: &EvalParams) -> f64 {
+    let mut best_k = 1.0;
+    let mut best_error = mean_squared_error(positions, params, best_k);
+
+    let mut step = 0.5;
+    while step > 0.001 {
+        let mut improved = true;
+        while improved {
+            improved = false;
+
+            for candidate in [best_k - step, best_k + step] {
+                if candidate <= 0.0 {
+                    continue;
+                }
+
+                let error = mean_squared_error(positions, params, candidate);
+                if error < best_error {
+                    best_error = error;
+                    best_k = candidate;
+                    improved = true;
+                }
+            }
+        }
+
+        step /= 2.0;
+    }
+
+    best_k
+}
+
+/// Texel's local search: nudge every weight up or down by `step`, keep the
+/// change if it lowers the error, and repeat until nothing helps anymore or
+/// we run out of passes. `on_pass` gets the pass number and the error after it.
+pub fn tune(
+    positions: &[LabelledPosition],
+    start: &EvalParams,
+    k: f64,
+    step: f64,
+    max_passes: usize,
+    mut on_pass: impl FnMut(usize, f64),
+) -> EvalParams {
+    let mut params = start.clone();
+    let mut best_error = mean_squared_error(positions, &params, k);
+
+    let num_weights = params.num_weights();
+
+    for pass in 1..=max_passes {
+        let mut improved = false;
+
+        for ind in 0..num_weights {
+            let original = *params.weight_mut(ind).unwrap();
+
+            for candidate in [original + step, original - step] {
+                *params.weight_mut(ind).unwrap() = candidate;
+
+                let error = mean_squared_error(positions, &params, k);
+                if error < best_error {
+                    best_error = error;
+                    improved = true;
+                    break;
+                }
+
+                *params.weight_mut(ind).unwrap() = original;
+            }
+        }
+
+        on_pass(pass, best_error);
+
+        if !improved {
+            break;
+        }
+    }
+
+    params
+}
+
+#[cfg(test)]
+mod tuning_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_labelled_line() {
+        let pos = parse_labelled_line("7k/8/8/8/8/8/8/Q6K w - - 0 1; 1-0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(pos.result, 1.0);
+
+        let pos = parse_labelled_line("7k/8/8/8/8/8/8/Q6K w - - 0 1;0.5")
+            .unwrap()
+            .unwrap();
+        assert_eq!(pos.result, 0.5);
+
+        assert!(parse_labelled_line("# comment").unwrap().is_none());
+        assert!(parse_labelled_line("7k/8/8/8/8/8/8/Q6K w - - 0 1").is_err());
+        assert!(parse_labelled_line("7k/8/8/8/8/8/8/Q6K w - - 0 1; 2").is_err());
+    }
+
+    #[test]
+    fn test_quiet() {
+        assert!(is_quiet(&Board::start_pos()));
+
+        // the pawn has to take the queen
+        let board = Board::from_fen("7k/8/8/3q1n2/4P3/8/8/7K w - - 0 1").unwrap();
+        assert!(!is_quiet(&board));
+    }
+
+    #[test]
+    fn test_load_selfplay_file() {
+        let record = selfplay::TrainingRecord {
+            board: Board::start_pos(),
+            score: 20,
+            best_move: Board::start_pos().generate_moves()[0],
+            result: 1.0,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("tuning_selfplay_test_{}.data", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut bytes = Vec::new();
+        selfplay::write_header(&mut bytes).unwrap();
+        bytes.extend_from_slice(&record.encode());
+        fs::write(path, bytes).unwrap();
+
+        let positions = load_labelled_positions(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].result, 1.0);
+    }
+
+    #[test]
+    fn test_tune_lowers_error() {
+        // white is a queen up and wins every game, so the queen should gain value
+        let positions: Vec<LabelledPosition> = [
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/3QK3 w - - 0 1",
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/3QK3 b - - 0 1",
+            "4k3/ppp1pppp/8/8/8/8/PPPPPPPP/3QK3 w - - 0 1",
+        ]
+        .iter()
+        .map(|fen| LabelledPosition {
+            board: Board::from_fen(fen).unwrap(),
+            result: 1.0,
+        })
+        .collect();
+
+        let start = EvalParams::default();
+        let k = 1.0;
+        let start_error = mean_squared_error(&positions, &start, k);
+
+        let mut passes = 0;
+        let tuned = tune(&positions, &start, k, 50.0, 2, |_, _| passes += 1);
+
+        assert!(passes > 0);
+        assert!(mean_squared_error(&positions, &tuned, k) < start_error);
+        assert!(tuned.material_mg[4] > start.material_mg[4]);
+    }
+}
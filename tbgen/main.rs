@@ -0,0 +1,97 @@
+use antichess_engine::engine::tablebase::{all_materials, Tablebase};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{App, Arg};
+
+use std::path::Path;
+use std::time::Instant;
+
+/// Generates endgame tables for our rules by retrograde analysis, and writes
+/// a win/draw/loss and a distance to mate file for each one.
+fn main() -> Result<()> {
+    let matches = App::new("tbgen")
+        .about("Generates endgame tablebases for forced capture chess")
+        .arg(
+            Arg::with_name("tables")
+                .value_name("TABLE")
+                .help("tables to generate, e.g. KRvK or KQvKP. Smaller tables they need are generated too")
+                .takes_value(true)
+                .multiple_values(true),
+        )
+        .arg(
+            Arg::with_name("pieces")
+                .long("pieces")
+                .value_name("N")
+                .help("generate every table with at most this many pieces, kings included")
+                .takes_value(true)
+                .possible_values(["3", "4", "5"])
+                .conflicts_with("tables"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("DIR")
+                .help("where to write the tables, existing tables in it are kept and reused")
+                .takes_value(true)
+                .default_value("tablebases"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("check every position of the new tables against its moves before saving"),
+        )
+        .after_help(
+            "A table has 2 * 10 * 64^(pieces - 1) positions without pawns (32 instead of 10 with \
+             pawns) and takes 3 bytes per position on disk. 5 piece tables need gigabytes of \
+             memory and hours to generate.",
+        )
+        .get_matches();
+
+    let names: Vec<String> = if let Some(pieces) = matches.value_of("pieces") {
+        all_materials(pieces.parse()?)
+            .iter()
+            .map(|m| m.name())
+            .collect()
+    } else if let Some(tables) = matches.values_of("tables") {
+        tables.map(String::from).collect()
+    } else {
+        bail!("give the tables to generate, or --pieces");
+    };
+
+    let output = matches.value_of("output").unwrap();
+
+    // a table we can't read would be thrown away and overwritten, so only a
+    // missing directory means starting from nothing
+    let mut tablebase = if Path::new(output).exists() {
+        Tablebase::load_dir(output)
+            .map_err(|e| anyhow!("could not load the tables in {}: {}", output, e))?
+    } else {
+        Tablebase::new()
+    };
+    let existing = tablebase.names();
+
+    for name in &names {
+        let start = Instant::now();
+        let before = tablebase.names();
+
+        tablebase.generate(name)?;
+
+        for new in tablebase.names().iter().filter(|n| !before.contains(n)) {
+            println!("generated {}", new);
+
+            if matches.is_present("verify") {
+                let positions = tablebase.verify(new)?;
+                println!("verified {} positions of {}", positions, new);
+            }
+        }
+
+        println!("{} done in {:.1}s", name, start.elapsed().as_secs_f64());
+    }
+
+    tablebase.save_dir(output)?;
+
+    let new = tablebase.names().len() - existing.len();
+    println!("wrote {} new tables to {}", new, output);
+
+    Ok(())
+}
@@ -2,10 +2,21 @@ use antichess_engine::engine::Engine;
 
 use pleco::{board::FenBuildError, Board};
 
+/// enough nodes to find the right move, without depending on how fast the
+/// machine running the tests is
+const NODE_LIMIT: u64 = 50_000;
+
+fn deterministic_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.params.deterministic = true;
+    engine.params.nodes = Some(NODE_LIMIT);
+    engine
+}
+
 #[test]
 fn test_endgame() -> Result<(), FenBuildError> {
     let mut game = Board::from_fen("2bqkbnr/3p2pp/2n2p2/4p3/8/1PP1P3/3PQPPP/2r1KBNR w - - 0 1")?;
-    let mut engine = Engine::new();
+    let mut engine = deterministic_engine();
 
     let m = engine.generate_move(&game);
     assert!(m.is_ok());
@@ -20,7 +31,7 @@ fn test_endgame() -> Result<(), FenBuildError> {
 #[test]
 fn test_endgame_forcetake() -> Result<(), FenBuildError> {
     let mut game = Board::from_fen("2bqk2r/1p1p1ppp/2pNp3/7P/8/8/R2KP3/2B2BnR b - - 0 1")?;
-    let mut engine = Engine::new();
+    let mut engine = deterministic_engine();
 
     let m = engine.generate_move(&game);
     assert!(m.is_ok());
@@ -35,7 +46,7 @@ fn test_endgame_forcetake() -> Result<(), FenBuildError> {
 #[test]
 fn test_checkmate() -> Result<(), FenBuildError> {
     let mut game = Board::from_fen("6k1/4QNpp/2p5/7P/8/8/3KP3/2B3BR w - - 0 1")?;
-    let mut engine = Engine::new();
+    let mut engine = deterministic_engine();
     engine.params.debug_print = 2;
 
     let m = engine.generate_move(&game);
@@ -49,3 +60,16 @@ fn test_checkmate() -> Result<(), FenBuildError> {
 
     Ok(())
 }
+
+#[test]
+fn test_endgame_same_move_every_time() -> Result<(), FenBuildError> {
+    let game = Board::from_fen("2bqk2r/1p1p1ppp/2pNp3/7P/8/8/R2KP3/2B2BnR b - - 0 1")?;
+
+    let first = deterministic_engine().generate_move(&game);
+    let second = deterministic_engine().generate_move(&game);
+
+    assert!(first.is_ok());
+    assert_eq!(first.unwrap(), second.unwrap());
+
+    Ok(())
+}
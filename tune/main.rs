@@ -0,0 +1,127 @@
+use antichess_engine::engine::eval_params::EvalParams;
+use antichess_engine::tuning::{find_k, load_labelled_positions, mean_squared_error, tune};
+
+use anyhow::{bail, Result};
+use clap::{App, Arg};
+
+/// Tunes the evaluation weights against a dataset of positions labelled with
+/// the result of the game they came from, and writes out a new params file.
+fn main() -> Result<()> {
+    let matches = App::new("tune")
+        .about("Texel style tuning of the evaluation weights")
+        .arg(
+            Arg::with_name("dataset")
+                .value_name("DATASET")
+                .help("file with one `<fen>;<result>` per line")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("where to write the tuned params, TOML or JSON")
+                .takes_value(true)
+                .default_value("tuned_params.toml"),
+        )
+        .arg(
+            Arg::with_name("params")
+                .long("params")
+                .value_name("FILE")
+                .help("params to start from, defaults to the built in ones")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passes")
+                .long("passes")
+                .value_name("N")
+                .help("maximum number of passes over all the weights")
+                .takes_value(true)
+                .default_value("20"),
+        )
+        .arg(
+            Arg::with_name("step")
+                .long("step")
+                .value_name("STEP")
+                .help("how much to change a weight by at a time")
+                .takes_value(true)
+                .default_value("1.0"),
+        )
+        .arg(
+            Arg::with_name("validation")
+                .long("validation")
+                .value_name("FRACTION")
+                .help("fraction of the dataset kept out of tuning to validate against")
+                .takes_value(true)
+                .default_value("0.1"),
+        )
+        .get_matches();
+
+    let passes: usize = matches.value_of("passes").unwrap().parse()?;
+    let step: f64 = matches.value_of("step").unwrap().parse()?;
+    let validation: f64 = matches.value_of("validation").unwrap().parse()?;
+
+    if !(0.0..1.0).contains(&validation) {
+        bail!("validation fraction has to be between 0 and 1");
+    }
+
+    let start = match matches.value_of("params") {
+        Some(path) => EvalParams::load(path)?,
+        None => EvalParams::default(),
+    };
+
+    let mut positions = load_labelled_positions(matches.value_of("dataset").unwrap())?;
+
+    // the dataset usually comes in game order, so interleave the validation
+    // positions instead of taking the last few games
+    let every = if validation > 0.0 {
+        (1.0 / validation).round() as usize
+    } else {
+        usize::MAX
+    };
+    let mut validation_set = Vec::new();
+    let mut training_set = Vec::new();
+    for (ind, pos) in positions.drain(..).enumerate() {
+        if every != usize::MAX && ind % every == every - 1 {
+            validation_set.push(pos);
+        } else {
+            training_set.push(pos);
+        }
+    }
+
+    if training_set.is_empty() {
+        bail!("no quiet positions to tune on");
+    }
+
+    println!(
+        "training on {} positions, validating on {}",
+        training_set.len(),
+        validation_set.len()
+    );
+
+    let k = find_k(&training_set, &start);
+    println!("k: {:.4}", k);
+
+    let start_validation_error = mean_squared_error(&validation_set, &start, k);
+    println!(
+        "start error: {:.6}, validation: {:.6}",
+        mean_squared_error(&training_set, &start, k),
+        start_validation_error
+    );
+
+    let tuned = tune(&training_set, &start, k, step, passes, |pass, error| {
+        println!("pass {}: error {:.6}", pass, error)
+    });
+
+    println!(
+        "validation error: {:.6} (was {:.6})",
+        mean_squared_error(&validation_set, &tuned, k),
+        start_validation_error
+    );
+
+    let output = matches.value_of("output").unwrap();
+    tuned.save(output)?;
+    println!("wrote {}", output);
+
+    Ok(())
+}